@@ -0,0 +1,96 @@
+use chrono::Utc;
+use sea_orm::prelude::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, Set,
+};
+
+use crate::entity::blob::{ActiveModel, Column, Entity as Blob, Model};
+
+pub struct BlobService;
+
+impl BlobService {
+    pub async fn get_blob<C: ConnectionTrait>(db: &C, hash: &str) -> Result<Option<Model>, DbErr> {
+        Blob::find_by_id(hash.to_string()).one(db).await
+    }
+
+    pub async fn list_blobs<C: ConnectionTrait>(db: &C) -> Result<Vec<Model>, DbErr> {
+        Blob::find().all(db).await
+    }
+
+    /// Add count references to the blob, the row is created on first reference.
+    pub async fn add_ref<C: ConnectionTrait>(
+        db: &C,
+        hash: &str,
+        size: i64,
+        count: i64,
+    ) -> Result<(), DbErr> {
+        let now = Utc::now().timestamp();
+        let result = Blob::update_many()
+            .col_expr(Column::RefCount, Expr::col(Column::RefCount).add(count))
+            .col_expr(Column::UpdateTime, Expr::value(now))
+            .filter(Column::Hash.eq(hash))
+            .exec(db)
+            .await?;
+        if result.rows_affected == 0 {
+            ActiveModel {
+                hash: Set(hash.to_string()),
+                size: Set(size),
+                ref_count: Set(count),
+                create_time: Set(now),
+                update_time: Set(now),
+            }
+            .insert(db)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Set the reference count of the blob, e.g. when fsck recounts it, the row is created if missing.
+    pub async fn set_ref_count<C: ConnectionTrait>(
+        db: &C,
+        hash: &str,
+        size: i64,
+        count: i64,
+    ) -> Result<(), DbErr> {
+        let result = Blob::update_many()
+            .col_expr(Column::RefCount, Expr::value(count))
+            .col_expr(Column::UpdateTime, Expr::value(Utc::now().timestamp()))
+            .filter(Column::Hash.eq(hash))
+            .exec(db)
+            .await?;
+        if result.rows_affected == 0 {
+            Self::add_ref(db, hash, size, count).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the row of a blob no longer referenced, return true if nothing references the blob.
+    /// The delete runs first so the caller holds the write lock while it acts on the result.
+    pub async fn remove_unreferenced<C: ConnectionTrait>(
+        db: &C,
+        hash: &str,
+    ) -> Result<bool, DbErr> {
+        Blob::delete_many()
+            .filter(Column::Hash.eq(hash))
+            .filter(Column::RefCount.lte(0))
+            .exec(db)
+            .await?;
+        Ok(Self::get_blob(db, hash).await?.is_none())
+    }
+
+    /// Drop one reference, return true if the blob is no longer referenced and its row is removed.
+    pub async fn release<C: ConnectionTrait>(db: &C, hash: &str) -> Result<bool, DbErr> {
+        Blob::update_many()
+            .col_expr(Column::RefCount, Expr::col(Column::RefCount).sub(1))
+            .col_expr(Column::UpdateTime, Expr::value(Utc::now().timestamp()))
+            .filter(Column::Hash.eq(hash))
+            .exec(db)
+            .await?;
+        let result = Blob::delete_many()
+            .filter(Column::Hash.eq(hash))
+            .filter(Column::RefCount.lte(0))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+}
@@ -0,0 +1,31 @@
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter};
+
+use crate::entity::chat_setting::{ActiveModel, Column, Entity as ChatSetting, Model};
+
+pub struct ChatSettingService;
+
+impl ChatSettingService {
+    pub async fn get_setting<C: ConnectionTrait>(db: &C, id: &str) -> Result<Option<Model>, DbErr> {
+        ChatSetting::find_by_id(id.to_string()).one(db).await
+    }
+
+    /// Insert the settings of the chat or replace them.
+    pub async fn save_setting<C: ConnectionTrait>(
+        db: &C,
+        setting: ActiveModel,
+        exists: bool,
+    ) -> Result<Model, DbErr> {
+        match exists {
+            true => setting.update(db).await,
+            false => setting.insert(db).await,
+        }
+    }
+
+    pub async fn delete_settings<C: ConnectionTrait>(db: &C, ids: &[String]) -> Result<u64, DbErr> {
+        let result = ChatSetting::delete_many()
+            .filter(Column::Id.is_in(ids.to_vec()))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
@@ -1,30 +1,34 @@
 use chrono::Utc;
 use sea_orm::prelude::Expr;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, ModelTrait,
-    PaginatorTrait, QueryFilter, QuerySelect, Value,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    ModelTrait, PaginatorTrait, QueryFilter, QuerySelect, Value,
 };
 
 use crate::dto::file::{ListByPageBody, ListByPidBody, ListGeneralBody, PageResult};
 use crate::entity::file::{
     ActiveModel as FileActiveModel, Column, Entity as File, Model as FileModel,
 };
+use crate::{FILE_STATE_NORMAL, FILE_STATE_TRASHED};
 
 pub struct FileService;
 
 impl FileService {
-    pub async fn create_file(
-        db: &DatabaseConnection,
+    pub async fn create_file<C: ConnectionTrait>(
+        db: &C,
         file: FileActiveModel,
     ) -> Result<FileModel, DbErr> {
         file.insert(db).await
     }
 
-    pub async fn get_file(db: &DatabaseConnection, id: &str) -> Result<Option<FileModel>, DbErr> {
+    pub async fn get_file<C: ConnectionTrait>(
+        db: &C,
+        id: &str,
+    ) -> Result<Option<FileModel>, DbErr> {
         File::find().filter(Column::Id.eq(id)).one(db).await
     }
 
-    pub async fn delete_file(db: &DatabaseConnection, id: &str) -> Result<(), DbErr> {
+    pub async fn delete_file<C: ConnectionTrait>(db: &C, id: &str) -> Result<(), DbErr> {
         if let Some(file) = File::find_by_id(id.to_string()).one(db).await? {
             file.delete(db).await?;
         }
@@ -34,8 +38,12 @@ impl FileService {
     pub async fn list_files_by_pid(
         db: &DatabaseConnection,
         body: &ListByPidBody,
+        wids: &[String],
     ) -> Result<Vec<FileModel>, DbErr> {
-        let mut select = File::find().filter(Column::Pid.eq(&body.pid));
+        let mut select = File::find()
+            .filter(Column::Pid.eq(&body.pid))
+            .filter(Column::Wid.is_in(wids.to_vec()))
+            .filter(Column::State.eq(FILE_STATE_NORMAL));
         if body.r#type.is_some() {
             let file_type = body.r#type.clone().unwrap();
             select = select.filter(Column::Type.eq(file_type));
@@ -49,7 +57,8 @@ impl FileService {
     ) -> Result<Vec<FileModel>, DbErr> {
         let mut select = File::find()
             .filter(Column::Wid.eq(&body.wid))
-            .filter(Column::Zone.eq(&body.zone));
+            .filter(Column::Zone.eq(&body.zone))
+            .filter(Column::State.eq(FILE_STATE_NORMAL));
         if body.r#type.is_some() {
             let file_type = body.r#type.clone().unwrap();
             select = select.filter(Column::Type.eq(file_type));
@@ -60,11 +69,14 @@ impl FileService {
     pub async fn list_files_by_page(
         db: &DatabaseConnection,
         body: &ListByPageBody,
+        wids: &[String],
     ) -> Result<PageResult, DbErr> {
         let paginator = File::find()
             .filter(Column::Pid.eq(&body.pid))
+            .filter(Column::Wid.is_in(wids.to_vec()))
             .filter(Column::Type.eq(&body.r#type))
             .filter(Column::Name.like(body.name.to_owned() + "%"))
+            .filter(Column::State.eq(FILE_STATE_NORMAL))
             .paginate(db, body.page_size);
         let total_result = paginator.num_items().await;
         if total_result.is_err() {
@@ -81,12 +93,37 @@ impl FileService {
         })
     }
 
-    pub async fn update_file_size(
-        db: &DatabaseConnection,
+    pub async fn update_file_size<C: ConnectionTrait>(
+        db: &C,
+        id: &str,
+        size: i64,
+    ) -> Result<u64, DbErr> {
+        match File::update_many()
+            .col_expr(Column::Size, Expr::value(Value::BigInt(Some(size))))
+            .col_expr(
+                Column::UpdateTime,
+                Expr::value(Value::BigInt(Some(Utc::now().timestamp()))),
+            )
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn update_file_hash<C: ConnectionTrait>(
+        db: &C,
         id: &str,
+        hash: &str,
         size: i64,
     ) -> Result<u64, DbErr> {
         match File::update_many()
+            .col_expr(
+                Column::Hash,
+                Expr::value(Value::String(Some(Box::from(hash.to_string())))),
+            )
             .col_expr(Column::Size, Expr::value(Value::BigInt(Some(size))))
             .col_expr(
                 Column::UpdateTime,
@@ -160,9 +197,156 @@ impl FileService {
             .select_only()
             .columns([Column::Zone])
             .filter(Column::Wid.eq(wid))
+            .filter(Column::State.eq(FILE_STATE_NORMAL))
             .distinct()
             .into_tuple()
             .all(db)
             .await
     }
+
+    pub async fn list_all_files<C: ConnectionTrait>(db: &C) -> Result<Vec<FileModel>, DbErr> {
+        File::find().all(db).await
+    }
+
+    pub async fn list_children<C: ConnectionTrait>(
+        db: &C,
+        pids: &[String],
+        state: i8,
+    ) -> Result<Vec<FileModel>, DbErr> {
+        File::find()
+            .filter(Column::Pid.is_in(pids.to_vec()))
+            .filter(Column::State.eq(state))
+            .all(db)
+            .await
+    }
+
+    pub async fn list_trash<C: ConnectionTrait>(
+        db: &C,
+        wid: &str,
+    ) -> Result<Vec<FileModel>, DbErr> {
+        File::find()
+            .filter(Column::Wid.eq(wid))
+            .filter(Column::State.eq(FILE_STATE_TRASHED))
+            .all(db)
+            .await
+    }
+
+    pub async fn list_expired_trash<C: ConnectionTrait>(
+        db: &C,
+        delete_before: i64,
+    ) -> Result<Vec<FileModel>, DbErr> {
+        File::find()
+            .filter(Column::State.eq(FILE_STATE_TRASHED))
+            .filter(Column::DeleteTime.lte(delete_before))
+            .all(db)
+            .await
+    }
+
+    pub async fn update_files_state<C: ConnectionTrait>(
+        db: &C,
+        ids: &[String],
+        state: i8,
+        delete_time: Option<i64>,
+        trash_id: Option<String>,
+    ) -> Result<u64, DbErr> {
+        match File::update_many()
+            .col_expr(Column::State, Expr::value(Value::TinyInt(Some(state))))
+            .col_expr(Column::DeleteTime, Expr::value(Value::BigInt(delete_time)))
+            .col_expr(
+                Column::TrashId,
+                Expr::value(Value::String(trash_id.map(Box::new))),
+            )
+            .col_expr(
+                Column::UpdateTime,
+                Expr::value(Value::BigInt(Some(Utc::now().timestamp()))),
+            )
+            .filter(Column::Id.is_in(ids.to_vec()))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn update_file_pid<C: ConnectionTrait>(
+        db: &C,
+        id: &str,
+        pid: &str,
+    ) -> Result<u64, DbErr> {
+        match File::update_many()
+            .col_expr(
+                Column::Pid,
+                Expr::value(Value::String(Some(Box::from(pid.to_string())))),
+            )
+            .filter(Column::Id.eq(id))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn delete_files<C: ConnectionTrait>(db: &C, ids: &[String]) -> Result<u64, DbErr> {
+        match File::delete_many()
+            .filter(Column::Id.is_in(ids.to_vec()))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn list_files_by_wid<C: ConnectionTrait>(
+        db: &C,
+        wid: &str,
+    ) -> Result<Vec<FileModel>, DbErr> {
+        File::find().filter(Column::Wid.eq(wid)).all(db).await
+    }
+
+    /// Move every file of the workspace, root files stay at the root of the other workspace.
+    pub async fn move_workspace_files<C: ConnectionTrait>(
+        db: &C,
+        from_wid: &str,
+        to_wid: &str,
+    ) -> Result<u64, DbErr> {
+        File::update_many()
+            .col_expr(
+                Column::Pid,
+                Expr::value(Value::String(Some(Box::from(String::new())))),
+            )
+            .filter(Column::Wid.eq(from_wid))
+            .filter(Column::Pid.eq(from_wid))
+            .exec(db)
+            .await?;
+        match File::update_many()
+            .col_expr(
+                Column::Wid,
+                Expr::value(Value::String(Some(Box::from(to_wid.to_string())))),
+            )
+            .col_expr(
+                Column::UpdateTime,
+                Expr::value(Value::BigInt(Some(Utc::now().timestamp()))),
+            )
+            .filter(Column::Wid.eq(from_wid))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn list_normal_files_by_wid<C: ConnectionTrait>(
+        db: &C,
+        wid: &str,
+    ) -> Result<Vec<FileModel>, DbErr> {
+        File::find()
+            .filter(Column::Wid.eq(wid))
+            .filter(Column::State.eq(FILE_STATE_NORMAL))
+            .all(db)
+            .await
+    }
 }
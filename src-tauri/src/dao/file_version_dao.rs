@@ -0,0 +1,64 @@
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder,
+};
+
+use crate::entity::file_version::{ActiveModel, Column, Entity as FileVersion, Model};
+
+pub struct FileVersionService;
+
+impl FileVersionService {
+    pub async fn create_version<C: ConnectionTrait>(
+        db: &C,
+        version: ActiveModel,
+    ) -> Result<Model, DbErr> {
+        version.insert(db).await
+    }
+
+    pub async fn get_version<C: ConnectionTrait>(db: &C, id: &str) -> Result<Option<Model>, DbErr> {
+        FileVersion::find_by_id(id.to_string()).one(db).await
+    }
+
+    /// Versions of the file, newest first.
+    pub async fn list_versions<C: ConnectionTrait>(db: &C, fid: &str) -> Result<Vec<Model>, DbErr> {
+        FileVersion::find()
+            .filter(Column::Fid.eq(fid))
+            .order_by_desc(Column::Version)
+            .all(db)
+            .await
+    }
+
+    pub async fn list_versions_by_fids<C: ConnectionTrait>(
+        db: &C,
+        fids: &[String],
+    ) -> Result<Vec<Model>, DbErr> {
+        FileVersion::find()
+            .filter(Column::Fid.is_in(fids.to_vec()))
+            .all(db)
+            .await
+    }
+
+    pub async fn list_all_versions<C: ConnectionTrait>(db: &C) -> Result<Vec<Model>, DbErr> {
+        FileVersion::find().all(db).await
+    }
+
+    pub async fn list_versions_before<C: ConnectionTrait>(
+        db: &C,
+        create_before: i64,
+    ) -> Result<Vec<Model>, DbErr> {
+        FileVersion::find()
+            .filter(Column::CreateTime.lt(create_before))
+            .all(db)
+            .await
+    }
+
+    pub async fn delete_versions<C: ConnectionTrait>(db: &C, ids: &[String]) -> Result<u64, DbErr> {
+        match FileVersion::delete_many()
+            .filter(Column::Id.is_in(ids.to_vec()))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+}
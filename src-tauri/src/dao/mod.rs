@@ -3,4 +3,10 @@ pub mod user_dao;
 pub mod workspace_dao;
 pub mod setting_dao;
 pub mod ai_source_dao;
-pub mod ai_model_dao;
\ No newline at end of file
+pub mod ai_model_dao;
+pub mod session_dao;
+pub mod blob_dao;pub mod file_version_dao;
+pub mod search_dao;
+pub mod workspace_member_dao;
+pub mod chat_setting_dao;
+pub mod prompt_template_dao;
@@ -0,0 +1,85 @@
+use sea_orm::prelude::Expr;
+use sea_orm::sea_query::Condition;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder,
+};
+
+use crate::entity::prompt_template::{ActiveModel, Column, Entity as PromptTemplate, Model};
+
+pub struct PromptTemplateService;
+
+impl PromptTemplateService {
+    pub async fn create_template<C: ConnectionTrait>(
+        db: &C,
+        template: ActiveModel,
+    ) -> Result<Model, DbErr> {
+        template.insert(db).await
+    }
+
+    pub async fn get_template<C: ConnectionTrait>(
+        db: &C,
+        id: &str,
+    ) -> Result<Option<Model>, DbErr> {
+        PromptTemplate::find_by_id(id.to_string()).one(db).await
+    }
+
+    /// Global templates of the user followed by the templates shared in the workspaces.
+    pub async fn list_templates<C: ConnectionTrait>(
+        db: &C,
+        uid: &str,
+        wids: &[String],
+    ) -> Result<Vec<Model>, DbErr> {
+        PromptTemplate::find()
+            .filter(
+                Condition::any()
+                    .add(
+                        Condition::all()
+                            .add(Column::Wid.is_null())
+                            .add(Column::Uid.eq(uid)),
+                    )
+                    .add(Column::Wid.is_in(wids.to_vec())),
+            )
+            .order_by_asc(Column::Wid)
+            .order_by_asc(Column::Name)
+            .all(db)
+            .await
+    }
+
+    pub async fn update_template<C: ConnectionTrait>(
+        db: &C,
+        template: ActiveModel,
+    ) -> Result<Model, DbErr> {
+        template.update(db).await
+    }
+
+    pub async fn delete_template<C: ConnectionTrait>(db: &C, id: &str) -> Result<u64, DbErr> {
+        let result = PromptTemplate::delete_by_id(id.to_string())
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    pub async fn delete_templates_by_wid<C: ConnectionTrait>(
+        db: &C,
+        wid: &str,
+    ) -> Result<u64, DbErr> {
+        let result = PromptTemplate::delete_many()
+            .filter(Column::Wid.eq(wid))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    pub async fn move_templates<C: ConnectionTrait>(
+        db: &C,
+        from_wid: &str,
+        to_wid: &str,
+    ) -> Result<u64, DbErr> {
+        let result = PromptTemplate::update_many()
+            .col_expr(Column::Wid, Expr::value(to_wid))
+            .filter(Column::Wid.eq(from_wid))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
@@ -0,0 +1,121 @@
+use sea_orm::{ConnectionTrait, DbErr, FromQueryResult, Statement, Value};
+
+use crate::dto::file::{SearchBody, SearchHit};
+use crate::FILE_STATE_NORMAL;
+
+pub struct SearchService;
+
+impl SearchService {
+    /// Replace the indexed name and content of the file.
+    pub async fn index_file<C: ConnectionTrait>(
+        db: &C,
+        fid: &str,
+        wid: &str,
+        zone: &str,
+        name: &str,
+        content: &str,
+    ) -> Result<(), DbErr> {
+        Self::remove_files(db, &[fid.to_string()]).await?;
+        db.execute(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            "INSERT INTO file_fts (fid, wid, zone, name, content) VALUES (?, ?, ?, ?, ?)",
+            [
+                fid.into(),
+                wid.into(),
+                zone.into(),
+                name.into(),
+                content.into(),
+            ],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    pub async fn rename_file<C: ConnectionTrait>(
+        db: &C,
+        fid: &str,
+        name: &str,
+    ) -> Result<(), DbErr> {
+        db.execute(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            "UPDATE file_fts SET name = ? WHERE fid = ?",
+            [name.into(), fid.into()],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_files<C: ConnectionTrait>(db: &C, fids: &[String]) -> Result<(), DbErr> {
+        if fids.is_empty() {
+            return Ok(());
+        }
+        let placeholders = vec!["?"; fids.len()].join(", ");
+        db.execute(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            format!("DELETE FROM file_fts WHERE fid IN ({})", placeholders),
+            fids.iter().map(|fid| fid.as_str().into()),
+        ))
+        .await?;
+        Ok(())
+    }
+
+    pub async fn count_files<C: ConnectionTrait>(db: &C) -> Result<i64, DbErr> {
+        let result = db
+            .query_one(Statement::from_string(
+                db.get_database_backend(),
+                "SELECT COUNT(*) AS count FROM file_fts",
+            ))
+            .await?;
+        match result {
+            Some(row) => row.try_get("", "count"),
+            None => Ok(0),
+        }
+    }
+
+    /// Hits of files in normal state, best first, match is an fts5 query.
+    pub async fn search<C: ConnectionTrait>(
+        db: &C,
+        body: &SearchBody,
+        query: &str,
+    ) -> Result<Vec<SearchHit>, DbErr> {
+        let mut sql = "SELECT file_fts.fid AS id, file_fts.wid, file_fts.zone, file_fts.name,
+            snippet(file_fts, -1, '<b>', '</b>', '...', 16) AS snippet, bm25(file_fts) AS score
+            FROM file_fts
+            JOIN file ON file.id = file_fts.fid
+            WHERE file_fts MATCH ? AND file.state = ? AND file_fts.wid = ?"
+            .to_string();
+        let mut values: Vec<Value> = vec![
+            query.into(),
+            FILE_STATE_NORMAL.into(),
+            body.wid.as_str().into(),
+        ];
+        if let Some(zone) = &body.zone {
+            sql.push_str(" AND file_fts.zone = ?");
+            values.push(zone.as_str().into());
+        }
+        sql.push_str(" ORDER BY score LIMIT ? OFFSET ?");
+        values.push((body.page_size as i64).into());
+        values.push(((body.page_num * body.page_size) as i64).into());
+        SearchHit::find_by_statement(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            sql,
+            values,
+        ))
+        .all(db)
+        .await
+    }
+
+    pub async fn move_workspace<C: ConnectionTrait>(
+        db: &C,
+        from_wid: &str,
+        to_wid: &str,
+    ) -> Result<(), DbErr> {
+        db.execute(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            "UPDATE file_fts SET wid = ? WHERE wid = ?",
+            [to_wid.into(), from_wid.into()],
+        ))
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use chrono::Utc;
+use sea_orm::prelude::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    Value,
+};
+
+use crate::entity::session::{ActiveModel, Column, Entity as Session, Model};
+
+pub struct SessionService;
+
+impl SessionService {
+    pub async fn create_session(
+        db: &DatabaseConnection,
+        session: ActiveModel,
+    ) -> Result<Model, DbErr> {
+        session.insert(db).await
+    }
+
+    pub async fn get_session(db: &DatabaseConnection, id: &str) -> Result<Option<Model>, DbErr> {
+        Session::find_by_id(id.to_string()).one(db).await
+    }
+
+    pub async fn list_active_sessions(
+        db: &DatabaseConnection,
+        user_id: &str,
+    ) -> Result<Vec<Model>, DbErr> {
+        Session::find()
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::RevokeTime.is_null())
+            .filter(Column::ExpireTime.gt(Utc::now().timestamp()))
+            .order_by_desc(Column::UpdateTime)
+            .all(db)
+            .await
+    }
+
+    pub async fn rotate_refresh_token(
+        db: &DatabaseConnection,
+        id: &str,
+        old_hash: &str,
+        refresh_token_hash: &str,
+        expire_time: i64,
+    ) -> Result<u64, DbErr> {
+        match Session::update_many()
+            .col_expr(
+                Column::RefreshTokenHash,
+                Expr::value(Value::String(Some(Box::from(
+                    refresh_token_hash.to_string(),
+                )))),
+            )
+            .col_expr(
+                Column::ExpireTime,
+                Expr::value(Value::BigInt(Some(expire_time))),
+            )
+            .col_expr(
+                Column::UpdateTime,
+                Expr::value(Value::BigInt(Some(Utc::now().timestamp()))),
+            )
+            .filter(Column::Id.eq(id))
+            // the token checked by the caller must still be current, a concurrent rotation wins
+            .filter(Column::RefreshTokenHash.eq(old_hash))
+            .filter(Column::RevokeTime.is_null())
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn revoke_session(
+        db: &DatabaseConnection,
+        user_id: &str,
+        id: &str,
+    ) -> Result<u64, DbErr> {
+        match Session::update_many()
+            .col_expr(
+                Column::RevokeTime,
+                Expr::value(Value::BigInt(Some(Utc::now().timestamp()))),
+            )
+            .filter(Column::Id.eq(id))
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::RevokeTime.is_null())
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn revoke_other_sessions(
+        db: &DatabaseConnection,
+        user_id: &str,
+        id: &str,
+    ) -> Result<u64, DbErr> {
+        match Session::update_many()
+            .col_expr(
+                Column::RevokeTime,
+                Expr::value(Value::BigInt(Some(Utc::now().timestamp()))),
+            )
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::Id.ne(id))
+            .filter(Column::RevokeTime.is_null())
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+}
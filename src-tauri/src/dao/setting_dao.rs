@@ -1,6 +1,6 @@
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, IntoActiveModel,
-    ModelTrait, QueryFilter,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    IntoActiveModel, ModelTrait, QueryFilter,
 };
 
 use crate::entity::prelude::Setting;
@@ -52,4 +52,18 @@ impl SettingService {
             .one(db)
             .await
     }
+
+    pub async fn delete_settings_by_prefix<C: ConnectionTrait>(
+        db: &C,
+        prefix: &str,
+    ) -> Result<u64, DbErr> {
+        match Setting::delete_many()
+            .filter(setting::Column::Key.starts_with(prefix))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
 }
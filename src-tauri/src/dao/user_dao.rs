@@ -1,7 +1,9 @@
+use chrono::Utc;
 use futures::{StreamExt, TryFutureExt};
+use sea_orm::prelude::Expr;
 use sea_orm::{
     ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
-    FromQueryResult, ModelTrait, PaginatorTrait, QueryFilter, Statement,
+    FromQueryResult, ModelTrait, PaginatorTrait, QueryFilter, Statement, Value,
 };
 
 use crate::entity::user;
@@ -68,4 +70,58 @@ impl UserService {
             .one(db)
             .await
     }
+
+    pub async fn get_user_model_by_id(
+        db: &DatabaseConnection,
+        id: &str,
+    ) -> Result<Option<Model>, DbErr> {
+        User::find_by_id(id.to_string()).one(db).await
+    }
+
+    pub async fn update_password(
+        db: &DatabaseConnection,
+        id: &str,
+        password: &str,
+        state: i8,
+    ) -> Result<u64, DbErr> {
+        match User::update_many()
+            .col_expr(
+                user::Column::Password,
+                Expr::value(Value::String(Some(Box::from(password.to_string())))),
+            )
+            .col_expr(
+                user::Column::State,
+                Expr::value(Value::TinyInt(Some(state))),
+            )
+            .col_expr(
+                user::Column::UpdateTime,
+                Expr::value(Value::BigInt(Some(Utc::now().timestamp()))),
+            )
+            .filter(user::Column::Id.eq(id))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn update_state(db: &DatabaseConnection, id: &str, state: i8) -> Result<u64, DbErr> {
+        match User::update_many()
+            .col_expr(
+                user::Column::State,
+                Expr::value(Value::TinyInt(Some(state))),
+            )
+            .col_expr(
+                user::Column::UpdateTime,
+                Expr::value(Value::BigInt(Some(Utc::now().timestamp()))),
+            )
+            .filter(user::Column::Id.eq(id))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
 }
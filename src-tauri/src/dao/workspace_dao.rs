@@ -1,7 +1,7 @@
 // src/dao.rs
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, ModelTrait,
-    QueryFilter,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    IntoActiveModel, ModelTrait, QueryFilter,
 };
 
 use crate::entity::workspace;
@@ -12,15 +12,15 @@ use crate::entity::workspace::{
 pub struct WorkspaceService;
 
 impl WorkspaceService {
-    pub async fn create_workspace(
-        db: &DatabaseConnection,
+    pub async fn create_workspace<C: ConnectionTrait>(
+        db: &C,
         workspace: WorkspaceActiveModel,
     ) -> Result<WorkspaceModel, sea_orm::DbErr> {
         workspace.insert(db).await
     }
 
-    pub async fn get_workspace(
-        db: &DatabaseConnection,
+    pub async fn get_workspace<C: ConnectionTrait>(
+        db: &C,
         id: &str,
     ) -> Result<Option<WorkspaceModel>, sea_orm::DbErr> {
         Workspace::find_by_id(id.to_string()).one(db).await
@@ -47,9 +47,10 @@ impl WorkspaceService {
             let mut active_model = existing_workspace.into_active_model();
 
             active_model.name = workspace.name.clone();
-            active_model.create_time = workspace.create_time.clone();
+            active_model.description = workspace.description.clone();
+            active_model.icon = workspace.icon.clone();
+            active_model.color = workspace.color.clone();
             active_model.update_time = workspace.update_time.clone();
-            active_model.state = workspace.state.clone();
 
             let updated_workspace = active_model.update(db).await?;
             Ok(Some(updated_workspace))
@@ -58,7 +59,10 @@ impl WorkspaceService {
         }
     }
 
-    pub async fn delete_workspace(db: &DatabaseConnection, id: &str) -> Result<(), sea_orm::DbErr> {
+    pub async fn delete_workspace<C: ConnectionTrait>(
+        db: &C,
+        id: &str,
+    ) -> Result<(), sea_orm::DbErr> {
         if let Some(workspace) = Workspace::find_by_id(id.to_string()).one(db).await? {
             workspace.delete(db).await?;
         }
@@ -84,4 +88,36 @@ impl WorkspaceService {
     ) -> Result<Vec<WorkspaceModel>, sea_orm::DbErr> {
         Workspace::find().all(db).await
     }
+
+    pub async fn list_workspaces_by_uid<C: ConnectionTrait>(
+        db: &C,
+        uid: &str,
+    ) -> Result<Vec<WorkspaceModel>, sea_orm::DbErr> {
+        Workspace::find()
+            .filter(workspace::Column::Uid.eq(uid))
+            .all(db)
+            .await
+    }
+
+    pub async fn list_workspaces_by_ids<C: ConnectionTrait>(
+        db: &C,
+        ids: &[String],
+    ) -> Result<Vec<WorkspaceModel>, sea_orm::DbErr> {
+        Workspace::find()
+            .filter(workspace::Column::Id.is_in(ids.to_vec()))
+            .all(db)
+            .await
+    }
+
+    pub async fn get_workspace_by_uid_and_name<C: ConnectionTrait>(
+        db: &C,
+        uid: &str,
+        name: &str,
+    ) -> Result<Option<WorkspaceModel>, sea_orm::DbErr> {
+        Workspace::find()
+            .filter(workspace::Column::Uid.eq(uid))
+            .filter(workspace::Column::Name.eq(name))
+            .one(db)
+            .await
+    }
 }
@@ -0,0 +1,104 @@
+use chrono::Utc;
+use sea_orm::prelude::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    Value,
+};
+
+use crate::entity::workspace_member::{ActiveModel, Column, Entity as WorkspaceMember, Model};
+
+pub struct WorkspaceMemberService;
+
+impl WorkspaceMemberService {
+    pub async fn create_member<C: ConnectionTrait>(
+        db: &C,
+        member: ActiveModel,
+    ) -> Result<Model, DbErr> {
+        member.insert(db).await
+    }
+
+    pub async fn get_member<C: ConnectionTrait>(
+        db: &C,
+        wid: &str,
+        uid: &str,
+    ) -> Result<Option<Model>, DbErr> {
+        WorkspaceMember::find()
+            .filter(Column::Wid.eq(wid))
+            .filter(Column::Uid.eq(uid))
+            .one(db)
+            .await
+    }
+
+    pub async fn list_members<C: ConnectionTrait>(db: &C, wid: &str) -> Result<Vec<Model>, DbErr> {
+        WorkspaceMember::find()
+            .filter(Column::Wid.eq(wid))
+            .order_by_asc(Column::CreateTime)
+            .all(db)
+            .await
+    }
+
+    pub async fn list_members_by_uid<C: ConnectionTrait>(
+        db: &C,
+        uid: &str,
+    ) -> Result<Vec<Model>, DbErr> {
+        WorkspaceMember::find()
+            .filter(Column::Uid.eq(uid))
+            .all(db)
+            .await
+    }
+
+    pub async fn update_role<C: ConnectionTrait>(
+        db: &C,
+        wid: &str,
+        uid: &str,
+        role: &str,
+    ) -> Result<u64, DbErr> {
+        match WorkspaceMember::update_many()
+            .col_expr(
+                Column::Role,
+                Expr::value(Value::String(Some(Box::from(role.to_string())))),
+            )
+            .col_expr(
+                Column::UpdateTime,
+                Expr::value(Value::BigInt(Some(Utc::now().timestamp()))),
+            )
+            .filter(Column::Wid.eq(wid))
+            .filter(Column::Uid.eq(uid))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn delete_member<C: ConnectionTrait>(
+        db: &C,
+        wid: &str,
+        uid: &str,
+    ) -> Result<u64, DbErr> {
+        match WorkspaceMember::delete_many()
+            .filter(Column::Wid.eq(wid))
+            .filter(Column::Uid.eq(uid))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn delete_members_by_wid<C: ConnectionTrait>(
+        db: &C,
+        wid: &str,
+    ) -> Result<u64, DbErr> {
+        match WorkspaceMember::delete_many()
+            .filter(Column::Wid.eq(wid))
+            .exec(db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected),
+            Err(err) => Err(err),
+        }
+    }
+}
@@ -38,4 +38,11 @@ pub struct ListBody {
 #[serde(rename_all = "camelCase")]
 pub struct CommonBody {
     pub id: String,
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PullBody {
+    pub source_id: String,
+    pub name:  String,
+}
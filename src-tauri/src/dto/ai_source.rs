@@ -6,6 +6,8 @@ pub struct CreateBody {
     pub name: String,
     pub url: String,
     pub key: String,
+    // openai, ollama or anthropic, defaults to openai
+    pub provider: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
@@ -15,6 +17,8 @@ pub struct UpdateBody {
     pub name: String,
     pub url: String,
     pub key: String,
+    // openai, ollama or anthropic, defaults to openai
+    pub provider: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
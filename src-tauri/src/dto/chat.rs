@@ -1,7 +1,47 @@
 use serde::Serialize;
 
+use crate::service::chat_provider_service::ChatError;
+use crate::{CHUNK_STATUS_CANCELLED, CHUNK_STATUS_END, CHUNK_STATUS_ERROR, CHUNK_STATUS_TEXT};
+
 #[derive(Clone, Serialize)]
 pub struct ChunkPayload {
     pub chunk: Option<String>,
     pub status: i8,
-}
\ No newline at end of file
+    // why the reply failed, only sent with the error status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ChatError>,
+}
+
+impl ChunkPayload {
+    pub fn text(text: String) -> ChunkPayload {
+        ChunkPayload {
+            chunk: Some(text),
+            status: CHUNK_STATUS_TEXT,
+            error: None,
+        }
+    }
+
+    pub fn end() -> ChunkPayload {
+        ChunkPayload {
+            chunk: None,
+            status: CHUNK_STATUS_END,
+            error: None,
+        }
+    }
+
+    pub fn error(error: ChatError) -> ChunkPayload {
+        ChunkPayload {
+            chunk: None,
+            status: CHUNK_STATUS_ERROR,
+            error: Some(error),
+        }
+    }
+
+    pub fn cancelled() -> ChunkPayload {
+        ChunkPayload {
+            chunk: None,
+            status: CHUNK_STATUS_CANCELLED,
+            error: None,
+        }
+    }
+}
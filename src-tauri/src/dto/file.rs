@@ -1,3 +1,4 @@
+use sea_orm::FromQueryResult;
 use serde::{Deserialize, Serialize};
 
 
@@ -81,9 +82,115 @@ pub struct GeneralBody {
     pub id: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashBody {
+    pub wid: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FsckBody {
+    #[serde(default)]
+    pub repair: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FsckIssueKind {
+    // row exists, blob on disk does not
+    MissingBlob,
+    // blob on disk, no row
+    OrphanBlob,
+    SizeMismatch,
+    // content of the blob does not match its hash
+    CorruptBlob,
+    // blob in the store, no file or version references it
+    UnreferencedBlob,
+    // reference count of the blob differs from the file and version rows referencing it
+    RefCountMismatch,
+    DanglingParent,
+    Cycle,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FsckIssue {
+    pub kind: FsckIssueKind,
+    pub wid: String,
+    pub id: String,
+    pub message: String,
+    pub repaired: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FsckReport {
+    pub checked_files: u64,
+    pub checked_blobs: u64,
+    pub issues: Vec<FsckIssue>,
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct PageResult {
     pub total: u64,
     pub items: Vec<crate::entity::file::Model>,
 }
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionListBody {
+    pub fid: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionBody {
+    pub id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionDiffBody {
+    pub from_id: String,
+    // compare with current content when absent
+    pub to_id: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionContent {
+    pub version: crate::entity::file_version::Model,
+    pub content: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchBody {
+    pub wid: String,
+    pub zone: Option<String>,
+    pub query: String,
+    #[serde(default = "default_search_page_size")]
+    pub page_size: u64,
+    // starts from 0
+    #[serde(default)]
+    pub page_num: u64,
+}
+
+fn default_search_page_size() -> u64 {
+    20
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, FromQueryResult)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub id: String,
+    pub wid: String,
+    pub zone: String,
+    pub name: String,
+    // matched text with hits wrapped in <b></b>
+    pub snippet: String,
+    // bm25 rank, lower is better
+    pub score: f64,
+}
@@ -12,6 +12,7 @@ pub struct Model {
     pub build_in: bool,
     pub url: String,
     pub key: String,
+    pub provider: String,
     pub enable: bool,
     pub sync: bool,
     pub create_time: i64,
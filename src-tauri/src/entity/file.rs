@@ -18,6 +18,11 @@ pub struct Model {
     pub create_time: i64,
     pub update_time: i64,
     pub state: i8,
+    pub delete_time: Option<i64>,
+    // sha256 of the content in blob store, none for dirs and files written before the store
+    pub hash: Option<String>,
+    // shared by the rows trashed by the same delete, none for files not in trash
+    pub trash_id: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
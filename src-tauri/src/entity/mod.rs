@@ -7,3 +7,9 @@ pub mod workspace;
 pub mod setting;
 pub mod ai_source;
 pub mod ai_model;
+pub mod session;
+pub mod blob;
+pub mod file_version;
+pub mod workspace_member;
+pub mod chat_setting;
+pub mod prompt_template;
@@ -5,4 +5,10 @@ pub use super::user::Entity as User;
 pub use super::workspace::Entity as Workspace;
 pub use super::setting::Entity as Setting;
 pub use super::ai_source::Entity as AiSource;
-pub use super::ai_model::Entity as AiModel;
\ No newline at end of file
+pub use super::ai_model::Entity as AiModel;
+pub use super::session::Entity as Session;
+pub use super::blob::Entity as Blob;
+pub use super::file_version::Entity as FileVersion;
+pub use super::workspace_member::Entity as WorkspaceMember;
+pub use super::chat_setting::Entity as ChatSetting;
+pub use super::prompt_template::Entity as PromptTemplate;
\ No newline at end of file
@@ -14,6 +14,10 @@ pub struct Model {
     pub create_time: i64,
     pub update_time: i64,
     pub state: i8,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+    pub color: Option<String>,
+    pub created_by: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
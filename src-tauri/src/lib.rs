@@ -9,6 +9,7 @@ use thiserror::Error;
 pub mod api;
 pub mod dao;
 pub mod entity;
+pub mod migration;
 pub mod service;
 pub mod util;
 pub mod dto;
@@ -20,20 +21,71 @@ pub const CONFIG_PATH: &str = "configs";
 pub const FILE_PATH: &str = "files";
 pub const DATA_PATH: &str = "data";
 pub const WORKSPACE_PATH: &str = "workspace";
+// e.g. .fatherbox/backups/fatherbox-20261018-120000.zip
+pub const BACKUP_PATH: &str = "backups";
+// a validated backup waiting to be swapped in on next start
+pub const RESTORE_PATH: &str = "restore";
 pub const DEFAULT_WORKSPACE: &str = "default";
 
 pub const FILE_TYPE: &str = "file";
 pub const DIR_TYPE: &str = "dir";
 
+pub const LOST_FOUND_NAME: &str = "lost+found";
+// content addressed blobs, e.g. .fatherbox/files/<uid>/.blobs/ab/abcd...
+pub const BLOB_PATH: &str = ".blobs";
+
+pub const FILE_STATE_NORMAL: i8 = 1;
+pub const FILE_STATE_TRASHED: i8 = 2;
+
+pub const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+pub const TRASH_PURGE_INTERVAL_SECONDS: u64 = 60 * 60;
+
+// larger files are searchable by name only
+pub const SEARCH_INDEX_MAX_SIZE: u64 = 1024 * 1024;
+
+pub const DEFAULT_MAX_VERSIONS: u64 = 20;
+pub const DEFAULT_VERSION_MAX_AGE_DAYS: i64 = 90;
+
+pub const DEFAULT_BACKUP_INTERVAL_HOURS: i64 = 24;
+pub const DEFAULT_MAX_BACKUPS: usize = 7;
+pub const BACKUP_CHECK_INTERVAL_SECONDS: u64 = 60 * 60;
+
+pub const DEFAULT_CHAT_MAX_RETRIES: u32 = 2;
+pub const DEFAULT_CHAT_RETRY_DELAY_MS: u64 = 500;
+pub const DEFAULT_CHAT_RETRY_MAX_DELAY_MS: u64 = 8000;
+
 pub const CHAT_ZONE: &str = "chat";
+// status of a streamed chat chunk
+pub const CHUNK_STATUS_TEXT: i8 = 0;
+pub const CHUNK_STATUS_END: i8 = 1;
+pub const CHUNK_STATUS_ERROR: i8 = -1;
+pub const CHUNK_STATUS_CANCELLED: i8 = 2;
 
 pub const RESPONSE_CODE_SUCCESS: i32 = 0;
 pub const RESPONSE_CODE_ERROR: i32 = -1;
 pub const RESPONSE_CODE_TIMEOUT: i32 = 401;
+pub const RESPONSE_CODE_FORBIDDEN: i32 = 403;
+
+pub const USER_STATE_NORMAL: i8 = 1;
+pub const USER_STATE_PASSWORD_EXPIRED: i8 = 2;
+// local user created on first start, it owns the install and manages backups
+pub const DEFAULT_USERNAME: &str = "default";
+pub const LOCAL_USER_TYPE: &str = "local";
+
+pub const TOKEN_SECRET_FILE_NAME: &str = "token.secret";
+pub const ACCESS_TOKEN_EXPIRE_SECONDS: i64 = 2 * 60 * 60;
+pub const REFRESH_TOKEN_EXPIRE_SECONDS: i64 = 30 * 24 * 60 * 60;
 
 
 
 pub const CHAT_API_SETTING_KEY: &str = "chat_api";
+pub const DEFAULT_WORKSPACE_SETTING_KEY: &str = "default_workspace";
+// version of the chat file layout, replies are stored with the assistant role since 2
+// and messages as a tree of branches since 3
+pub const CHAT_FORMAT_SETTING_KEY: &str = "chat_format";
+pub const CHAT_FORMAT_VERSION: &str = "3";
+// models asked when a chat without fallbacks of its own fails
+pub const CHAT_FALLBACKS_SETTING_KEY: &str = "chat_fallbacks";
 
 pub const OPENAI_NAME: &str = "OpenAI";
 pub const DEEP_SEEK: &str = "DeepSeek";
@@ -50,6 +102,10 @@ pub const API_SUFFIX: &str ="v1";
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub api: Option<ApiSettings>,
+    pub trash: Option<TrashSettings>,
+    pub version: Option<VersionSettings>,
+    pub backup: Option<BackupSettings>,
+    pub retry: Option<RetrySettings>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -58,6 +114,64 @@ pub struct ApiSettings {
     pub db_url: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrashSettings {
+    // trashed files are purged from disk after this many days
+    pub retention_days: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VersionSettings {
+    // versions kept per file, older ones are dropped on save
+    pub max_versions: u64,
+    pub max_age_days: i64,
+}
+
+impl Default for VersionSettings {
+    fn default() -> Self {
+        Self {
+            max_versions: DEFAULT_MAX_VERSIONS,
+            max_age_days: DEFAULT_VERSION_MAX_AGE_DAYS,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackupSettings {
+    // a backup is taken when the newest one is older, 0 disables scheduled backups
+    pub interval_hours: i64,
+    // older backups are removed
+    pub max_backups: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            interval_hours: DEFAULT_BACKUP_INTERVAL_HOURS,
+            max_backups: DEFAULT_MAX_BACKUPS,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetrySettings {
+    // retries of a chat request failing with a transient error before the next model is asked
+    pub max_retries: u32,
+    // the delay doubles on every retry up to the max
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_CHAT_MAX_RETRIES,
+            initial_delay_ms: DEFAULT_CHAT_RETRY_DELAY_MS,
+            max_delay_ms: DEFAULT_CHAT_RETRY_MAX_DELAY_MS,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppResponse<T> {
     pub code: i32,
@@ -70,11 +184,14 @@ pub struct AppResponse<T> {
 #[serde(rename_all = "camelCase")]
 pub struct LoginInfo {
     pub access_token: String,
+    pub refresh_token: String,
+    pub session_id: String,
     pub desc: String,
     pub real_name: String,
     pub user_id: String,
     pub username: String,
     pub mail: Option<String>,
+    pub password_expired: bool,
 }
 
 impl<T> AppResponse<T> {
@@ -96,6 +213,24 @@ impl<T> AppResponse<T> {
         }
     }
 
+    pub fn timeout(result: T, err_message: &str) -> Self {
+        Self {
+            code: RESPONSE_CODE_TIMEOUT,
+            r#type: String::new(),
+            message: err_message.to_string(),
+            result,
+        }
+    }
+
+    pub fn forbidden(result: T, err_message: &str) -> Self {
+        Self {
+            code: RESPONSE_CODE_FORBIDDEN,
+            r#type: String::new(),
+            message: err_message.to_string(),
+            result,
+        }
+    }
+
     pub fn is_success(&self) -> bool {
         self.code == RESPONSE_CODE_SUCCESS
     }
@@ -103,6 +238,10 @@ impl<T> AppResponse<T> {
     pub fn is_error(&self) -> bool {
         self.code == RESPONSE_CODE_ERROR
     }
+
+    pub fn is_forbidden(&self) -> bool {
+        self.code == RESPONSE_CODE_FORBIDDEN
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -133,6 +272,10 @@ pub struct AppState {
     pub conn: DatabaseConnection,
     pub root_path: PathBuf,
     pub user_path: PathBuf,
+    pub token_secret: Vec<u8>,
+    pub version_settings: VersionSettings,
+    pub backup_settings: BackupSettings,
+    pub retry_settings: RetrySettings,
 }
 
 #[derive(Error, Debug)]
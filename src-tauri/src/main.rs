@@ -7,18 +7,29 @@ use anyhow::anyhow;
 use app::api::{file, Api};
 use app::dao::file_dao::FileService;
 use app::dao::workspace_dao::WorkspaceService;
+use app::migration::migrate;
 use app::entity::workspace::Model;
 use app::service::user_service::{
-    create, get_access_codes, get_user_info, get_user_info_by_name, login, logout, refresh_token,
-    register, LoginBody, RegisterBody, UserInfo,
+    create, expire_password_if_match, get_access_codes, get_user_info, get_user_info_by_name,
+    login, logout, refresh_token, register, LoginBody, RegisterBody, UserInfo,
 };
+use app::dto::file::FsckBody;
+use app::service::ai_chat_service::migrate_chat_files;
+use app::service::backup_service::{apply_pending_restore, run_scheduled_backup};
+use app::service::file_service::purge_trash;
+use app::service::file_version_service::purge_versions;
+use app::service::fsck_service::fsck;
+use app::service::search_service::init_index;
 use app::service::workspace_service::create_workspace;
 use app::service::{file_service, user_service, workspace_service};
 use app::util::db_util::init_connection;
+use app::util::token_util::load_or_create_secret;
 use app::{
-    entity, AppResponse, AppState, Config, FileEntry, FileRequest, CONFIG_PATH, DATA_DB_NAME,
-    DATA_PATH, DEFAULT_WORKSPACE, DIR_TYPE, FILE_PATH, FILE_TYPE, RESPONSE_CODE_ERROR,
-    RESPONSE_CODE_SUCCESS, ROOT_PATH, WORKSPACE_PATH,
+    AppResponse, AppState, BackupSettings, Config, FileEntry, FileRequest,
+    BACKUP_CHECK_INTERVAL_SECONDS, CONFIG_PATH, DATA_DB_NAME, DATA_PATH,
+    DEFAULT_TRASH_RETENTION_DAYS, DEFAULT_USERNAME, DEFAULT_WORKSPACE, DIR_TYPE, FILE_PATH, FILE_TYPE,
+    LOCAL_USER_TYPE, RESPONSE_CODE_ERROR, RESPONSE_CODE_SUCCESS, ROOT_PATH, TRASH_PURGE_INTERVAL_SECONDS,
+    USER_STATE_PASSWORD_EXPIRED, VersionSettings, WORKSPACE_PATH,
 };
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
@@ -26,13 +37,13 @@ use clap::Parser;
 use config::FileFormat;
 use futures::future::err;
 use log::{error, info};
-use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbErr, ExecResult, Schema};
+use sea_orm::{Database, DatabaseConnection, DbErr, ExecResult};
 use serde_json::{to_value, Value};
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use std::{env, fs};
 use tauri::api::http::{ClientBuilder, HttpRequestBuilder, ResponseType};
 use tauri::api::path::home_dir;
@@ -56,6 +67,12 @@ struct Args {
     /// log level (v: info, vv: debug, vvv: trace)
     #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
     verbose: u8,
+    /// check files against blobs on disk and exit
+    #[arg(long)]
+    fsck: bool,
+    /// repair issues found by fsck
+    #[arg(long, requires = "fsck")]
+    repair: bool,
 }
 
 fn banner() {
@@ -125,6 +142,15 @@ async fn main() {
         info!("Create {} path: {}", FILE_PATH, file_path.display());
         fs::create_dir(file_path).unwrap();
     }
+    // init token secret
+    // e.g. .fatherbox/configs/token.secret
+    let token_secret = match load_or_create_secret(config_path) {
+        Ok(secret) => secret,
+        Err(err) => {
+            error!("Init token secret failed, err: {}", err);
+            exit(1)
+        }
+    };
     // process config
     let mut config_builder = config::Config::builder();
     config_builder = match &args.config {
@@ -135,6 +161,11 @@ async fn main() {
         }
     };
     let config: Config = config_builder.build().unwrap().try_deserialize().unwrap();
+    // swap in a backup staged by the restore command
+    if let Err(err) = apply_pending_restore(root_path) {
+        error!("Restore backup failed, err: {}", err);
+        exit(1);
+    }
     // init user db
     let db_result = init_data_db(data_path).await;
     if db_result.is_err() {
@@ -175,12 +206,44 @@ async fn main() {
         error!("Init file db failed, err: {}", db_result.err().unwrap());
         exit(1);
     }
+    // relabel replies of chats written before the assistant role existed
+    if let Err(err) = migrate_chat_files(&db, user_file_path).await {
+        error!("Migrate chat files failed, err: {}", err);
+    }
+    // index files written before search existed
+    if let Err(err) = init_index(&db, user_file_path).await {
+        error!("Init search index failed, err: {}", err);
+    }
+    if args.fsck {
+        exit(run_fsck(&db, user_file_path, args.repair).await);
+    }
+    // purge expired trash and file versions in background
+    let retention_days = match &config.trash {
+        Some(trash) => trash.retention_days,
+        None => DEFAULT_TRASH_RETENTION_DAYS,
+    };
+    let version_settings = config.version.clone().unwrap_or_default();
+    spawn_purge(
+        db.clone(),
+        user_file_path.to_owned(),
+        retention_days * 24 * 60 * 60,
+        version_settings.clone(),
+    );
+
+    // back up data and files in background
+    let backup_settings = config.backup.clone().unwrap_or_default();
+    spawn_backup(db.clone(), root_path.to_owned(), backup_settings.clone());
+    let retry_settings = config.retry.clone().unwrap_or_default();
 
     tauri::Builder::default()
         .manage(AppState {
             conn: db,
             root_path: root_path.to_owned(),
             user_path: user_file_path.to_owned(),
+            token_secret,
+            version_settings,
+            backup_settings,
+            retry_settings,
         })
         // why sync fn must after sync fc
         .invoke_handler(tauri::generate_handler![route_cmd, my_custom_command, stream_cmd])
@@ -188,12 +251,85 @@ async fn main() {
         .expect("error while running tauri application");
 }
 
+async fn run_fsck(db: &DatabaseConnection, user_path: &PathBuf, repair: bool) -> i32 {
+    let response = fsck(db, user_path, &FsckBody { repair }).await;
+    if !response.is_success() {
+        error!("fsck failed, err: {}", response.message);
+        return 2;
+    }
+    let report = response.result.unwrap();
+    println!(
+        "checked {} files, {} blobs, found {} issues",
+        report.checked_files,
+        report.checked_blobs,
+        report.issues.len()
+    );
+    for issue in &report.issues {
+        println!(
+            "{:?} {}/{}: {}{}",
+            issue.kind,
+            issue.wid,
+            issue.id,
+            issue.message,
+            if issue.repaired { " (repaired)" } else { "" }
+        );
+    }
+    if report.issues.iter().all(|issue| issue.repaired) {
+        0
+    } else {
+        1
+    }
+}
+
+fn spawn_purge(
+    db: DatabaseConnection,
+    user_path: PathBuf,
+    retention_seconds: i64,
+    version_settings: VersionSettings,
+) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(TRASH_PURGE_INTERVAL_SECONDS));
+        loop {
+            interval.tick().await;
+            let response = purge_trash(&db, &user_path, retention_seconds).await;
+            if !response.is_success() {
+                error!("purge trash failed, err: {}", response.message);
+            } else if response.result > 0 {
+                info!("purge trash success, count: {}", response.result);
+            }
+            let response = purge_versions(&db, &user_path, &version_settings).await;
+            if !response.is_success() {
+                error!("purge file versions failed, err: {}", response.message);
+            } else if response.result > 0 {
+                info!("purge file versions success, count: {}", response.result);
+            }
+        }
+    });
+}
+
+fn spawn_backup(db: DatabaseConnection, root_path: PathBuf, backup_settings: BackupSettings) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(BACKUP_CHECK_INTERVAL_SECONDS));
+        loop {
+            interval.tick().await;
+            let response = run_scheduled_backup(&db, &root_path, &backup_settings).await;
+            if !response.is_success() {
+                error!("scheduled backup failed, err: {}", response.message);
+            } else if let Some(backup) = response.result {
+                info!("scheduled backup success, name: {}", backup.name);
+            }
+        }
+    });
+}
+
 async fn init_default_user(db: &DatabaseConnection) -> Result<String, anyhow::Error> {
-    let default_username = "default";
+    let default_username = DEFAULT_USERNAME;
     let default_user_password = "123456";
     let default_nickname = "default user";
 
-    let get_response = get_user_info_by_name(db, default_username, "local").await;
+    let get_response = get_user_info_by_name(db, default_username, LOCAL_USER_TYPE).await;
     if !get_response.is_success() {
         error!("get default user error: {}", get_response.message);
         return Err(anyhow!("get default user error: {}", get_response.message));
@@ -208,6 +344,8 @@ async fn init_default_user(db: &DatabaseConnection) -> Result<String, anyhow::Er
                     password: default_user_password.to_string(),
                     nickname: default_nickname.to_string(),
                 },
+                // default password is well known, force user to change it
+                USER_STATE_PASSWORD_EXPIRED,
             )
             .await;
             if !create_response.is_success() {
@@ -219,7 +357,18 @@ async fn init_default_user(db: &DatabaseConnection) -> Result<String, anyhow::Er
             }
             Ok(create_response.result.unwrap().id)
         }
-        Some(user) => return Ok(user.id),
+        Some(user) => {
+            // default user created by old versions still uses the default password
+            let expire_response =
+                expire_password_if_match(db, &user.id, default_user_password).await;
+            if !expire_response.is_success() {
+                error!(
+                    "expire default user password error: {}",
+                    expire_response.message
+                );
+            }
+            return Ok(user.id);
+        }
     }
 }
 
@@ -227,7 +376,6 @@ async fn init_data_db(data_path: &PathBuf) -> Result<Option<DatabaseConnection>,
     // e.g. ~/.fatherbox/data.db
     let db_file_path = &data_path.join(DATA_DB_NAME);
     info!("begin init data db use file {:?}", db_file_path);
-    let db_exist = db_file_path.exists();
     let db = match init_connection(&db_file_path).await {
         Ok(conn) => conn,
         Err(err) => {
@@ -235,69 +383,12 @@ async fn init_data_db(data_path: &PathBuf) -> Result<Option<DatabaseConnection>,
             return Err(err);
         }
     };
-    if !db_exist {
-        info!("begin init tables in data db");
-        let builder = db.get_database_backend();
-        let schema = Schema::new(builder);
-        match db
-            .execute(builder.build(&schema.create_table_from_entity(entity::prelude::User)))
-            .await
-        {
-            Ok(_) => {}
-            Err(err) => {
-                info!("init data.db catch err: {:?}", err);
-                return Err(err);
-            }
-        }
-        match db
-            .execute(builder.build(&schema.create_table_from_entity(entity::prelude::Workspace)))
-            .await
-        {
-            Ok(_) => {}
-            Err(err) => {
-                info!("init data.db catch err: {:?}", err);
-                return Err(err);
-            }
-        }
-        match db
-            .execute(builder.build(&schema.create_table_from_entity(entity::prelude::File)))
-            .await
-        {
-            Ok(_) => {}
-            Err(err) => {
-                info!("init data.db catch err: {:?}", err);
-                return Err(err);
-            }
-        }
-        match db
-            .execute(builder.build(&schema.create_table_from_entity(entity::prelude::Setting)))
-            .await
-        {
-            Ok(_) => {}
-            Err(err) => {
-                info!("init data.db catch err: {:?}", err);
-                return Err(err);
-            }
-        }
-        match db
-            .execute(builder.build(&schema.create_table_from_entity(entity::prelude::AiSource)))
-            .await
-        {
-            Ok(_) => {}
-            Err(err) => {
-                info!("init data.db catch err: {:?}", err);
-                return Err(err);
-            }
-        }
-        match db
-            .execute(builder.build(&schema.create_table_from_entity(entity::prelude::AiModel)))
-            .await
-        {
-            Ok(_) => {}
-            Err(err) => {
-                info!("init data.db catch err: {:?}", err);
-                return Err(err);
-            }
+    // create or upgrade tables, refuse a db written by a newer binary
+    match migrate(&db).await {
+        Ok(_) => {}
+        Err(err) => {
+            error!("migrate data.db catch err: {:?}", err);
+            return Err(err);
         }
     }
     Ok(Some(db))
@@ -308,11 +399,7 @@ async fn init_default_workspace(
     uid: &str,
     workspace_path: &PathBuf,
 ) -> Result<Option<Model>, anyhow::Error> {
-    let get_response = workspace_service::get_workspace_by_name(db, DEFAULT_WORKSPACE).await;
-    if !get_response.is_success() {
-        return Err(anyhow!("{}", get_response.message));
-    }
-    let option_model = get_response.result;
+    let option_model = workspace_service::get_default_workspace(db, uid).await?;
     let mut id = "".to_string();
     if option_model.is_none() {
         // create default workspace
@@ -321,7 +408,7 @@ async fn init_default_workspace(
             error!("{}", create_response.message);
             return Err(anyhow!("{}", create_response.message));
         }
-        id = create_response.result.id.clone()
+        id = create_response.result.unwrap().id
     } else {
         id = option_model.unwrap().id
     }
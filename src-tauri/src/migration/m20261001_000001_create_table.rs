@@ -0,0 +1,245 @@
+use sea_orm_migration::prelude::*;
+
+// tables of the first release, created with if_not_exists so dbs created
+// before migrations existed are adopted as is
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(User::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(User::Id).string().not_null().primary_key())
+                    .col(ColumnDef::new(User::Username).string().not_null())
+                    .col(ColumnDef::new(User::Nickname).string().not_null())
+                    .col(ColumnDef::new(User::Avatar).binary())
+                    .col(ColumnDef::new(User::Password).string().not_null())
+                    .col(ColumnDef::new(User::Mail).string())
+                    .col(ColumnDef::new(User::Type).string().not_null())
+                    .col(ColumnDef::new(User::RefUserId).string())
+                    .col(ColumnDef::new(User::CreateTime).big_integer().not_null())
+                    .col(ColumnDef::new(User::UpdateTime).big_integer().not_null())
+                    .col(ColumnDef::new(User::State).tiny_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(Workspace::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Workspace::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Workspace::Uid).string().not_null())
+                    .col(ColumnDef::new(Workspace::Name).string().not_null())
+                    .col(
+                        ColumnDef::new(Workspace::CreateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Workspace::UpdateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Workspace::State).tiny_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(File::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(File::Id).string().not_null().primary_key())
+                    .col(ColumnDef::new(File::Name).string().not_null())
+                    .col(ColumnDef::new(File::Type).string().not_null())
+                    .col(ColumnDef::new(File::Wid).string().not_null())
+                    .col(ColumnDef::new(File::Pid).string().not_null())
+                    .col(ColumnDef::new(File::Zone).string().not_null())
+                    .col(ColumnDef::new(File::Size).big_integer().not_null())
+                    .col(ColumnDef::new(File::CreateTime).big_integer().not_null())
+                    .col(ColumnDef::new(File::UpdateTime).big_integer().not_null())
+                    .col(ColumnDef::new(File::State).tiny_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(Setting::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Setting::Key)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Setting::Value).binary().not_null())
+                    .col(ColumnDef::new(Setting::CreateTime).big_integer().not_null())
+                    .col(ColumnDef::new(Setting::UpdateTime).big_integer().not_null())
+                    .col(ColumnDef::new(Setting::State).tiny_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(AiSource::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AiSource::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(AiSource::Name).string().not_null())
+                    .col(ColumnDef::new(AiSource::BuildIn).boolean().not_null())
+                    .col(ColumnDef::new(AiSource::Url).string().not_null())
+                    .col(ColumnDef::new(AiSource::Key).string().not_null())
+                    .col(ColumnDef::new(AiSource::Enable).boolean().not_null())
+                    .col(ColumnDef::new(AiSource::Sync).boolean().not_null())
+                    .col(
+                        ColumnDef::new(AiSource::CreateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(AiSource::UpdateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(AiSource::State).tiny_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(AiModel::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AiModel::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(AiModel::Name).string().not_null())
+                    .col(ColumnDef::new(AiModel::SourceId).string().not_null())
+                    .col(ColumnDef::new(AiModel::Enable).boolean().not_null())
+                    .col(ColumnDef::new(AiModel::CreateTime).big_integer().not_null())
+                    .col(ColumnDef::new(AiModel::UpdateTime).big_integer().not_null())
+                    .col(ColumnDef::new(AiModel::State).tiny_integer().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AiModel::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(AiSource::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Setting::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(File::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Workspace::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(User::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+    Username,
+    Nickname,
+    Avatar,
+    Password,
+    Mail,
+    Type,
+    RefUserId,
+    CreateTime,
+    UpdateTime,
+    State,
+}
+
+#[derive(DeriveIden)]
+enum Workspace {
+    Table,
+    Id,
+    Uid,
+    Name,
+    CreateTime,
+    UpdateTime,
+    State,
+}
+
+#[derive(DeriveIden)]
+enum File {
+    Table,
+    Id,
+    Name,
+    Type,
+    Wid,
+    Pid,
+    Zone,
+    Size,
+    CreateTime,
+    UpdateTime,
+    State,
+}
+
+#[derive(DeriveIden)]
+enum Setting {
+    Table,
+    Key,
+    Value,
+    CreateTime,
+    UpdateTime,
+    State,
+}
+
+#[derive(DeriveIden)]
+enum AiSource {
+    Table,
+    Id,
+    Name,
+    BuildIn,
+    Url,
+    Key,
+    Enable,
+    Sync,
+    CreateTime,
+    UpdateTime,
+    State,
+}
+
+#[derive(DeriveIden)]
+enum AiModel {
+    Table,
+    Id,
+    Name,
+    SourceId,
+    Enable,
+    CreateTime,
+    UpdateTime,
+    State,
+}
@@ -0,0 +1,56 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Session::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Session::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Session::UserId).string().not_null())
+                    .col(
+                        ColumnDef::new(Session::RefreshTokenHash)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Session::Device).string().not_null())
+                    .col(ColumnDef::new(Session::CreateTime).big_integer().not_null())
+                    .col(ColumnDef::new(Session::UpdateTime).big_integer().not_null())
+                    .col(ColumnDef::new(Session::ExpireTime).big_integer().not_null())
+                    .col(ColumnDef::new(Session::RevokeTime).big_integer())
+                    .col(ColumnDef::new(Session::State).tiny_integer().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Session::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Session {
+    Table,
+    Id,
+    UserId,
+    RefreshTokenHash,
+    Device,
+    CreateTime,
+    UpdateTime,
+    ExpireTime,
+    RevokeTime,
+    State,
+}
@@ -0,0 +1,92 @@
+use sea_orm_migration::prelude::*;
+
+// lookups used by every file list and token check
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const IDX_FILE_WID_PID: &str = "idx_file_wid_pid";
+const IDX_WORKSPACE_UID: &str = "idx_workspace_uid";
+const IDX_SESSION_USER_ID: &str = "idx_session_user_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_FILE_WID_PID)
+                    .table(File::Table)
+                    .col(File::Wid)
+                    .col(File::Pid)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_WORKSPACE_UID)
+                    .table(Workspace::Table)
+                    .col(Workspace::Uid)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_SESSION_USER_ID)
+                    .table(Session::Table)
+                    .col(Session::UserId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_SESSION_USER_ID)
+                    .table(Session::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_WORKSPACE_UID)
+                    .table(Workspace::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_FILE_WID_PID)
+                    .table(File::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum File {
+    Table,
+    Wid,
+    Pid,
+}
+
+#[derive(DeriveIden)]
+enum Workspace {
+    Table,
+    Uid,
+}
+
+#[derive(DeriveIden)]
+enum Session {
+    Table,
+    UserId,
+}
@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+
+// content addressed blob store, file rows point at blobs by hash
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const IDX_FILE_HASH: &str = "idx_file_hash";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Blob::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Blob::Hash).string().not_null().primary_key())
+                    .col(ColumnDef::new(Blob::Size).big_integer().not_null())
+                    .col(ColumnDef::new(Blob::RefCount).big_integer().not_null())
+                    .col(ColumnDef::new(Blob::CreateTime).big_integer().not_null())
+                    .col(ColumnDef::new(Blob::UpdateTime).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .add_column(ColumnDef::new(File::Hash).string())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_FILE_HASH)
+                    .table(File::Table)
+                    .col(File::Hash)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_FILE_HASH)
+                    .table(File::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .drop_column(File::Hash)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Blob::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Blob {
+    Table,
+    Hash,
+    Size,
+    RefCount,
+    CreateTime,
+    UpdateTime,
+}
+
+#[derive(DeriveIden)]
+enum File {
+    Table,
+    Hash,
+}
@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+// previous contents of a file, each version holds a reference to its blob
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const IDX_FILE_VERSION_FID: &str = "idx_file_version_fid";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(FileVersion::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(FileVersion::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(FileVersion::Fid).string().not_null())
+                    .col(
+                        ColumnDef::new(FileVersion::Version)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(FileVersion::Hash).string().not_null())
+                    .col(ColumnDef::new(FileVersion::Size).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(FileVersion::CreateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_FILE_VERSION_FID)
+                    .table(FileVersion::Table)
+                    .col(FileVersion::Fid)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_FILE_VERSION_FID)
+                    .table(FileVersion::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(FileVersion::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileVersion {
+    Table,
+    Id,
+    Fid,
+    Version,
+    Hash,
+    Size,
+    CreateTime,
+}
@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+
+// full text index over file names and text contents, sqlite fts5
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS file_fts USING fts5(
+                fid UNINDEXED, wid UNINDEXED, zone UNINDEXED, name, content,
+                tokenize = 'unicode61 remove_diacritics 2')",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared("DROP TABLE IF EXISTS file_fts")
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use sea_orm_migration::prelude::*;
+
+// users sharing a workspace with its owner
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const IDX_WORKSPACE_MEMBER_WID_UID: &str = "idx_workspace_member_wid_uid";
+const IDX_WORKSPACE_MEMBER_UID: &str = "idx_workspace_member_uid";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkspaceMember::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(WorkspaceMember::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(WorkspaceMember::Wid).string().not_null())
+                    .col(ColumnDef::new(WorkspaceMember::Uid).string().not_null())
+                    .col(ColumnDef::new(WorkspaceMember::Role).string().not_null())
+                    .col(
+                        ColumnDef::new(WorkspaceMember::CreateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspaceMember::UpdateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_WORKSPACE_MEMBER_WID_UID)
+                    .table(WorkspaceMember::Table)
+                    .col(WorkspaceMember::Wid)
+                    .col(WorkspaceMember::Uid)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_WORKSPACE_MEMBER_UID)
+                    .table(WorkspaceMember::Table)
+                    .col(WorkspaceMember::Uid)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_WORKSPACE_MEMBER_UID)
+                    .table(WorkspaceMember::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_WORKSPACE_MEMBER_WID_UID)
+                    .table(WorkspaceMember::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(WorkspaceMember::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WorkspaceMember {
+    Table,
+    Id,
+    Wid,
+    Uid,
+    Role,
+    CreateTime,
+    UpdateTime,
+}
@@ -0,0 +1,65 @@
+use sea_orm_migration::prelude::*;
+
+// workspaces describe themselves and remember who created them
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // sqlite alters one column at a time
+        for column in [
+            ColumnDef::new(Workspace::Description).string().to_owned(),
+            ColumnDef::new(Workspace::Icon).string().to_owned(),
+            ColumnDef::new(Workspace::Color).string().to_owned(),
+            ColumnDef::new(Workspace::CreatedBy)
+                .string()
+                .not_null()
+                .default("")
+                .to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Workspace::Table)
+                        .add_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        // existing workspaces were created by their owner
+        manager
+            .get_connection()
+            .execute_unprepared("UPDATE workspace SET created_by = uid")
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            Workspace::CreatedBy,
+            Workspace::Color,
+            Workspace::Icon,
+            Workspace::Description,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Workspace::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Workspace {
+    Table,
+    Description,
+    Icon,
+    Color,
+    CreatedBy,
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+// sources name the protocol they speak, existing ones were all called through the openai client
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AiSource::Table)
+                    .add_column(
+                        ColumnDef::new(AiSource::Provider)
+                            .string()
+                            .not_null()
+                            .default("openai"),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AiSource::Table)
+                    .drop_column(AiSource::Provider)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AiSource {
+    Table,
+    Provider,
+}
@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+// system prompt and generation parameters of a chat, keyed by the id of its file row
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ChatSetting::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ChatSetting::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ChatSetting::SystemPrompt).string())
+                    .col(ColumnDef::new(ChatSetting::Temperature).double())
+                    .col(ColumnDef::new(ChatSetting::TopP).double())
+                    .col(ColumnDef::new(ChatSetting::MaxTokens).integer())
+                    .col(ColumnDef::new(ChatSetting::Stop).string())
+                    .col(ColumnDef::new(ChatSetting::Seed).big_integer())
+                    .col(
+                        ColumnDef::new(ChatSetting::CreateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ChatSetting::UpdateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ChatSetting::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ChatSetting {
+    Table,
+    Id,
+    SystemPrompt,
+    Temperature,
+    TopP,
+    MaxTokens,
+    Stop,
+    Seed,
+    CreateTime,
+    UpdateTime,
+}
@@ -0,0 +1,96 @@
+use sea_orm_migration::prelude::*;
+
+// reusable prompts with {{variable}} placeholders, global to the user or shared in a workspace
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const IDX_PROMPT_TEMPLATE_UID: &str = "idx_prompt_template_uid";
+const IDX_PROMPT_TEMPLATE_WID: &str = "idx_prompt_template_wid";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PromptTemplate::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PromptTemplate::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PromptTemplate::Uid).string().not_null())
+                    .col(ColumnDef::new(PromptTemplate::Wid).string())
+                    .col(ColumnDef::new(PromptTemplate::Name).string().not_null())
+                    .col(ColumnDef::new(PromptTemplate::Content).string().not_null())
+                    .col(
+                        ColumnDef::new(PromptTemplate::CreateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PromptTemplate::UpdateTime)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_PROMPT_TEMPLATE_UID)
+                    .table(PromptTemplate::Table)
+                    .col(PromptTemplate::Uid)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_PROMPT_TEMPLATE_WID)
+                    .table(PromptTemplate::Table)
+                    .col(PromptTemplate::Wid)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_PROMPT_TEMPLATE_WID)
+                    .table(PromptTemplate::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_PROMPT_TEMPLATE_UID)
+                    .table(PromptTemplate::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(PromptTemplate::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PromptTemplate {
+    Table,
+    Id,
+    Uid,
+    Wid,
+    Name,
+    Content,
+    CreateTime,
+    UpdateTime,
+}
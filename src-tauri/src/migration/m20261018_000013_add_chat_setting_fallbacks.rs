@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+// models asked in order when the model of a request fails, a json array of source and model ids
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ChatSetting::Table)
+                    .add_column(ColumnDef::new(ChatSetting::Fallbacks).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ChatSetting::Table)
+                    .drop_column(ChatSetting::Fallbacks)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ChatSetting {
+    Table,
+    Fallbacks,
+}
@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+// every row trashed by the same delete shares an id, deletes in the same second stay apart
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .add_column(ColumnDef::new(File::TrashId).string())
+                    .to_owned(),
+            )
+            .await?;
+        // files already in trash keep being grouped by their delete time
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE file SET trash_id = CAST(delete_time AS TEXT) WHERE delete_time IS NOT NULL",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(File::Table)
+                    .drop_column(File::TrashId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum File {
+    Table,
+    TrashId,
+}
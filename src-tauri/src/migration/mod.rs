@@ -0,0 +1,131 @@
+use log::info;
+use sea_orm::{DatabaseConnection, DbErr, EntityTrait};
+use sea_orm_migration::seaql_migrations;
+use sea_orm_migration::MigrationTrait;
+pub use sea_orm_migration::MigratorTrait;
+
+mod m20261001_000001_create_table;
+mod m20261018_000002_create_session_table;
+mod m20261018_000003_create_index;
+mod m20261018_000004_add_file_delete_time;
+mod m20261018_000005_create_blob_table;
+mod m20261018_000006_create_file_version_table;
+mod m20261018_000007_create_file_fts_table;
+mod m20261018_000008_create_workspace_member_table;
+mod m20261018_000009_add_workspace_metadata;
+mod m20261018_000010_add_ai_source_provider;
+mod m20261018_000011_create_chat_setting_table;
+mod m20261018_000012_create_prompt_template_table;
+mod m20261018_000013_add_chat_setting_fallbacks;
+mod m20261018_000014_add_file_trash_id;
+
+pub struct Migrator;
+
+impl MigratorTrait for Migrator {
+    // append new migrations to the end, never edit or reorder released ones
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20261001_000001_create_table::Migration),
+            Box::new(m20261018_000002_create_session_table::Migration),
+            Box::new(m20261018_000003_create_index::Migration),
+            Box::new(m20261018_000004_add_file_delete_time::Migration),
+            Box::new(m20261018_000005_create_blob_table::Migration),
+            Box::new(m20261018_000006_create_file_version_table::Migration),
+            Box::new(m20261018_000007_create_file_fts_table::Migration),
+            Box::new(m20261018_000008_create_workspace_member_table::Migration),
+            Box::new(m20261018_000009_add_workspace_metadata::Migration),
+            Box::new(m20261018_000010_add_ai_source_provider::Migration),
+            Box::new(m20261018_000011_create_chat_setting_table::Migration),
+            Box::new(m20261018_000012_create_prompt_template_table::Migration),
+            Box::new(m20261018_000013_add_chat_setting_fallbacks::Migration),
+            Box::new(m20261018_000014_add_file_trash_id::Migration),
+        ]
+    }
+}
+
+/// Apply all pending migrations, refuse a db written by a newer binary.
+pub async fn migrate(db: &DatabaseConnection) -> Result<(), DbErr> {
+    Migrator::install(db).await?;
+    check_known(db).await?;
+    info!("begin apply migrations to data db");
+    Migrator::up(db, None).await
+}
+
+/// Fail when the db has migrations this binary does not know.
+pub async fn check_known(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let known: Vec<String> = Migrator::migrations()
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect();
+    let applied = seaql_migrations::Entity::find().all(db).await?;
+    let unknown: Vec<String> = applied
+        .into_iter()
+        .map(|model| model.version)
+        .filter(|version| !known.contains(version))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(DbErr::Custom(format!(
+            "data db is newer than this binary, unknown migrations: {}",
+            unknown.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use chrono::Utc;
+    use sea_orm::ActiveValue::Set;
+    use sea_orm::{ActiveModelTrait, ConnectionTrait};
+    use sea_orm_migration::seaql_migrations;
+
+    use crate::migration::{migrate, Migrator, MigratorTrait};
+    use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let temp_dir = temp_dir();
+        let base_path = temp_dir.join(".fatherbox");
+        // 1. new db
+        let file_path = &base_path.join("test-migrate.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = init_connection(&file_path).await.unwrap();
+        migrate(&db).await.unwrap();
+        assert_eq!(
+            Migrator::migrations().len(),
+            Migrator::get_applied_migrations(&db).await.unwrap().len()
+        );
+        // run again is a no-op
+        migrate(&db).await.unwrap();
+        // 2. db newer than binary
+        seaql_migrations::ActiveModel {
+            version: Set("m29991231_000001_from_future".to_string()),
+            applied_at: Set(Utc::now().timestamp()),
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+        assert!(migrate(&db).await.is_err());
+        // 3. db created before migrations existed
+        let file_path = &base_path.join("test-migrate-legacy.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = init_connection(&file_path).await.unwrap();
+        // schema written by create_table_from_entity of the first release
+        db.execute_unprepared(
+            r#"CREATE TABLE "file" ( "id" varchar NOT NULL PRIMARY KEY, "name" varchar NOT NULL, "type" varchar NOT NULL, "wid" varchar NOT NULL, "pid" varchar NOT NULL, "zone" varchar NOT NULL, "size" bigint NOT NULL, "create_time" bigint NOT NULL, "update_time" bigint NOT NULL, "state" tinyint NOT NULL )"#,
+        )
+        .await
+        .unwrap();
+        migrate(&db).await.unwrap();
+        assert_eq!(
+            Migrator::migrations().len(),
+            Migrator::get_applied_migrations(&db).await.unwrap().len()
+        );
+    }
+}
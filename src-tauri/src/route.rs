@@ -1,7 +1,5 @@
 use std::path::PathBuf;
 
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
 use log::{debug, trace};
 use sea_orm::DatabaseConnection;
 use serde_json::{to_value, Value};
@@ -10,23 +8,44 @@ use tauri::{State, Window};
 use app::dto::file::{
     CopyBody as FileCopyBody, CreateBody as FileCreateBody, GeneralBody as FileGeneralBody,
     ListByPageBody as FileListByPageBody, ListByPidBody as FileListByPidBody,
-    ListGeneralBody as FileListGeneralBody, UpdateBody as FileUpdateBody,
+    ListGeneralBody as FileListGeneralBody,
+    SearchBody as FileSearchBody, TrashBody as FileTrashBody, UpdateBody as FileUpdateBody,
     UpdateContentBody as FileUpdateContentBody, UpdateNameBody as FileUpdateNameBody,
+    VersionBody as FileVersionBody, VersionDiffBody as FileVersionDiffBody,
+    VersionListBody as FileVersionListBody,
 };
 use app::service::ai_chat_service::{
-    create as chat_create, delete as chat_delete, list as chat_list,
+    create as chat_create, delete as chat_delete, get_fallbacks as chat_get_fallbacks,
+    get_settings as chat_get_settings, list as chat_list, message_cancel as chat_message_cancel,
     message_edit as chat_message_edit, message_list as chat_message_list,
     message_regenerate as chat_message_regenerate, message_request_stream as chat_message_request,
-    model_list as chat_model_list, update_name as chat_update_name, CommonBody as ChatCommonBody,
+    message_siblings as chat_message_siblings, message_switch as chat_message_switch,
+    model_list as chat_model_list, update_fallbacks as chat_update_fallbacks,
+    update_name as chat_update_name, update_settings as chat_update_settings,
+    BranchBody as ChatBranchBody, CancelBody as ChatCancelBody, CommonBody as ChatCommonBody,
     CreateBody as ChatCreateBody, EditBody as ModelMessageEditBody, ListBody as ChatListBody,
     RegenerateBody as ModelMessageRegenerateBody, RequestBody as ChatRequestBody,
-    UpdateNameBody as ChatUpdateNameBody,
+    UpdateFallbacksBody as ChatUpdateFallbacksBody, UpdateNameBody as ChatUpdateNameBody,
+    UpdateSettingsBody as ChatUpdateSettingsBody,
+};
+use app::service::backup_service::{
+    create_backup, list_backups, prune_backups, restore_backup, RestoreBody,
 };
 use app::service::file_service::{
-    copy_file, create_file, delete_file, get_file, get_path, get_workspace_files,
-    get_workspace_files_by_page, get_workspace_files_by_pid, update_file, update_file_content,
-    update_file_name,
+    copy_file, create_file, delete_file, empty_trash, get_file, get_path, get_workspace_files,
+    get_workspace_files_by_page, get_workspace_files_by_pid, list_trash, restore_file, update_file,
+    update_file_content, update_file_name,
+};
+use app::service::file_version_service::{
+    diff_versions, get_version, list_versions, restore_version,
+};
+use app::service::prompt_template_service::{
+    create_template, delete_template, get_template, list_templates, render_template,
+    update_template, CommonBody as PromptTemplateCommonBody,
+    CreateBody as PromptTemplateCreateBody, ListBody as PromptTemplateListBody,
+    RenderBody as PromptTemplateRenderBody, UpdateBody as PromptTemplateUpdateBody,
 };
+use app::service::search_service::search as file_search;
 
 use app::service::ai_source_service::{
     create as ai_source_create, delete as ai_source_delete, enable as ai_source_enable,
@@ -47,15 +66,33 @@ use app::dto::ai_source::{
     EnableBody as AiSourceEnableBody, UpdateBody as AiSourceUpdateBody,
 };
 use app::dto::chat::ChunkPayload;
+use app::service::session_service::{
+    check_session, list_sessions, revoke_other_sessions, revoke_session, RefreshTokenBody,
+    RevokeBody as SessionRevokeBody,
+};
 use app::service::user_service::{
-    get_access_codes, get_user_info, login, logout, refresh_token, register, LoginBody,
-    RegisterBody,
+    change_password, get_access_codes, get_user_info, login, logout, refresh_token, register,
+    ChangePasswordBody, LoginBody, RegisterBody,
+};
+use app::service::workspace_archive_service::{
+    export_workspace, import_workspace, ExportBody as WorkspaceExportBody,
+    ImportBody as WorkspaceImportBody, ProgressPayload,
 };
 use app::service::workspace_service::{
-    create_workspace, delete_workspace, get_workspace, list_workspaces,
-    CreateBody as WorkspaceCreateBody, GeneralBody as WorkspaceGeneralBody,
+    create_workspace, default_workspace, delete_workspace, get_workspace, invite_member,
+    list_members, list_my_workspaces, list_workspaces, remove_member, set_default_workspace,
+    update_member_role, update_workspace, CreateBody as WorkspaceCreateBody,
+    DeleteBody as WorkspaceDeleteBody, GeneralBody as WorkspaceGeneralBody, MemberBody,
+    MemberInviteBody, MemberListBody, MemberRoleBody, UpdateBody as WorkspaceUpdateBody,
 };
-use app::{AppResponse, AppState, LoginInfo};
+use app::util::token_util::{verify_token, TokenError};
+use app::{AppResponse, AppState, BackupSettings, LoginInfo, RetrySettings, VersionSettings};
+
+// commands which can be invoked without access token
+const ANONYMOUS_COMMANDS: [&str; 3] = ["user_login", "user_register", "user_refresh_token"];
+// commands which can be invoked before an expired password is changed
+const PASSWORD_EXPIRED_COMMANDS: [&str; 3] =
+    ["user_change_password", "user_get_info", "user_logout"];
 
 #[tauri::command]
 pub async fn route_cmd(
@@ -71,18 +108,51 @@ pub async fn route_cmd(
     // Pre-processing or logging logic
     let db = &state.conn;
     let user_path = &state.user_path;
+    let token_secret = &state.token_secret;
+    let version_settings = &state.version_settings;
+    let root_path = &state.root_path;
+    let backup_settings = &state.backup_settings;
+    let retry_settings = &state.retry_settings;
+    if ANONYMOUS_COMMANDS.contains(&command.as_str()) {
+        return Ok(invoke_anonymous_cmd(db, token_secret, command, args).await);
+    }
+    // verify access token before any command runs
+    let login_info = match get_user_info_from_access_token(db, token_secret, access_token).await {
+        Ok(login_info) => login_info,
+        Err(response) => return Ok(to_value(&response).unwrap()),
+    };
+    if login_info.password_expired && !PASSWORD_EXPIRED_COMMANDS.contains(&command.as_str()) {
+        let response = AppResponse::error(
+            None::<String>,
+            "Password is expired, please change password first",
+        );
+        return Ok(to_value(&response).unwrap());
+    }
     return if command.starts_with("user") {
-        Ok(invoke_user_cmd(db, command, access_token, args).await)
+        Ok(invoke_user_cmd(db, token_secret, command, &login_info, args).await)
     } else if command.starts_with("chat") {
-        Ok(invoke_chat_cmd(window, db, user_path, command, access_token, args).await)
+        Ok(invoke_chat_cmd(
+            window,
+            db,
+            user_path,
+            retry_settings,
+            command,
+            &login_info,
+            args,
+        )
+        .await)
     } else if command.starts_with("workspace") {
-        Ok(invoke_workspace_cmd(db, command, access_token, args).await)
+        Ok(invoke_workspace_cmd(window, db, user_path, command, &login_info, args).await)
     } else if command.starts_with("file") {
-        Ok(invoke_file_cmd(db, user_path, command, access_token, args).await)
+        Ok(invoke_file_cmd(db, user_path, version_settings, command, &login_info, args).await)
+    } else if command.starts_with("prompt_template") {
+        Ok(invoke_prompt_template_cmd(db, command, &login_info, args).await)
+    } else if command.starts_with("backup") {
+        Ok(invoke_backup_cmd(db, root_path, backup_settings, command, &login_info, args).await)
     } else if command.starts_with("ai_source") {
-        Ok(invoke_ai_source_cmd(db, user_path, command, access_token, args).await)
+        Ok(invoke_ai_source_cmd(db, user_path, command, &login_info, args).await)
     } else if command.starts_with("ai_model") {
-        Ok(invoke_ai_model_cmd(db, user_path, command, access_token, args).await)
+        Ok(invoke_ai_model_cmd(db, user_path, command, &login_info, args).await)
     } else {
         let response =
             AppResponse::error(None::<String>, &format!("Command {:?} not found", command));
@@ -90,71 +160,115 @@ pub async fn route_cmd(
     };
 }
 
-fn get_user_info_from_access_token(
+pub(crate) async fn get_user_info_from_access_token(
+    db: &DatabaseConnection,
+    token_secret: &[u8],
     access_token_option: Option<String>,
-) -> Result<LoginInfo, anyhow::Error> {
+) -> Result<LoginInfo, AppResponse<Option<String>>> {
     if access_token_option.is_none() {
-        return Err(anyhow::anyhow!("User token is null"));
+        return Err(AppResponse::error(None, "User token is null"));
     }
     let access_token = &access_token_option.unwrap();
     if access_token.is_empty() {
-        return Err(anyhow::anyhow!("User token is empty"));
+        return Err(AppResponse::error(None, "User token is empty"));
+    }
+    let claims = match verify_token(token_secret, access_token) {
+        Ok(claims) => claims,
+        Err(TokenError::Expired) => {
+            return Err(AppResponse::timeout(None, &TokenError::Expired.to_string()));
+        }
+        Err(err) => {
+            trace!("verify access token failed, err: {}", err);
+            return Err(AppResponse::error(None, &err.to_string()));
+        }
+    };
+    // reject tokens of revoked or expired sessions
+    let session_response = check_session(db, &claims.sub, &claims.sid).await;
+    if !session_response.is_success() {
+        return Err(AppResponse {
+            code: session_response.code,
+            r#type: session_response.r#type,
+            message: session_response.message,
+            result: None,
+        });
     }
-    // todo throw exception if access_token is expired
-    let result = BASE64_STANDARD.decode(access_token).unwrap();
-    let user_id = String::from_utf8(result).unwrap();
     Ok(LoginInfo {
         access_token: access_token.to_string(),
+        refresh_token: "".to_string(),
+        session_id: claims.sid,
         desc: "".to_string(),
         real_name: "".to_string(),
-        user_id,
+        user_id: claims.sub,
         username: "".to_string(),
         mail: None,
+        password_expired: claims.password_expired,
     })
 }
 
-pub async fn invoke_user_cmd(
+pub async fn invoke_anonymous_cmd(
     db: &DatabaseConnection,
+    token_secret: &[u8],
     command: String,
-    access_token: Option<String>,
     args: Value,
 ) -> Value {
-    if command == "user_login" {
-        let result: LoginBody = serde_json::from_value(args).unwrap();
-        let response = login(db, &result).await;
-        return to_value(&response).unwrap();
-    } else if command == "user_register" {
-        let result: RegisterBody = serde_json::from_value(args).unwrap();
-        let response = register(db, &result).await;
-        return to_value(&response).unwrap();
-    }
-    let login_info_result = get_user_info_from_access_token(access_token);
-    if login_info_result.is_err() {
-        return to_value(&AppResponse::error(
+    match command.as_str() {
+        "user_login" => {
+            let result: LoginBody = serde_json::from_value(args).unwrap();
+            let response = login(db, token_secret, &result).await;
+            to_value(&response).unwrap()
+        }
+        "user_register" => {
+            let result: RegisterBody = serde_json::from_value(args).unwrap();
+            let response = register(db, &result).await;
+            to_value(&response).unwrap()
+        }
+        "user_refresh_token" => {
+            let body: RefreshTokenBody = serde_json::from_value(args).unwrap();
+            let response = refresh_token(db, token_secret, &body).await;
+            to_value(&response).unwrap()
+        }
+        _ => to_value(&AppResponse::error(
             None::<String>,
-            &login_info_result.err().unwrap().to_string(),
+            "Anonymous command not found",
         ))
-        .unwrap();
+        .unwrap(),
     }
-    let login_info = login_info_result.unwrap();
+}
+
+pub async fn invoke_user_cmd(
+    db: &DatabaseConnection,
+    token_secret: &[u8],
+    command: String,
+    login_info: &LoginInfo,
+    args: Value,
+) -> Value {
     let user_id = &login_info.user_id;
-    let access_token_str = &login_info.access_token;
+    let session_id = &login_info.session_id;
     match command.as_str() {
         "user_get_info" => {
             let response = get_user_info(db, user_id).await;
             to_value(&response).unwrap()
         }
-        "user_login" => {
-            let result: LoginBody = serde_json::from_value(args).unwrap();
-            let response = login(db, &result).await;
+        "user_logout" => {
+            let response = logout(db, user_id, session_id).await;
             to_value(&response).unwrap()
         }
-        "user_logout" => {
-            let response = logout().await;
+        "user_change_password" => {
+            let body: ChangePasswordBody = serde_json::from_value(args).unwrap();
+            let response = change_password(db, token_secret, user_id, session_id, &body).await;
             to_value(&response).unwrap()
         }
-        "user_refresh_token" => {
-            let response = refresh_token(db, access_token_str).await;
+        "user_session_list" => {
+            let response = list_sessions(db, user_id, session_id).await;
+            to_value(&response).unwrap()
+        }
+        "user_session_revoke" => {
+            let body: SessionRevokeBody = serde_json::from_value(args).unwrap();
+            let response = revoke_session(db, user_id, &body.id).await;
+            to_value(&response).unwrap()
+        }
+        "user_session_revoke_others" => {
+            let response = revoke_other_sessions(db, user_id, session_id).await;
             to_value(&response).unwrap()
         }
         "user_get_access_codes" => {
@@ -173,16 +287,12 @@ pub async fn invoke_chat_cmd(
     window: Window,
     db: &DatabaseConnection,
     user_path: &PathBuf,
+    retry_settings: &RetrySettings,
     command: String,
-    access_token: Option<String>,
+    login_info: &LoginInfo,
     args: Value,
 ) -> Value {
-    if access_token.is_none() {
-        return to_value(&AppResponse::error(None::<String>, "User token is null")).unwrap();
-    }
-    let access_token = access_token.unwrap();
-    let result = BASE64_STANDARD.decode(&access_token).unwrap();
-    let user_id = String::from_utf8(result).unwrap();
+    let user_id = &login_info.user_id;
     match command.as_str() {
         "chat_get_models" => {
             let response = chat_model_list().await;
@@ -190,22 +300,41 @@ pub async fn invoke_chat_cmd(
         }
         "chat_list" => {
             let body: ChatListBody = serde_json::from_value(args).unwrap();
-            let response = chat_list(db, &user_id, &body.wid).await;
+            let response = chat_list(db, user_id, &body.wid).await;
             to_value(&response).unwrap()
         }
         "chat_create" => {
             let body: ChatCreateBody = serde_json::from_value(args).unwrap();
-            let response = chat_create(db, &user_id, &body).await;
+            let response = chat_create(db, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "chat_delete" => {
             let body: ChatCommonBody = serde_json::from_value(args).unwrap();
-            let response = chat_delete(db, &user_id, &body.id).await;
+            let response = chat_delete(db, user_id, &body.id).await;
             to_value(&response).unwrap()
         }
         "chat_update_name" => {
             let body: ChatUpdateNameBody = serde_json::from_value(args).unwrap();
-            let response = chat_update_name(db, &user_id, &body).await;
+            let response = chat_update_name(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "chat_get_settings" => {
+            let body: ChatCommonBody = serde_json::from_value(args).unwrap();
+            let response = chat_get_settings(db, user_id, &body.id).await;
+            to_value(&response).unwrap()
+        }
+        "chat_update_settings" => {
+            let body: ChatUpdateSettingsBody = serde_json::from_value(args).unwrap();
+            let response = chat_update_settings(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "chat_get_fallbacks" => {
+            let response = chat_get_fallbacks(db).await;
+            to_value(&response).unwrap()
+        }
+        "chat_update_fallbacks" => {
+            let body: ChatUpdateFallbacksBody = serde_json::from_value(args).unwrap();
+            let response = chat_update_fallbacks(db, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "chat_model_list" => {
@@ -214,59 +343,71 @@ pub async fn invoke_chat_cmd(
         }
         "chat_message_list" => {
             let body: ChatCommonBody = serde_json::from_value(args).unwrap();
-            let response = chat_message_list(db, user_path, &user_id, &body.id).await;
+            let response = chat_message_list(db, user_path, user_id, &body.id).await;
+            to_value(&response).unwrap()
+        }
+        "chat_message_siblings" => {
+            let body: ChatBranchBody = serde_json::from_value(args).unwrap();
+            let response = chat_message_siblings(db, user_path, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "chat_message_switch" => {
+            let body: ChatBranchBody = serde_json::from_value(args).unwrap();
+            let response = chat_message_switch(db, user_path, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "chat_message_request" => {
             let body: ChatRequestBody = serde_json::from_value(args).unwrap();
-            let callback_wrapper = |content: Option<String>, status: i8| {
-                window
-                    .emit(
-                        &body.request_id,
-                        ChunkPayload {
-                            chunk: content,
-                            status,
-                        },
-                    )
-                    .unwrap();
+            let callback_wrapper = |payload: ChunkPayload| {
+                window.emit(&body.request_id, payload).unwrap();
             };
             debug!("request body: {:?}", body);
-            let response =
-                chat_message_request(callback_wrapper, db, user_path, &user_id, &body).await;
+            let response = chat_message_request(
+                callback_wrapper,
+                db,
+                user_path,
+                user_id,
+                retry_settings,
+                &body,
+            )
+            .await;
             to_value(&response).unwrap()
         }
         "chat_message_regenerate" => {
             let body: ModelMessageRegenerateBody = serde_json::from_value(args).unwrap();
-            let callback_wrapper = |content: Option<String>, status: i8| {
-                window
-                    .emit(
-                        &body.request_id,
-                        ChunkPayload {
-                            chunk: content,
-                            status,
-                        },
-                    )
-                    .unwrap();
+            let callback_wrapper = |payload: ChunkPayload| {
+                window.emit(&body.request_id, payload).unwrap();
             };
-            let response =
-                chat_message_regenerate(callback_wrapper, db, user_path, &user_id, &body).await;
+            let response = chat_message_regenerate(
+                callback_wrapper,
+                db,
+                user_path,
+                user_id,
+                retry_settings,
+                &body,
+            )
+            .await;
+            to_value(&response).unwrap()
+        }
+        "chat_message_cancel" => {
+            let body: ChatCancelBody = serde_json::from_value(args).unwrap();
+            let response = chat_message_cancel(user_id, &body);
             to_value(&response).unwrap()
         }
         "chat_message_edit" => {
             let body: ModelMessageEditBody = serde_json::from_value(args).unwrap();
-            let callback_wrapper = |content: Option<String>, status: i8| {
-                window
-                    .emit(
-                        &body.request_id,
-                        ChunkPayload {
-                            chunk: content,
-                            status,
-                        },
-                    )
-                    .unwrap();
+            let callback_wrapper = |payload: ChunkPayload| {
+                window.emit(&body.request_id, payload).unwrap();
             };
-            let response =
-                chat_message_edit(callback_wrapper, db, user_path, &user_id, &body).await;
+            let response = chat_message_edit(
+                callback_wrapper,
+                db,
+                user_path,
+                user_id,
+                retry_settings,
+                &body,
+            )
+            .await;
             to_value(&response).unwrap()
         }
         _ => to_value(&AppResponse::error(
@@ -281,15 +422,9 @@ pub async fn invoke_ai_source_cmd(
     db: &DatabaseConnection,
     user_path: &PathBuf,
     command: String,
-    access_token: Option<String>,
+    login_info: &LoginInfo,
     args: Value,
 ) -> Value {
-    if access_token.is_none() {
-        return to_value(&AppResponse::error(None::<String>, "User token is null")).unwrap();
-    }
-    let access_token = access_token.unwrap();
-    let result = BASE64_STANDARD.decode(&access_token).unwrap();
-    let user_id = String::from_utf8(result).unwrap();
     match command.as_str() {
         "ai_source_list" => {
             let response = ai_source_list(db).await;
@@ -331,15 +466,9 @@ pub async fn invoke_ai_model_cmd(
     db: &DatabaseConnection,
     user_path: &PathBuf,
     command: String,
-    access_token: Option<String>,
+    login_info: &LoginInfo,
     args: Value,
 ) -> Value {
-    if access_token.is_none() {
-        return to_value(&AppResponse::error(None::<String>, "User token is null")).unwrap();
-    }
-    let access_token = access_token.unwrap();
-    let result = BASE64_STANDARD.decode(&access_token).unwrap();
-    let user_id = String::from_utf8(result).unwrap();
     match command.as_str() {
         "ai_model_list" => {
             let body: AiModelListBody = serde_json::from_value(args).unwrap();
@@ -379,24 +508,17 @@ pub async fn invoke_ai_model_cmd(
 }
 
 pub async fn invoke_workspace_cmd(
+    window: Window,
     db: &DatabaseConnection,
+    user_path: &PathBuf,
     command: String,
-    access_token: Option<String>,
+    login_info: &LoginInfo,
     args: Value,
 ) -> Value {
-    let login_info_result = get_user_info_from_access_token(access_token);
-    if login_info_result.is_err() {
-        return to_value(&AppResponse::error(
-            None::<String>,
-            &login_info_result.err().unwrap().to_string(),
-        ))
-        .unwrap();
-    }
-    let login_info = login_info_result.unwrap();
     let user_id = &login_info.user_id;
     match command.as_str() {
         "workspace_list" => {
-            let response = list_workspaces(db).await;
+            let response = list_workspaces(db, user_id).await;
             to_value(&response).unwrap()
         }
         "workspace_create" => {
@@ -405,13 +527,71 @@ pub async fn invoke_workspace_cmd(
             to_value(&response).unwrap()
         }
         "workspace_delete" => {
-            let body: WorkspaceGeneralBody = serde_json::from_value(args).unwrap();
-            let response = delete_workspace(db, &body.id).await;
+            let body: WorkspaceDeleteBody = serde_json::from_value(args).unwrap();
+            let response = delete_workspace(db, user_path, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "workspace_get" => {
             let body: WorkspaceGeneralBody = serde_json::from_value(args).unwrap();
-            let response = get_workspace(db, &body.id).await;
+            let response = get_workspace(db, user_id, &body.id).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_update" => {
+            let body: WorkspaceUpdateBody = serde_json::from_value(args).unwrap();
+            let response = update_workspace(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_list_mine" => {
+            let response = list_my_workspaces(db, user_id).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_get_default" => {
+            let response = default_workspace(db, user_id).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_set_default" => {
+            let body: WorkspaceGeneralBody = serde_json::from_value(args).unwrap();
+            let response = set_default_workspace(db, user_id, &body.id).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_export" => {
+            let body: WorkspaceExportBody = serde_json::from_value(args).unwrap();
+            let callback_wrapper = |done: u64, total: u64| {
+                window
+                    .emit(&body.request_id, ProgressPayload { done, total })
+                    .unwrap();
+            };
+            let response = export_workspace(callback_wrapper, db, user_path, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_import" => {
+            let body: WorkspaceImportBody = serde_json::from_value(args).unwrap();
+            let callback_wrapper = |done: u64, total: u64| {
+                window
+                    .emit(&body.request_id, ProgressPayload { done, total })
+                    .unwrap();
+            };
+            let response = import_workspace(callback_wrapper, db, user_path, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_member_list" => {
+            let body: MemberListBody = serde_json::from_value(args).unwrap();
+            let response = list_members(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_member_invite" => {
+            let body: MemberInviteBody = serde_json::from_value(args).unwrap();
+            let response = invite_member(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_member_remove" => {
+            let body: MemberBody = serde_json::from_value(args).unwrap();
+            let response = remove_member(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "workspace_member_update_role" => {
+            let body: MemberRoleBody = serde_json::from_value(args).unwrap();
+            let response = update_member_role(db, user_id, &body).await;
             to_value(&response).unwrap()
         }
         _ => to_value(&AppResponse::error(
@@ -422,74 +602,190 @@ pub async fn invoke_workspace_cmd(
     }
 }
 
+pub async fn invoke_prompt_template_cmd(
+    db: &DatabaseConnection,
+    command: String,
+    login_info: &LoginInfo,
+    args: Value,
+) -> Value {
+    let user_id = &login_info.user_id;
+    match command.as_str() {
+        "prompt_template_create" => {
+            let body: PromptTemplateCreateBody = serde_json::from_value(args).unwrap();
+            let response = create_template(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "prompt_template_list" => {
+            let body: PromptTemplateListBody = serde_json::from_value(args).unwrap();
+            let response = list_templates(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "prompt_template_get" => {
+            let body: PromptTemplateCommonBody = serde_json::from_value(args).unwrap();
+            let response = get_template(db, user_id, &body.id).await;
+            to_value(&response).unwrap()
+        }
+        "prompt_template_update" => {
+            let body: PromptTemplateUpdateBody = serde_json::from_value(args).unwrap();
+            let response = update_template(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "prompt_template_delete" => {
+            let body: PromptTemplateCommonBody = serde_json::from_value(args).unwrap();
+            let response = delete_template(db, user_id, &body.id).await;
+            to_value(&response).unwrap()
+        }
+        "prompt_template_render" => {
+            let body: PromptTemplateRenderBody = serde_json::from_value(args).unwrap();
+            let response = render_template(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        _ => to_value(&AppResponse::error(
+            None::<String>,
+            "Prompt template command not found",
+        ))
+        .unwrap(),
+    }
+}
+
+pub async fn invoke_backup_cmd(
+    db: &DatabaseConnection,
+    root_path: &PathBuf,
+    backup_settings: &BackupSettings,
+    command: String,
+    login_info: &LoginInfo,
+    args: Value,
+) -> Value {
+    let user_id = &login_info.user_id;
+    match command.as_str() {
+        "backup_create" => {
+            let response = create_backup(db, root_path, user_id).await;
+            if response.is_success() {
+                prune_backups(root_path, backup_settings.max_backups);
+            }
+            to_value(&response).unwrap()
+        }
+        "backup_list" => {
+            let response = list_backups(db, root_path, user_id).await;
+            to_value(&response).unwrap()
+        }
+        "backup_restore" => {
+            let body: RestoreBody = serde_json::from_value(args).unwrap();
+            let response = restore_backup(db, root_path, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        _ => to_value(&AppResponse::error(
+            None::<String>,
+            "Backup command not found",
+        ))
+        .unwrap(),
+    }
+}
+
 pub async fn invoke_file_cmd(
     db: &DatabaseConnection,
     user_path: &PathBuf,
+    version_settings: &VersionSettings,
     command: String,
-    access_token: Option<String>,
+    login_info: &LoginInfo,
     args: Value,
 ) -> Value {
-    // if access_token.is_none() {
-    //     return to_value(&AppResponse::error(None::<String>, "User token is null")).unwrap()
-    // }
-    // let access_token = access_token.unwrap();
-    // let result = BASE64_STANDARD.decode(&access_token).unwrap();
-    // let user_id = String::from_utf8(result).unwrap();
-    // todo need user_id to query
+    let user_id = &login_info.user_id;
     match command.as_str() {
         "file_get_all_workspace_files" => {
             let body: FileListGeneralBody = serde_json::from_value(args).unwrap();
-            let response = get_workspace_files(db, &body).await;
+            let response = get_workspace_files(db, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_get_workspace_files_by_id" => {
             let body: FileListByPidBody = serde_json::from_value(args).unwrap();
-            let response = get_workspace_files_by_pid(db, &body).await;
+            let response = get_workspace_files_by_pid(db, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_get_workspace_files_by_page" => {
             let body: FileListByPageBody = serde_json::from_value(args).unwrap();
-            let response = get_workspace_files_by_page(db, &body).await;
+            let response = get_workspace_files_by_page(db, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_get" => {
             let body: FileGeneralBody = serde_json::from_value(args).unwrap();
-            let response = get_file(db, &body).await;
+            let response = get_file(db, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_get_path" => {
             let body: FileGeneralBody = serde_json::from_value(args).unwrap();
-            let response = get_path(user_path, &body).await;
+            let response = get_path(db, user_path, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_copy" => {
             let body: FileCopyBody = serde_json::from_value(args).unwrap();
-            let response = copy_file(db, user_path, &body).await;
+            let response = copy_file(db, user_path, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_create" => {
             let body: FileCreateBody = serde_json::from_value(args).unwrap();
-            let response = create_file(db, user_path, &body).await;
+            let response = create_file(db, user_path, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_update" => {
             let body: FileUpdateBody = serde_json::from_value(args).unwrap();
-            let response = update_file(db, &body).await;
+            let response = update_file(db, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_update_content" => {
             let body: FileUpdateContentBody = serde_json::from_value(args).unwrap();
-            let response = update_file_content(db, user_path, &body).await;
+            let response =
+                update_file_content(db, user_path, version_settings, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_update_name" => {
             let body: FileUpdateNameBody = serde_json::from_value(args).unwrap();
-            let response = update_file_name(db, &body).await;
+            let response = update_file_name(db, user_id, &body).await;
             to_value(&response).unwrap()
         }
         "file_delete" => {
             let body: FileGeneralBody = serde_json::from_value(args).unwrap();
-            let response = delete_file(db, &body).await;
+            let response = delete_file(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "file_trash_list" => {
+            let body: FileTrashBody = serde_json::from_value(args).unwrap();
+            let response = list_trash(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "file_restore" => {
+            let body: FileGeneralBody = serde_json::from_value(args).unwrap();
+            let response = restore_file(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "file_trash_empty" => {
+            let body: FileTrashBody = serde_json::from_value(args).unwrap();
+            let response = empty_trash(db, user_path, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "file_search" => {
+            let body: FileSearchBody = serde_json::from_value(args).unwrap();
+            let response = file_search(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "file_version_list" => {
+            let body: FileVersionListBody = serde_json::from_value(args).unwrap();
+            let response = list_versions(db, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "file_version_get" => {
+            let body: FileVersionBody = serde_json::from_value(args).unwrap();
+            let response = get_version(db, user_path, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "file_version_restore" => {
+            let body: FileVersionBody = serde_json::from_value(args).unwrap();
+            let response = restore_version(db, user_path, version_settings, user_id, &body).await;
+            to_value(&response).unwrap()
+        }
+        "file_version_diff" => {
+            let body: FileVersionDiffBody = serde_json::from_value(args).unwrap();
+            let response = diff_versions(db, user_path, user_id, &body).await;
             to_value(&response).unwrap()
         }
         _ => to_value(&AppResponse::error(
@@ -2,40 +2,54 @@ use std::cell::OnceCell;
 use std::collections::HashMap;
 use std::future::Future;
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Mutex, RwLock};
+use std::time::Duration;
 use std::{fs, vec};
 
 use crate::entity::file::{
     ActiveModel as FileActiveModel, Column, Entity as File, Model as FileModel,
 };
 use anyhow::Context;
-use async_openai::config::OpenAIConfig;
-use async_openai::types::Role::{System, User};
-use async_openai::types::{
-    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
-    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessage,
-    ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
-};
-use async_openai::Client;
 use chrono::Utc;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
 use futures::future::ok;
-use futures::StreamExt;
-use log::{debug, error, info, trace};
+use log::{error, info, trace, warn};
 use once_cell::sync::Lazy;
-use sea_orm::{DatabaseConnection, Set};
+use sea_orm::{DatabaseConnection, DbErr, Set};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Error};
 use tauri::api::http::{Body, ClientBuilder, HttpRequestBuilder, ResponseType};
 use tauri::{App, Window};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+use crate::dao::chat_setting_dao::ChatSettingService;
 use crate::dao::file_dao::FileService;
+use crate::dao::search_dao::SearchService;
+use crate::dao::setting_dao::SettingService;
+use crate::dto::chat::ChunkPayload;
 use crate::dto::file::ListGeneralBody;
+use crate::entity::ai_model::Model as AiModel;
+use crate::entity::chat_setting::ActiveModel as ChatSettingActiveModel;
 use crate::entity::file::ActiveModel;
 use crate::service::ai_model_service::get as get_ai_model;
 use crate::service::ai_source_service::get as get_ai_source;
-use crate::{AppResponse, CHAT_ZONE, FILE_TYPE};
+use crate::service::chat_provider_service::{
+    new_provider, ChatError, ChatProvider, GenerationOptions,
+};
+use crate::service::chat_tree_service::{load_chat, parse_chat, save_chat, ChatTree, MessageNode};
+use crate::service::permission_service::{
+    check_admin, check_file, check_workspace, AccessError, Role as WorkspaceRole,
+};
+use crate::service::prompt_template_service::render_prompt;
+use crate::service::search_service::{index_file, remove_indexed_files, rename_file};
+use crate::service::setting_service::save_setting;
+use crate::{
+    AppResponse, RetrySettings, CHAT_FALLBACKS_SETTING_KEY, CHAT_FORMAT_SETTING_KEY,
+    CHAT_FORMAT_VERSION, CHAT_ZONE, FILE_TYPE,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ModelData {
@@ -58,11 +72,73 @@ pub struct Model {
     digest: String,
 }
 
+/// Author of a message in the chat history.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageRole {
+    // instructions for the model
+    System,
+    // prompt typed by the user
+    User,
+    // reply of the model
+    Assistant,
+    // result of a tool called by the model
+    Tool,
+}
+
+impl MessageRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageRole::System => "system",
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+            MessageRole::Tool => "tool",
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct Message {
-    role: String,
-    content: String,
+    pub role: MessageRole,
+    pub content: String,
+    // id of the tool call a tool message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    // how a reply ended when it is not complete
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<MessageStatus>,
+    // why a failed reply failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ChatError>,
+    // model which wrote the reply, a fallback when the requested one failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_id: Option<String>,
+}
+
+impl Message {
+    pub fn new(role: MessageRole, content: &str) -> Message {
+        Message {
+            role,
+            content: content.to_string(),
+            tool_call_id: None,
+            status: None,
+            error: None,
+            source_id: None,
+            model_id: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageStatus {
+    // stopped by the user, the content is what was received until then
+    Cancelled,
+    // stopped by an error of the provider, the content is what was received until then
+    Failed,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
@@ -74,8 +150,8 @@ pub struct ListBody {
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateBody {
-    name: String,
-    wid: String,
+    pub name: String,
+    pub wid: String,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
@@ -95,10 +171,23 @@ pub struct CommonBody {
 #[serde(rename_all = "camelCase")]
 pub struct RequestBody {
     pub id: String,
+    // ignored when a template is given
+    #[serde(default)]
     pub prompt: String,
     pub model_id: String,
     pub source_id: String,
     pub request_id: String,
+    // prompt template rendered with the variables instead of the prompt
+    #[serde(default)]
+    pub template_id: Option<String>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelBody {
+    pub request_id: String,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
@@ -111,6 +200,23 @@ pub struct RegenerateBody {
     pub request_id: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BranchBody {
+    pub id: String,
+    pub message_id: String,
+}
+
+/// A message of the active branch, the other versions are listed by its siblings.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BranchMessage {
+    #[serde(flatten)]
+    pub node: MessageNode,
+    pub sibling_index: usize,
+    pub sibling_count: usize,
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct EditBody {
@@ -122,23 +228,98 @@ pub struct EditBody {
     pub request_id: String,
 }
 
+/// A model of a source.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelRef {
+    pub source_id: String,
+    pub model_id: String,
+}
+
+/// System prompt and generation parameters applied to every request of the chat.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatSettings {
+    pub system_prompt: Option<String>,
+    #[serde(flatten)]
+    pub options: GenerationOptions,
+    // asked in order when the requested model fails, the global ones are used when empty
+    #[serde(default)]
+    pub fallbacks: Vec<ModelRef>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateSettingsBody {
+    pub id: String,
+    #[serde(flatten)]
+    pub settings: ChatSettings,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateFallbacksBody {
+    pub fallbacks: Vec<ModelRef>,
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct Response {
     id: String, // chat id
     text: Option<String>,
     index: usize,
-    error: Option<String>,
+    error: Option<ChatError>,
+    // model which wrote the reply
+    source_id: Option<String>,
+    model_id: Option<String>,
 }
 
+// fallback models of a chat or of all chats
+const MAX_FALLBACKS: usize = 8;
+
 // static API_ADDRESS: &str = https://api.deepseek.com/v1
 static API_ADDRESS: &str = "http://localhost:11434/v1";
 
+// streams in progress by request id, with the user who started them
+static IN_FLIGHT_REQUESTS: Lazy<DashMap<String, (String, CancellationToken)>> =
+    Lazy::new(DashMap::new);
+
+/// Stream request the user may cancel until it is dropped.
+struct InFlightRequest {
+    request_id: String,
+    token: CancellationToken,
+}
+
+impl InFlightRequest {
+    fn register(user_id: &str, request_id: &str) -> Result<InFlightRequest, &'static str> {
+        match IN_FLIGHT_REQUESTS.entry(request_id.to_string()) {
+            Entry::Occupied(_) => Err("request is already running"),
+            Entry::Vacant(entry) => {
+                let token = CancellationToken::new();
+                entry.insert((user_id.to_string(), token.clone()));
+                Ok(InFlightRequest {
+                    request_id: request_id.to_string(),
+                    token,
+                })
+            }
+        }
+    }
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        IN_FLIGHT_REQUESTS.remove(&self.request_id);
+    }
+}
+
 pub async fn list(
     db: &DatabaseConnection,
     user_id: &str,
     wid: &str,
 ) -> AppResponse<Option<Vec<ChatInfo>>> {
+    if let Err(err) = check_workspace(db, user_id, wid, WorkspaceRole::Viewer).await {
+        return err.response(None);
+    }
     match FileService::list_files(
         db,
         &ListGeneralBody {
@@ -169,6 +350,9 @@ pub async fn create(
     user_id: &str,
     body: &CreateBody,
 ) -> AppResponse<Option<ChatInfo>> {
+    if let Err(err) = check_workspace(db, user_id, &body.wid, WorkspaceRole::Editor).await {
+        return err.response(None);
+    }
     match FileService::create_file(
         db,
         ActiveModel {
@@ -187,18 +371,36 @@ pub async fn create(
     )
     .await
     {
-        Ok(model) => AppResponse::success(Some(ChatInfo {
-            id: model.id,
-            name: model.name,
-            create_time: model.create_time,
-        })),
+        Ok(model) => {
+            // transcript is indexed once the first message is written
+            let result =
+                SearchService::index_file(db, &model.id, &model.wid, CHAT_ZONE, &model.name, "")
+                    .await;
+            if let Err(err) = result {
+                error!("index chat {} failed, err: {}", model.id, err);
+            }
+            AppResponse::success(Some(ChatInfo {
+                id: model.id,
+                name: model.name,
+                create_time: model.create_time,
+            }))
+        }
         Err(err) => AppResponse::error(None::<ChatInfo>, &err.to_string()),
     }
 }
 
 pub async fn delete(db: &DatabaseConnection, user_id: &str, id: &str) -> AppResponse<String> {
+    if let Err(err) = check_file(db, user_id, id, WorkspaceRole::Editor).await {
+        return err.response("".to_string());
+    }
     match FileService::delete_file(db, &id).await {
-        Ok(_) => AppResponse::success("".to_string()),
+        Ok(_) => {
+            remove_indexed_files(db, &[id.to_string()]).await;
+            if let Err(err) = ChatSettingService::delete_settings(db, &[id.to_string()]).await {
+                error!("delete settings of chat {} failed, err: {}", id, err);
+            }
+            AppResponse::success("".to_string())
+        }
         Err(err) => AppResponse::error("".to_string(), &err.to_string()),
     }
 }
@@ -208,21 +410,19 @@ pub async fn update_name(
     user_id: &str,
     body: &UpdateNameBody,
 ) -> AppResponse<Option<ChatInfo>> {
-    let get_result = FileService::get_file(db, &body.id).await;
-    if get_result.is_err() {
-        return AppResponse::error(None, &get_result.err().unwrap().to_string());
-    }
-    let option_model = get_result.unwrap();
-    if option_model.is_none() {
-        return AppResponse::error(None, "chat not found");
-    }
-    let model = option_model.unwrap();
+    let model = match check_file(db, user_id, &body.id, WorkspaceRole::Editor).await {
+        Ok(model) => model,
+        Err(err) => return err.response(None),
+    };
     match FileService::update_file_name(db, &body.id, &body.name).await {
-        Ok(_) => AppResponse::success(Some(ChatInfo {
-            id: model.id,
-            name: model.name,
-            create_time: model.create_time,
-        })),
+        Ok(_) => {
+            rename_file(db, &body.id, &body.name).await;
+            AppResponse::success(Some(ChatInfo {
+                id: model.id,
+                name: model.name,
+                create_time: model.create_time,
+            }))
+        }
         Err(err) => AppResponse::error(None, &err.to_string()),
     }
 }
@@ -232,61 +432,639 @@ pub async fn message_list(
     user_path: &PathBuf,
     user_id: &str,
     id: &str,
-) -> AppResponse<Option<Vec<Message>>> {
-    let get_result = FileService::get_file(db, id).await;
-    if get_result.is_err() {
-        return AppResponse::error(None, &get_result.err().unwrap().to_string());
-    }
-    let option_model = get_result.unwrap();
-    if option_model.is_none() {
-        return AppResponse::error(None, "chat not found in db");
+) -> AppResponse<Option<Vec<BranchMessage>>> {
+    let app_response = get_chat(db, user_id, id, WorkspaceRole::Viewer).await;
+    if !app_response.is_success() {
+        return AppResponse {
+            code: app_response.code,
+            r#type: app_response.r#type,
+            message: app_response.message,
+            result: None,
+        };
     }
-    let mut model = option_model.unwrap();
+    let mut model = app_response.result.unwrap();
     let file_path = &user_path.join(&model.wid).join(&model.id);
     if !file_path.exists() {
         return AppResponse::error(None, "chat not found in file system");
     }
-    // todo catch error
-    // 读取文件内容到字符串
-    let file_content = fs::read_to_string(file_path).unwrap();
-    // 将 JSON 字符串反序列化为结构体
-    let data: Vec<Message> = serde_json::from_str(&file_content).unwrap();
-    AppResponse::success(Some(data))
+    match load_chat(file_path) {
+        Ok(tree) => AppResponse::success(Some(branch_messages(&tree))),
+        Err(err) => {
+            error!("read chat {} failed, err: {}", model.id, err);
+            AppResponse::error(None, "read chat failed")
+        }
+    }
 }
 
-async fn get_chat(db: &DatabaseConnection, id: &str) -> AppResponse<Option<FileModel>> {
-    let get_result = FileService::get_file(db, id).await;
-    if get_result.is_err() {
-        return AppResponse::error(None, &get_result.err().unwrap().to_string());
+/// Messages of the active branch with their place among their siblings.
+fn branch_messages(tree: &ChatTree) -> Vec<BranchMessage> {
+    tree.active_path()
+        .into_iter()
+        .map(|node| {
+            let siblings = tree.children(node.parent_id.as_deref());
+            BranchMessage {
+                sibling_index: siblings
+                    .iter()
+                    .position(|sibling| sibling.id == node.id)
+                    .unwrap_or_default(),
+                sibling_count: siblings.len(),
+                node: node.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Other versions of a message, written by edits and regenerations.
+pub async fn message_siblings(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    user_id: &str,
+    body: &BranchBody,
+) -> AppResponse<Option<Vec<MessageNode>>> {
+    let app_response = get_chat(db, user_id, &body.id, WorkspaceRole::Viewer).await;
+    if !app_response.is_success() {
+        return AppResponse {
+            code: app_response.code,
+            r#type: app_response.r#type,
+            message: app_response.message,
+            result: None,
+        };
+    }
+    let model = app_response.result.unwrap();
+    let file_path = &user_path.join(&model.wid).join(&model.id);
+    if !file_path.exists() {
+        return AppResponse::error(None, "chat not found in file system");
     }
-    let option_model = get_result.unwrap();
-    if option_model.is_none() {
-        return AppResponse::error(None, "chat not found in db");
+    let tree = match load_chat(file_path) {
+        Ok(tree) => tree,
+        Err(err) => {
+            error!("read chat {} failed, err: {}", model.id, err);
+            return AppResponse::error(None, "read chat failed");
+        }
+    };
+    match tree.siblings(&body.message_id) {
+        Some(siblings) => AppResponse::success(Some(siblings.into_iter().cloned().collect())),
+        None => AppResponse::error(None, "message not found"),
     }
-    AppResponse::success(Some(option_model.unwrap()))
 }
 
-pub async fn message_request(
+/// Show the branch through the message and send it to the model on the next requests.
+pub async fn message_switch(
     db: &DatabaseConnection,
     user_path: &PathBuf,
     user_id: &str,
-    body: &RequestBody,
-) -> AppResponse<Option<Response>> {
-    let app_response = get_chat(db, &body.id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
+    body: &BranchBody,
+) -> AppResponse<Option<Vec<BranchMessage>>> {
+    let app_response = get_chat(db, user_id, &body.id, WorkspaceRole::Editor).await;
+    if !app_response.is_success() {
+        return AppResponse {
+            code: app_response.code,
+            r#type: app_response.r#type,
+            message: app_response.message,
+            result: None,
+        };
     }
     let model = app_response.result.unwrap();
-    let app_response = get_ai_source(db, &body.source_id).await;
+    let file_path = &user_path.join(&model.wid).join(&model.id);
+    if !file_path.exists() {
+        return AppResponse::error(None, "chat not found in file system");
+    }
+    let mut tree = match load_chat(file_path) {
+        Ok(tree) => tree,
+        Err(err) => {
+            error!("read chat {} failed, err: {}", model.id, err);
+            return AppResponse::error(None, "read chat failed");
+        }
+    };
+    if !tree.switch(&body.message_id) {
+        return AppResponse::error(None, "message not found");
+    }
+    if let Err(err) = save_chat(file_path, &tree) {
+        error!("save chat {} failed, err: {}", model.id, err);
+        return AppResponse::error(None, "save chat failed");
+    }
+    AppResponse::success(Some(branch_messages(&tree)))
+}
+
+/// A model to ask with the provider of its source.
+struct Candidate {
+    source_id: String,
+    model: AiModel,
+    provider: Box<dyn ChatProvider>,
+}
+
+impl Candidate {
+    /// Reply written by the model.
+    fn reply(&self, content: &str) -> Message {
+        let mut message = Message::new(MessageRole::Assistant, content);
+        message.source_id = Some(self.source_id.clone());
+        message.model_id = Some(self.model.id.clone());
+        message
+    }
+
+    fn failed_reply(&self, content: &str, err: ChatError) -> Message {
+        let mut message = self.reply(content);
+        message.status = Some(MessageStatus::Failed);
+        message.error = Some(err);
+        message
+    }
+}
+
+/// Model of the source, fallbacks must be enabled and belong to the source.
+async fn get_candidate(
+    db: &DatabaseConnection,
+    source_id: &str,
+    model_id: &str,
+    fallback: bool,
+) -> Result<Candidate, String> {
+    let app_response = get_ai_source(db, source_id).await;
     if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
+        return Err(app_response.message);
     }
-    let ai_source = app_response.result.unwrap();
-    let app_response = get_ai_model(db, &body.model_id).await;
+    let Some(ai_source) = app_response.result else {
+        return Err("ai source not found".to_string());
+    };
+    let app_response = get_ai_model(db, model_id).await;
     if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
+        return Err(app_response.message);
+    }
+    let Some(ai_model) = app_response.result else {
+        return Err("ai model not found".to_string());
+    };
+    if fallback && (!ai_source.enable || !ai_model.enable) {
+        return Err("ai model is disabled".to_string());
+    }
+    if fallback && ai_model.source_id != ai_source.id {
+        return Err("ai model does not belong to the source".to_string());
+    }
+    Ok(Candidate {
+        source_id: ai_source.id.clone(),
+        provider: new_provider(&ai_source)?,
+        model: ai_model,
+    })
+}
+
+/// The requested model followed by the usable fallbacks of the chat,
+/// or the global ones when the chat has none.
+async fn get_candidates(
+    db: &DatabaseConnection,
+    settings: &ChatSettings,
+    source_id: &str,
+    model_id: &str,
+) -> Result<Vec<Candidate>, String> {
+    let mut candidates = vec![get_candidate(db, source_id, model_id, false).await?];
+    let fallbacks = match settings.fallbacks.is_empty() {
+        true => load_fallbacks(db).await.map_err(|err| err.to_string())?,
+        false => settings.fallbacks.clone(),
+    };
+    for fallback in fallbacks {
+        let asked = candidates.iter().any(|candidate| {
+            candidate.source_id == fallback.source_id && candidate.model.id == fallback.model_id
+        });
+        if asked {
+            continue;
+        }
+        match get_candidate(db, &fallback.source_id, &fallback.model_id, true).await {
+            Ok(candidate) => candidates.push(candidate),
+            Err(message) => info!("skip fallback model {}, {}", fallback.model_id, message),
+        }
+    }
+    Ok(candidates)
+}
+
+/// Backoff before the retry following `attempt` failed retries, doubling from the initial delay.
+fn retry_delay(settings: &RetrySettings, attempt: u32) -> Duration {
+    let delay = settings
+        .initial_delay_ms
+        .saturating_mul(2u64.saturating_pow(attempt));
+    Duration::from_millis(delay.min(settings.max_delay_ms))
+}
+
+fn no_candidate_reply() -> Message {
+    let mut message = Message::new(MessageRole::Assistant, "");
+    message.status = Some(MessageStatus::Failed);
+    message.error = Some(ChatError::Provider {
+        status: None,
+        body: "no model to ask".to_string(),
+    });
+    message
+}
+
+/// Ask the candidates in order until one replies, transient errors are retried with backoff
+/// before the next candidate is asked.
+async fn chat_reply(
+    retry_settings: &RetrySettings,
+    candidates: &[Candidate],
+    history: &[Message],
+    options: &GenerationOptions,
+) -> Message {
+    for (index, candidate) in candidates.iter().enumerate() {
+        let mut attempt = 0;
+        loop {
+            let model = &candidate.model.name;
+            let err = match candidate.provider.chat(history, model, options).await {
+                Ok(text) => return candidate.reply(&text),
+                Err(err) => err,
+            };
+            error!("send chat request to model {} error: {}", model, err);
+            if err.is_transient() && attempt < retry_settings.max_retries {
+                tokio::time::sleep(retry_delay(retry_settings, attempt)).await;
+                attempt += 1;
+                continue;
+            }
+            if index + 1 == candidates.len() {
+                return candidate.failed_reply("", err);
+            }
+            break;
+        }
+    }
+    no_candidate_reply()
+}
+
+/// Stream one attempt of the candidate into the reply, `None` when the request was cancelled.
+async fn stream_attempt<F>(
+    callback: &F,
+    request: &InFlightRequest,
+    candidate: &Candidate,
+    history: &[Message],
+    options: &GenerationOptions,
+    reply: &mut String,
+) -> Option<Result<(), ChatError>>
+where
+    F: Fn(ChunkPayload) + Sync,
+{
+    let mut callback_wrapper = |content: String| {
+        reply.push_str(&content);
+        callback(ChunkPayload::text(content));
+    };
+    let model = &candidate.model.name;
+    let stream = candidate
+        .provider
+        .chat_stream(&mut callback_wrapper, history, model, options);
+    // dropping the stream closes the connection to the provider
+    tokio::select! {
+        result = stream => Some(result),
+        _ = request.token.cancelled() => None,
+    }
+}
+
+/// Send the reply to the callback until it ends, fails or the request is cancelled,
+/// return the reply with the text received, how it ended and the model which wrote it.
+///
+/// Like `chat_reply` failed candidates are retried and then replaced by the next one,
+/// but only as long as no text was sent to the callback.
+async fn stream_reply<F>(
+    callback: &F,
+    request: &InFlightRequest,
+    retry_settings: &RetrySettings,
+    candidates: &[Candidate],
+    history: &[Message],
+    options: &GenerationOptions,
+) -> Message
+where
+    F: Fn(ChunkPayload) + Sync,
+{
+    let mut reply = String::new();
+    let cancelled = |candidate: &Candidate, reply: &str| {
+        callback(ChunkPayload::cancelled());
+        let mut message = candidate.reply(reply);
+        message.status = Some(MessageStatus::Cancelled);
+        message
+    };
+    for (index, candidate) in candidates.iter().enumerate() {
+        let mut attempt = 0;
+        loop {
+            let result =
+                stream_attempt(callback, request, candidate, history, options, &mut reply).await;
+            let err = match result {
+                Some(Ok(())) => {
+                    callback(ChunkPayload::end());
+                    return candidate.reply(&reply);
+                }
+                Some(Err(err)) => err,
+                None => return cancelled(candidate, &reply),
+            };
+            error!(
+                "chat stream of model {} failed, err: {}",
+                candidate.model.name, err
+            );
+            // text sent to the callback can not be taken back
+            let partial = !reply.is_empty();
+            let retry = !partial && err.is_transient() && attempt < retry_settings.max_retries;
+            if !retry && (partial || index + 1 == candidates.len()) {
+                callback(ChunkPayload::error(err.clone()));
+                return candidate.failed_reply(&reply, err);
+            }
+            if !retry {
+                warn!("ask the next model instead of {}", candidate.model.name);
+                break;
+            }
+            let delay = retry_delay(retry_settings, attempt);
+            attempt += 1;
+            let wait = tokio::select! {
+                _ = tokio::time::sleep(delay) => true,
+                _ = request.token.cancelled() => false,
+            };
+            if !wait {
+                return cancelled(candidate, &reply);
+            }
+        }
+    }
+    let message = no_candidate_reply();
+    callback(ChunkPayload::error(message.error.clone().unwrap()));
+    message
+}
+
+/// Response of a request, an error when the reply failed though the chat keeps it.
+fn reply_response(id: &str, index: usize, reply: &Message) -> AppResponse<Option<Response>> {
+    let response = Response {
+        id: id.to_string(),
+        text: Some(reply.content.clone()),
+        index,
+        error: reply.error.clone(),
+        source_id: reply.source_id.clone(),
+        model_id: reply.model_id.clone(),
+    };
+    match &reply.error {
+        Some(err) => {
+            let mut app_response = AppResponse::error(Some(response), &err.to_string());
+            app_response.r#type = err.kind().to_string();
+            app_response
+        }
+        None => AppResponse::success(Some(response)),
     }
-    let ai_model = app_response.result.unwrap();
+}
+
+/// Stop the stream of the request, the reply received so far is kept.
+pub fn message_cancel(user_id: &str, body: &CancelBody) -> AppResponse<bool> {
+    match IN_FLIGHT_REQUESTS.get(&body.request_id) {
+        Some(request) if request.0 == user_id => {
+            request.1.cancel();
+            AppResponse::success(true)
+        }
+        _ => AppResponse::success(false),
+    }
+}
+
+/// Prompt typed by the user or rendered from the template of the request.
+async fn request_prompt(
+    db: &DatabaseConnection,
+    user_id: &str,
+    wid: &str,
+    body: &RequestBody,
+) -> Result<String, String> {
+    match &body.template_id {
+        Some(template_id) => render_prompt(db, user_id, wid, template_id, &body.variables).await,
+        None => Ok(body.prompt.clone()),
+    }
+}
+
+/// Settings of the chat, a chat that was never configured uses the defaults.
+async fn load_settings(db: &DatabaseConnection, id: &str) -> Result<ChatSettings, DbErr> {
+    let model = match ChatSettingService::get_setting(db, id).await? {
+        Some(model) => model,
+        None => return Ok(ChatSettings::default()),
+    };
+    let stop = match &model.stop {
+        Some(stop) => serde_json::from_str(stop).unwrap_or_default(),
+        None => vec![],
+    };
+    let fallbacks = match &model.fallbacks {
+        Some(fallbacks) => serde_json::from_str(fallbacks).unwrap_or_default(),
+        None => vec![],
+    };
+    Ok(ChatSettings {
+        system_prompt: model.system_prompt,
+        options: GenerationOptions {
+            temperature: model.temperature.map(|temperature| temperature as f32),
+            top_p: model.top_p.map(|top_p| top_p as f32),
+            max_tokens: model.max_tokens.map(|max_tokens| max_tokens as u32),
+            stop,
+            seed: model.seed,
+        },
+        fallbacks,
+    })
+}
+
+/// Fallback models of the chats without fallbacks of their own.
+async fn load_fallbacks(db: &DatabaseConnection) -> Result<Vec<ModelRef>, DbErr> {
+    let model = SettingService::get_setting_by_key(db, CHAT_FALLBACKS_SETTING_KEY).await?;
+    Ok(model
+        .and_then(|model| serde_json::from_slice(&model.value).ok())
+        .unwrap_or_default())
+}
+
+fn check_fallbacks(fallbacks: &[ModelRef]) -> Result<(), &'static str> {
+    if fallbacks.len() > MAX_FALLBACKS {
+        return Err("at most 8 fallback models are allowed");
+    }
+    if fallbacks
+        .iter()
+        .any(|fallback| fallback.source_id.is_empty() || fallback.model_id.is_empty())
+    {
+        return Err("fallback model needs a source and a model");
+    }
+    Ok(())
+}
+
+/// History sent to the model, led by the system prompt of the chat. Failed replies and empty
+/// replies are no context, some providers reject an empty assistant message.
+fn with_system_prompt(settings: &ChatSettings, messages: &[Message]) -> Vec<Message> {
+    let mut history = vec![];
+    if let Some(system_prompt) = &settings.system_prompt {
+        if !system_prompt.is_empty() {
+            history.push(Message::new(MessageRole::System, system_prompt));
+        }
+    }
+    history.extend(messages.iter().cloned().filter(|message| {
+        message.status != Some(MessageStatus::Failed)
+            && !(message.role == MessageRole::Assistant && message.content.is_empty())
+    }));
+    history
+}
+
+fn check_settings(settings: &ChatSettings) -> Result<(), &'static str> {
+    let options = &settings.options;
+    if options
+        .temperature
+        .is_some_and(|temperature| !(0.0..=2.0).contains(&temperature))
+    {
+        return Err("temperature must be between 0 and 2");
+    }
+    if options
+        .top_p
+        .is_some_and(|top_p| !(0.0..=1.0).contains(&top_p))
+    {
+        return Err("top_p must be between 0 and 1");
+    }
+    if options
+        .max_tokens
+        .is_some_and(|max_tokens| max_tokens == 0 || max_tokens > i32::MAX as u32)
+    {
+        return Err("max tokens must be greater than 0");
+    }
+    // openai compatible apis accept up to 4 stop sequences
+    if options.stop.len() > 4 || options.stop.iter().any(|stop| stop.is_empty()) {
+        return Err("at most 4 non empty stop sequences are allowed");
+    }
+    check_fallbacks(&settings.fallbacks)
+}
+
+pub async fn get_settings(
+    db: &DatabaseConnection,
+    user_id: &str,
+    id: &str,
+) -> AppResponse<Option<ChatSettings>> {
+    let app_response = get_chat(db, user_id, id, WorkspaceRole::Viewer).await;
+    if !app_response.is_success() {
+        return AppResponse {
+            code: app_response.code,
+            r#type: app_response.r#type,
+            message: app_response.message,
+            result: None,
+        };
+    }
+    match load_settings(db, id).await {
+        Ok(settings) => AppResponse::success(Some(settings)),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+pub async fn update_settings(
+    db: &DatabaseConnection,
+    user_id: &str,
+    body: &UpdateSettingsBody,
+) -> AppResponse<Option<ChatSettings>> {
+    let app_response = get_chat(db, user_id, &body.id, WorkspaceRole::Editor).await;
+    if !app_response.is_success() {
+        return AppResponse {
+            code: app_response.code,
+            r#type: app_response.r#type,
+            message: app_response.message,
+            result: None,
+        };
+    }
+    if let Err(message) = check_settings(&body.settings) {
+        return AppResponse::error(None, message);
+    }
+    let exists = match ChatSettingService::get_setting(db, &body.id).await {
+        Ok(model) => model.is_some(),
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let options = &body.settings.options;
+    let stop = match options.stop.is_empty() {
+        true => None,
+        false => Some(serde_json::to_string(&options.stop).unwrap()),
+    };
+    let fallbacks = &body.settings.fallbacks;
+    let fallbacks = match fallbacks.is_empty() {
+        true => None,
+        false => Some(serde_json::to_string(fallbacks).unwrap()),
+    };
+    let mut active_model = ChatSettingActiveModel {
+        id: Set(body.id.clone()),
+        system_prompt: Set(body.settings.system_prompt.clone()),
+        temperature: Set(options.temperature.map(f64::from)),
+        top_p: Set(options.top_p.map(f64::from)),
+        max_tokens: Set(options.max_tokens.map(|max_tokens| max_tokens as i32)),
+        stop: Set(stop),
+        seed: Set(options.seed),
+        fallbacks: Set(fallbacks),
+        update_time: Set(Utc::now().timestamp()),
+        ..Default::default()
+    };
+    if !exists {
+        active_model.create_time = Set(Utc::now().timestamp());
+    }
+    match ChatSettingService::save_setting(db, active_model, exists).await {
+        Ok(_) => AppResponse::success(Some(body.settings.clone())),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+pub async fn get_fallbacks(db: &DatabaseConnection) -> AppResponse<Vec<ModelRef>> {
+    match load_fallbacks(db).await {
+        Ok(fallbacks) => AppResponse::success(fallbacks),
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+/// Replace the fallback models of the chats without fallbacks of their own, they apply to the
+/// chats of every user so only the owner of the install may change them.
+pub async fn update_fallbacks(
+    db: &DatabaseConnection,
+    user_id: &str,
+    body: &UpdateFallbacksBody,
+) -> AppResponse<Vec<ModelRef>> {
+    if let Err(err) = check_admin(db, user_id).await {
+        return err.response(vec![]);
+    }
+    if let Err(message) = check_fallbacks(&body.fallbacks) {
+        return AppResponse::error(vec![], message);
+    }
+    let value = serde_json::to_string(&body.fallbacks).unwrap();
+    match save_setting(db, CHAT_FALLBACKS_SETTING_KEY, &value).await {
+        Ok(_) => AppResponse::success(body.fallbacks.clone()),
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+async fn get_chat(
+    db: &DatabaseConnection,
+    user_id: &str,
+    id: &str,
+    role: WorkspaceRole,
+) -> AppResponse<Option<FileModel>> {
+    match check_file(db, user_id, id, role).await {
+        Ok(model) => AppResponse::success(Some(model)),
+        Err(AccessError::NotFound(_)) => AppResponse::error(None, "chat not found in db"),
+        Err(err) => err.response(None),
+    }
+}
+
+/// Parent of the message at the index of the active branch, which must have the given role.
+/// A stale index from the ui must not add the message anywhere else.
+fn branch_point(
+    tree: &ChatTree,
+    index: usize,
+    role: MessageRole,
+) -> Result<Option<String>, &'static str> {
+    match tree.active_path().get(index) {
+        Some(node) if node.message.role == role => Ok(node.parent_id.clone()),
+        Some(_) => Err("message at the index has another role"),
+        None => Err("message not found in chat"),
+    }
+}
+
+pub async fn message_request(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    user_id: &str,
+    retry_settings: &RetrySettings,
+    body: &RequestBody,
+) -> AppResponse<Option<Response>> {
+    let app_response = get_chat(db, user_id, &body.id, WorkspaceRole::Editor).await;
+    if !app_response.is_success() {
+        return AppResponse {
+            code: app_response.code,
+            r#type: app_response.r#type,
+            message: app_response.message,
+            result: None,
+        };
+    }
+    let model = app_response.result.unwrap();
+    let settings = match load_settings(db, &model.id).await {
+        Ok(settings) => settings,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let candidates = match get_candidates(db, &settings, &body.source_id, &body.model_id).await {
+        Ok(candidates) => candidates,
+        Err(message) => return AppResponse::error(None, &message),
+    };
+    let prompt = match request_prompt(db, user_id, &model.wid, body).await {
+        Ok(prompt) => prompt,
+        Err(message) => return AppResponse::error(None, &message),
+    };
     let file_path = &user_path.join(&model.wid).join(&model.id);
     if !file_path.exists() {
         let result = fs::File::create(file_path);
@@ -301,35 +1079,30 @@ pub async fn message_request(
             );
         }
     }
-    // todo catch error
-    // 读取文件内容到字符串
-    let file_content = fs::read_to_string(file_path).unwrap();
-    // 将 JSON 字符串反序列化为结构体
-    let mut messages = Vec::new();
-    if !file_content.is_empty() {
-        messages = serde_json::from_str(&file_content).unwrap();
-    }
+    let mut tree = match load_chat(file_path) {
+        Ok(tree) => tree,
+        Err(err) => {
+            error!("read chat {} failed, err: {}", model.id, err);
+            return AppResponse::error(None, "read chat failed");
+        }
+    };
     // add user message
-    messages.push(Message {
-        role: User.to_string(),
-        content: body.prompt.clone(),
-    });
-    // add system message
-    let text = do_openai_request(&messages, &ai_source.url, &ai_source.key, &ai_model.name).await;
-    messages.push(Message {
-        role: System.to_string(),
-        content: text.clone(),
-    });
-    // save to file
-    let json_str = serde_json::to_string(&messages).unwrap();
-    fs::write(file_path, json_str).unwrap();
+    let parent_id = tree.active_id.clone();
+    let prompt_id = tree.push(
+        parent_id.as_deref(),
+        Message::new(MessageRole::User, &prompt),
+    );
+    // add assistant message
+    let history = with_system_prompt(&settings, &tree.active_messages());
+    let reply = chat_reply(retry_settings, &candidates, &history, &settings.options).await;
+    tree.push(Some(&prompt_id), reply.clone());
+    if let Err(err) = save_chat(file_path, &tree) {
+        error!("save chat {} failed, err: {}", model.id, err);
+        return AppResponse::error(None, "save chat failed");
+    }
+    index_file(db, user_path, &model).await;
     // return
-    AppResponse::success(Some(Response {
-        id: body.id.clone(),
-        index: messages.len() - 1,
-        text: Some(text),
-        error: None,
-    }))
+    reply_response(&body.id, tree.active_path().len() - 1, &reply)
 }
 
 pub async fn message_request_stream<F>(
@@ -337,26 +1110,34 @@ pub async fn message_request_stream<F>(
     db: &DatabaseConnection,
     user_path: &PathBuf,
     user_id: &str,
+    retry_settings: &RetrySettings,
     body: &RequestBody,
 ) -> AppResponse<Option<Response>>
 where
-    F: Fn(Option<String>, i8),
+    F: Fn(ChunkPayload) + Sync,
 {
-    let app_response = get_chat(db, &body.id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
+    let app_response = get_chat(db, user_id, &body.id, WorkspaceRole::Editor).await;
+    if !app_response.is_success() {
+        return AppResponse {
+            code: app_response.code,
+            r#type: app_response.r#type,
+            message: app_response.message,
+            result: None,
+        };
     }
     let model = app_response.result.unwrap();
-    let app_response = get_ai_source(db, &body.source_id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
-    }
-    let ai_source = app_response.result.unwrap();
-    let app_response = get_ai_model(db, &body.model_id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
-    }
-    let ai_model = app_response.result.unwrap();
+    let settings = match load_settings(db, &model.id).await {
+        Ok(settings) => settings,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let candidates = match get_candidates(db, &settings, &body.source_id, &body.model_id).await {
+        Ok(candidates) => candidates,
+        Err(message) => return AppResponse::error(None, &message),
+    };
+    let prompt = match request_prompt(db, user_id, &model.wid, body).await {
+        Ok(prompt) => prompt,
+        Err(message) => return AppResponse::error(None, &message),
+    };
     let file_path = &user_path.join(&model.wid).join(&model.id);
     if !file_path.exists() {
         let result = fs::File::create(file_path);
@@ -371,56 +1152,39 @@ where
             );
         }
     }
-    // todo catch error
-    // 读取文件内容到字符串
-    let file_content = fs::read_to_string(file_path).unwrap();
-    // 将 JSON 字符串反序列化为结构体
-    let mut messages = Vec::new();
-    if !file_content.is_empty() {
-        messages = serde_json::from_str(&file_content).unwrap();
-    }
-    // add user message
-    messages.push(Message {
-        role: User.to_string(),
-        content: body.prompt.clone(),
-    });
-    messages.push(Message {
-        role: System.to_string(),
-        content: String::new(),
-    });
-    let callback_wrapper = |content: Option<String>, status: i8| {
-        let mut messages = messages.clone();
-        let len = messages.len() - 1;
-        match messages.get_mut(len) {
-            None => {}
-            Some(message) => {
-                if content.is_some() {
-                    message.content = format!("{}{}", &message.content, content.clone().unwrap());
-                } else {
-                    let json_str = serde_json::to_string(&messages).unwrap();
-                    fs::write(file_path, json_str).unwrap();
-                }
-                // invoke callback
-                callback(content, status);
-            }
+    let mut tree = match load_chat(file_path) {
+        Ok(tree) => tree,
+        Err(err) => {
+            error!("read chat {} failed, err: {}", model.id, err);
+            return AppResponse::error(None, "read chat failed");
         }
     };
-    // add system message
-    do_openai_request_stream(
-        callback_wrapper,
-        &messages,
-        &ai_source.url,
-        &ai_source.key,
-        &ai_model.name,
+    // add user message
+    let active_id = tree.active_id.clone();
+    let prompt = Message::new(MessageRole::User, &prompt);
+    let parent_id = Some(tree.push(active_id.as_deref(), prompt));
+    let request = match InFlightRequest::register(user_id, &body.request_id) {
+        Ok(request) => request,
+        Err(message) => return AppResponse::error(None, message),
+    };
+    let history = with_system_prompt(&settings, &tree.active_messages());
+    let reply = stream_reply(
+        &callback,
+        &request,
+        retry_settings,
+        &candidates,
+        &history,
+        &settings.options,
     )
     .await;
+    tree.push(parent_id.as_deref(), reply.clone());
+    if let Err(err) = save_chat(file_path, &tree) {
+        error!("save chat {} failed, err: {}", model.id, err);
+        return AppResponse::error(None, "save chat failed");
+    }
+    index_file(db, user_path, &model).await;
     // return
-    AppResponse::success(Some(Response {
-        id: body.id.clone(),
-        index: messages.len() - 1,
-        text: Some(String::new()),
-        error: None,
-    }))
+    reply_response(&body.id, tree.active_path().len() - 1, &reply)
 }
 
 pub async fn message_regenerate<F>(
@@ -428,76 +1192,73 @@ pub async fn message_regenerate<F>(
     db: &DatabaseConnection,
     user_path: &PathBuf,
     user_id: &str,
+    retry_settings: &RetrySettings,
     body: &RegenerateBody,
 ) -> AppResponse<Option<Response>>
 where
-    F: Fn(Option<String>, i8),
+    F: Fn(ChunkPayload) + Sync,
 {
-    let app_response = get_chat(db, &body.id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
+    let app_response = get_chat(db, user_id, &body.id, WorkspaceRole::Editor).await;
+    if !app_response.is_success() {
+        return AppResponse {
+            code: app_response.code,
+            r#type: app_response.r#type,
+            message: app_response.message,
+            result: None,
+        };
     }
     let model = app_response.result.unwrap();
-    let app_response = get_ai_source(db, &body.source_id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
-    }
-    let ai_source = app_response.result.unwrap();
-    let app_response = get_ai_model(db, &body.model_id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
-    }
-    let ai_model = app_response.result.unwrap();
+    let settings = match load_settings(db, &model.id).await {
+        Ok(settings) => settings,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let candidates = match get_candidates(db, &settings, &body.source_id, &body.model_id).await {
+        Ok(candidates) => candidates,
+        Err(message) => return AppResponse::error(None, &message),
+    };
     let file_path = &user_path.join(&model.wid).join(&model.id);
     if !file_path.exists() {
         return AppResponse::error(None, "chat not found in file system");
     }
-    // todo catch error
-    // 读取文件内容到字符串
-    let file_content = fs::read_to_string(file_path).unwrap();
-    // 将 JSON 字符串反序列化为结构体
-    let mut messages: Vec<Message> = serde_json::from_str(&file_content).unwrap();
-    if body.index < messages.len() {
-        messages.truncate(body.index);
-    }
-    // add system message
-    messages.push(Message {
-        role: System.to_string(),
-        content: String::new(),
-    });
-    let callback_wrapper = |content: Option<String>, status: i8| {
-        let mut messages = messages.clone();
-        let len = messages.len() - 1;
-        match messages.get_mut(len) {
-            None => {}
-            Some(message) => {
-                if content.is_some() {
-                    message.content = format!("{}{}", &message.content, content.clone().unwrap());
-                } else {
-                    let json_str = serde_json::to_string(&messages).unwrap();
-                    fs::write(file_path, json_str).unwrap();
-                }
-                // invoke callback
-                callback(content, status);
-            }
+    let mut tree = match load_chat(file_path) {
+        Ok(tree) => tree,
+        Err(err) => {
+            error!("read chat {} failed, err: {}", model.id, err);
+            return AppResponse::error(None, "read chat failed");
         }
     };
-    // add system message
-    do_openai_request_stream(
-        callback_wrapper,
-        &messages,
-        &ai_source.url,
-        &ai_source.key,
-        &ai_model.name,
+    // the reply is added next to the message at the index, which stays in its own branch
+    let parent_id = match branch_point(&tree, body.index, MessageRole::Assistant) {
+        Ok(parent_id) => parent_id,
+        Err(message) => return AppResponse::error(None, message),
+    };
+    let request = match InFlightRequest::register(user_id, &body.request_id) {
+        Ok(request) => request,
+        Err(message) => return AppResponse::error(None, message),
+    };
+    let messages: Vec<Message> = tree
+        .path_to(parent_id.as_deref())
+        .into_iter()
+        .map(|node| node.message.clone())
+        .collect();
+    let history = with_system_prompt(&settings, &messages);
+    let reply = stream_reply(
+        &callback,
+        &request,
+        retry_settings,
+        &candidates,
+        &history,
+        &settings.options,
     )
     .await;
+    tree.push(parent_id.as_deref(), reply.clone());
+    if let Err(err) = save_chat(file_path, &tree) {
+        error!("save chat {} failed, err: {}", model.id, err);
+        return AppResponse::error(None, "save chat failed");
+    }
+    index_file(db, user_path, &model).await;
     // return
-    AppResponse::success(Some(Response {
-        id: body.id.clone(),
-        index: body.index.clone(),
-        text: Some(String::new()),
-        error: None,
-    }))
+    reply_response(&body.id, tree.active_path().len() - 1, &reply)
 }
 
 pub async fn message_edit<F>(
@@ -505,175 +1266,70 @@ pub async fn message_edit<F>(
     db: &DatabaseConnection,
     user_path: &PathBuf,
     user_id: &str,
+    retry_settings: &RetrySettings,
     body: &EditBody,
 ) -> AppResponse<Option<Response>>
 where
-    F: Fn(Option<String>, i8),
+    F: Fn(ChunkPayload) + Sync,
 {
-    let app_response = get_chat(db, &body.id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
+    let app_response = get_chat(db, user_id, &body.id, WorkspaceRole::Editor).await;
+    if !app_response.is_success() {
+        return AppResponse {
+            code: app_response.code,
+            r#type: app_response.r#type,
+            message: app_response.message,
+            result: None,
+        };
     }
     let model = app_response.result.unwrap();
-    let app_response = get_ai_source(db, &body.source_id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
-    }
-    let ai_source = app_response.result.unwrap();
-    let app_response = get_ai_model(db, &body.model_id).await;
-    if app_response.is_error() {
-        return AppResponse::error(None, &app_response.message);
-    }
-    let ai_model = app_response.result.unwrap();
+    let settings = match load_settings(db, &model.id).await {
+        Ok(settings) => settings,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let candidates = match get_candidates(db, &settings, &body.source_id, &body.model_id).await {
+        Ok(candidates) => candidates,
+        Err(message) => return AppResponse::error(None, &message),
+    };
     let file_path = &user_path.join(&model.wid).join(&model.id);
     if !file_path.exists() {
         return AppResponse::error(None, "chat not found in file system");
     }
-    // todo catch error
-    // 读取文件内容到字符串
-    let file_content = fs::read_to_string(file_path).unwrap();
-    // 将 JSON 字符串反序列化为结构体
-    let mut messages: Vec<Message> = serde_json::from_str(&file_content).unwrap();
-    if body.index < messages.len() {
-        messages.truncate(body.index);
-    }
-    // add user message
-    messages.push(Message {
-        role: User.to_string(),
-        content: body.prompt.clone(),
-    });
-    // add system message
-    messages.push(Message {
-        role: System.to_string(),
-        content: String::new(),
-    });
-    let callback_wrapper = |content: Option<String>, status: i8| {
-        let mut messages = messages.clone();
-        let len = messages.len() - 1;
-        match messages.get_mut(len) {
-            None => {}
-            Some(message) => {
-                if content.is_some() {
-                    message.content = format!("{}{}", &message.content, content.clone().unwrap());
-                } else {
-                    let json_str = serde_json::to_string(&messages).unwrap();
-                    fs::write(file_path, json_str).unwrap();
-                }
-                // invoke callback
-                callback(content, status);
-            }
+    let mut tree = match load_chat(file_path) {
+        Ok(tree) => tree,
+        Err(err) => {
+            error!("read chat {} failed, err: {}", model.id, err);
+            return AppResponse::error(None, "read chat failed");
         }
     };
-    // add system message
-    do_openai_request_stream(
-        callback_wrapper,
-        &messages,
-        &ai_source.url,
-        &ai_source.key,
-        &ai_model.name,
+    // the edited prompt is added next to the message at the index, which stays in its own branch
+    let branch_id = match branch_point(&tree, body.index, MessageRole::User) {
+        Ok(branch_id) => branch_id,
+        Err(message) => return AppResponse::error(None, message),
+    };
+    let prompt = Message::new(MessageRole::User, &body.prompt);
+    let parent_id = Some(tree.push(branch_id.as_deref(), prompt));
+    let request = match InFlightRequest::register(user_id, &body.request_id) {
+        Ok(request) => request,
+        Err(message) => return AppResponse::error(None, message),
+    };
+    let history = with_system_prompt(&settings, &tree.active_messages());
+    let reply = stream_reply(
+        &callback,
+        &request,
+        retry_settings,
+        &candidates,
+        &history,
+        &settings.options,
     )
     .await;
-    // return
-    AppResponse::success(Some(Response {
-        id: body.id.clone(),
-        index: body.index.clone(),
-        text: Some(String::new()),
-        error: None,
-    }))
-}
-
-async fn do_openai_request_stream<F>(
-    mut callback: F,
-    messages: &Vec<Message>,
-    url: &str,
-    key: &str,
-    model: &str,
-) where
-    F: FnMut(Option<String>, i8),
-{
-    // new openai client// This secret could be from a file, or environment variable.
-    let config = OpenAIConfig::new().with_api_base(url).with_api_key(key);
-    let client = Client::with_config(config);
-    // todo convert message to chat message
-    let mut request_messages = vec![];
-    for msg in messages.iter() {
-        request_messages.push(
-            ChatCompletionRequestUserMessageArgs::default()
-                .name(msg.role.clone())
-                .content(msg.content.clone())
-                .build()
-                .unwrap()
-                .into(),
-        )
-    }
-    let request = CreateChatCompletionRequestArgs::default()
-        .model(model.to_string())
-        .messages(request_messages)
-        .build()
-        .unwrap();
-    debug!("request {:?}", request);
-    match client.chat().create_stream(request).await {
-        Ok(mut stream) => {
-            while let Some(result) = stream.next().await {
-                match result {
-                    Ok(response) => {
-                        response.choices.iter().for_each(|chat_choice| {
-                            if let Some(ref content) = chat_choice.delta.content {
-                                debug!("stream body: {:?}", content);
-                                callback(Some(content.to_string()), 0);
-                            }
-                        });
-                    }
-                    Err(err) => {
-                        error!("stream err: {:?}", err);
-                        callback(None, -1);
-                    }
-                }
-            }
-            callback(None, 1)
-        }
-        Err(err) => {
-            error!("stream err: {:?}", err);
-            callback(None, 1)
-        }
-    }
-}
-
-async fn do_openai_request(messages: &Vec<Message>, url: &str, key: &str, model: &str) -> String {
-    // new openai client// This secret could be from a file, or environment variable.
-    let config = OpenAIConfig::new().with_api_base(url).with_api_key(key);
-    let client = Client::with_config(config);
-    // todo convert message to chat message
-    let mut request_messages = vec![];
-    for msg in messages.iter() {
-        request_messages.push(
-            ChatCompletionRequestUserMessageArgs::default()
-                .name(msg.role.clone())
-                .content(msg.content.clone())
-                .build()
-                .unwrap()
-                .into(),
-        )
-    }
-    let request = CreateChatCompletionRequestArgs::default()
-        .model(model.to_string())
-        .messages(request_messages)
-        .build()
-        .unwrap();
-    debug!("request {:?}", request);
-    let result = client.chat().create(request).await;
-    if result.is_err() {
-        error!("send chat request error: {}", result.err().unwrap());
-        return "Sorry, System Error".to_string();
-    }
-    let response = result.unwrap();
-    debug!("response {:?}", response);
-    let mut text = String::new();
-    if !response.choices.is_empty() {
-        let choice = response.choices[0].clone();
-        text = choice.message.content.unwrap_or_default();
+    tree.push(parent_id.as_deref(), reply.clone());
+    if let Err(err) = save_chat(file_path, &tree) {
+        error!("save chat {} failed, err: {}", model.id, err);
+        return AppResponse::error(None, "save chat failed");
     }
-    text
+    index_file(db, user_path, &model).await;
+    // return
+    reply_response(&body.id, tree.active_path().len() - 1, &reply)
 }
 
 pub async fn model_list() -> AppResponse<Option<ModelData>> {
@@ -697,16 +1353,89 @@ pub async fn model_list() -> AppResponse<Option<ModelData>> {
     AppResponse::success(Some(model_data))
 }
 
+/// Store the chats written before branches existed as a single branch and relabel the
+/// replies of the ones written when they were stored with the system role, runs once.
+pub async fn migrate_chat_files(
+    db: &DatabaseConnection,
+    user_path: &Path,
+) -> Result<(), anyhow::Error> {
+    let format = SettingService::get_setting_by_key(db, CHAT_FORMAT_SETTING_KEY).await?;
+    if format
+        .as_ref()
+        .is_some_and(|model| model.value == CHAT_FORMAT_VERSION.as_bytes())
+    {
+        return Ok(());
+    }
+    // the format is only recorded since replies are stored with the assistant role
+    let system_replies = format.is_none();
+    let models = FileService::list_all_files(db).await?;
+    let mut count = 0;
+    for model in models.iter().filter(|model| model.zone == CHAT_ZONE) {
+        let file_path = user_path.join(&model.wid).join(&model.id);
+        let file_content = match fs::read_to_string(&file_path) {
+            Ok(file_content) if !file_content.is_empty() => file_content,
+            _ => continue,
+        };
+        let mut tree = match parse_chat(&file_content) {
+            Ok(tree) => tree,
+            Err(err) => {
+                error!("parse chat {} failed, err: {}", model.id, err);
+                continue;
+            }
+        };
+        // only prompts and replies were stored, so every system message is a reply
+        if system_replies {
+            for node in tree.nodes.iter_mut() {
+                if node.message.role == MessageRole::System {
+                    node.message.role = MessageRole::Assistant;
+                }
+            }
+        }
+        save_chat(&file_path, &tree)?;
+        count += 1;
+    }
+    save_setting(db, CHAT_FORMAT_SETTING_KEY, CHAT_FORMAT_VERSION).await?;
+    info!("migrate chat files success, count: {}", count);
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
+    use crate::dto::ai_model::{CreateBody as ModelCreateBody, EnableBody as ModelEnableBody};
+    use crate::dto::ai_source::CreateBody as SourceCreateBody;
+    use crate::dto::chat::ChunkPayload;
     use crate::entity;
-    use crate::service::ai_chat_service::{create, message_request, CreateBody, RequestBody};
+    use crate::entity::ai_model::Model as AiModel;
+    use crate::migration::migrate;
+    use crate::service::ai_chat_service::{
+        branch_point, chat_reply, get_candidates, get_fallbacks, message_cancel, reply_response, retry_delay,
+        stream_reply, update_fallbacks, with_system_prompt, CancelBody, Candidate,
+        InFlightRequest, Message, MessageStatus, ModelRef, UpdateFallbacksBody,
+    };
+    use crate::service::ai_chat_service::{
+        create, delete, get_settings, message_list, message_request, migrate_chat_files,
+        update_settings, ChatSettings, CreateBody, MessageRole, RequestBody, UpdateSettingsBody,
+    };
+    use crate::service::ai_model_service::{create as create_model, enable as enable_model};
+    use crate::service::ai_source_service::create as create_source;
+    use crate::service::chat_provider_service::{
+        ChatError, ChatProvider, ChunkCallback, GenerationOptions,
+    };
+    use crate::service::chat_tree_service::ChatTree;
+    use crate::service::user_service::{register, RegisterBody};
     use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+    use crate::{RetrySettings, DEFAULT_USERNAME};
+    use futures::future::{pending, BoxFuture};
     use sea_orm::{ConnectionTrait, Schema};
     use std::env::temp_dir;
     use std::fs;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
     use uuid::Uuid;
 
+    use crate::service::workspace_service::create_workspace;
+
     #[tokio::test] //由此判断这是一个测试函数
     async fn test_chat_request() {
         let workspace = "test";
@@ -729,10 +1458,20 @@ mod test {
         db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::AiModel)))
             .await
             .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Workspace)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::ChatSetting)))
+            .await
+            .unwrap();
 
         // todo new ai source & ai model
         let user_id = Uuid::new_v4().to_string();
-        let ws_id = Uuid::new_v4().to_string();
+        let ws_id = create_workspace(db, &user_id, workspace)
+            .await
+            .result
+            .unwrap()
+            .id;
         let ws_path = &user_path.join(&ws_id);
         if !ws_path.exists() {
             fs::create_dir_all(ws_path).unwrap();
@@ -759,12 +1498,15 @@ mod test {
             db,
             user_path,
             &user_id,
+            &RetrySettings::default(),
             &RequestBody {
                 id: chat_id.to_string(),
                 prompt: "who is blackstar".to_string(),
                 model_id: "llama3.1:8b".to_string(),
                 source_id: "abc".to_string(),
                 request_id: "1".to_string(),
+                template_id: None,
+                variables: Default::default(),
             },
         )
         .await;
@@ -777,12 +1519,15 @@ mod test {
             db,
             user_path,
             &user_id,
+            &RetrySettings::default(),
             &RequestBody {
                 id: chat_id.to_string(),
                 prompt: "oh, no".to_string(),
                 model_id: "llama3.1:8b".to_string(),
                 source_id: "abc".to_string(),
                 request_id: "2".to_string(),
+                template_id: None,
+                variables: Default::default(),
             },
         )
         .await;
@@ -791,4 +1536,539 @@ mod test {
         }
         println!("{:?}", result.result.unwrap());
     }
+
+    #[tokio::test]
+    async fn test_migrate_chat_files() {
+        let temp_dir = temp_dir();
+        let user_path = &temp_dir.join(".fatherbox").join("test-chat-format");
+        let file_path = &temp_dir.join(".fatherbox").join("test-chat-format.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let user_id = Uuid::new_v4().to_string();
+        let ws_id = create_workspace(db, &user_id, "test")
+            .await
+            .result
+            .unwrap()
+            .id;
+        fs::create_dir_all(user_path.join(&ws_id)).unwrap();
+        let chat_id = create(
+            db,
+            &user_id,
+            &CreateBody {
+                name: "chat1".to_string(),
+                wid: ws_id.clone(),
+            },
+        )
+        .await
+        .result
+        .unwrap()
+        .id;
+        // replies used to be stored with the system role
+        fs::write(
+            user_path.join(&ws_id).join(&chat_id),
+            r#"[{"role":"user","content":"hi"},{"role":"system","content":"hello"}]"#,
+        )
+        .unwrap();
+        migrate_chat_files(db, user_path).await.unwrap();
+        let messages = message_list(db, user_path, &user_id, &chat_id)
+            .await
+            .result
+            .unwrap();
+        assert_eq!(MessageRole::User, messages[0].node.message.role);
+        assert_eq!(MessageRole::Assistant, messages[1].node.message.role);
+        assert_eq!(
+            Some(&messages[0].node.id),
+            messages[1].node.parent_id.as_ref()
+        );
+        assert_eq!(1, messages[1].sibling_count);
+        // stored as a tree
+        let file_content = fs::read_to_string(user_path.join(&ws_id).join(&chat_id)).unwrap();
+        assert!(file_content.starts_with(r#"{"nodes":"#));
+        // runs once, later system messages are kept
+        fs::write(
+            user_path.join(&ws_id).join(&chat_id),
+            r#"[{"role":"system","content":"be brief"}]"#,
+        )
+        .unwrap();
+        migrate_chat_files(db, user_path).await.unwrap();
+        let messages = message_list(db, user_path, &user_id, &chat_id)
+            .await
+            .result
+            .unwrap();
+        assert_eq!(MessageRole::System, messages[0].node.message.role);
+    }
+
+    #[tokio::test]
+    async fn test_chat_settings() {
+        let temp_dir = temp_dir();
+        let file_path = &temp_dir
+            .join(".fatherbox")
+            .join("test-chat-settings.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let user_id = Uuid::new_v4().to_string();
+        let ws_id = create_workspace(db, &user_id, "test")
+            .await
+            .result
+            .unwrap()
+            .id;
+        let chat_id = create(
+            db,
+            &user_id,
+            &CreateBody {
+                name: "chat1".to_string(),
+                wid: ws_id.clone(),
+            },
+        )
+        .await
+        .result
+        .unwrap()
+        .id;
+        // 1. defaults until the chat is configured
+        let settings = get_settings(db, &user_id, &chat_id).await.result.unwrap();
+        assert_eq!(ChatSettings::default(), settings);
+        // 2. update and read back
+        let settings = ChatSettings {
+            system_prompt: Some("answer in one sentence".to_string()),
+            options: GenerationOptions {
+                temperature: Some(0.2),
+                top_p: Some(0.9),
+                max_tokens: Some(512),
+                stop: vec!["END".to_string()],
+                seed: Some(42),
+            },
+            fallbacks: vec![ModelRef {
+                source_id: "source".to_string(),
+                model_id: "model".to_string(),
+            }],
+        };
+        let body = UpdateSettingsBody {
+            id: chat_id.clone(),
+            settings: settings.clone(),
+        };
+        let result = update_settings(db, &user_id, &body).await;
+        if result.is_error() {
+            panic!("{:?}", result.message);
+        }
+        assert_eq!(
+            settings,
+            get_settings(db, &user_id, &chat_id).await.result.unwrap()
+        );
+        // update again replaces the record
+        let mut body = body.clone();
+        body.settings.options.seed = None;
+        assert!(update_settings(db, &user_id, &body).await.is_success());
+        let result = get_settings(db, &user_id, &chat_id).await.result.unwrap();
+        assert_eq!(None, result.options.seed);
+        // 3. out of range parameters are rejected
+        body.settings.options.temperature = Some(3.0);
+        assert!(update_settings(db, &user_id, &body).await.is_error());
+        body.settings.options.temperature = None;
+        body.settings.fallbacks[0].model_id = "".to_string();
+        assert!(update_settings(db, &user_id, &body).await.is_error());
+        // 4. other users can not read them
+        let other_id = Uuid::new_v4().to_string();
+        assert!(!get_settings(db, &other_id, &chat_id).await.is_success());
+        // 5. removed with the chat
+        assert!(delete(db, &user_id, &chat_id).await.is_success());
+        let model = crate::dao::chat_setting_dao::ChatSettingService::get_setting(db, &chat_id)
+            .await
+            .unwrap();
+        assert!(model.is_none());
+    }
+
+    #[test]
+    fn test_history() {
+        let settings = ChatSettings {
+            system_prompt: Some("be brief".to_string()),
+            ..Default::default()
+        };
+        let mut failed = Message::new(MessageRole::Assistant, "partial");
+        failed.status = Some(MessageStatus::Failed);
+        let mut cancelled = Message::new(MessageRole::Assistant, "stopped");
+        cancelled.status = Some(MessageStatus::Cancelled);
+        let messages = vec![
+            Message::new(MessageRole::User, "first"),
+            failed,
+            Message::new(MessageRole::User, "second"),
+            Message::new(MessageRole::Assistant, ""),
+            Message::new(MessageRole::User, "third"),
+            cancelled.clone(),
+            Message::new(MessageRole::User, "fourth"),
+        ];
+        // failed and empty replies are left out, the system prompt leads
+        let history = with_system_prompt(&settings, &messages);
+        assert_eq!(
+            vec![
+                Message::new(MessageRole::System, "be brief"),
+                Message::new(MessageRole::User, "first"),
+                Message::new(MessageRole::User, "second"),
+                Message::new(MessageRole::User, "third"),
+                cancelled,
+                Message::new(MessageRole::User, "fourth"),
+            ],
+            history
+        );
+    }
+
+    #[test]
+    fn test_branch_point() {
+        let tree = ChatTree::from_messages(vec![
+            Message::new(MessageRole::User, "hi"),
+            Message::new(MessageRole::Assistant, "hello"),
+        ]);
+        let prompt_id = Some(tree.nodes[0].id.clone());
+        // a reply is regenerated and a prompt edited next to the message at the index
+        assert_eq!(Ok(prompt_id), branch_point(&tree, 1, MessageRole::Assistant));
+        assert_eq!(Ok(None), branch_point(&tree, 0, MessageRole::User));
+        // a stale index or one of another role adds nothing
+        assert!(branch_point(&tree, 2, MessageRole::Assistant).is_err());
+        assert!(branch_point(&tree, 0, MessageRole::Assistant).is_err());
+        assert!(branch_point(&tree, 1, MessageRole::User).is_err());
+    }
+
+    // sends one chunk then waits for more forever, or fails with the error when given
+    struct PartialProvider(Option<ChatError>);
+
+    impl ChatProvider for PartialProvider {
+        fn chat<'a>(
+            &'a self,
+            _messages: &'a [Message],
+            _model: &'a str,
+            _options: &'a GenerationOptions,
+        ) -> BoxFuture<'a, Result<String, ChatError>> {
+            Box::pin(pending())
+        }
+
+        fn chat_stream<'a>(
+            &'a self,
+            callback: &'a mut ChunkCallback<'_>,
+            _messages: &'a [Message],
+            _model: &'a str,
+            _options: &'a GenerationOptions,
+        ) -> BoxFuture<'a, Result<(), ChatError>> {
+            Box::pin(async move {
+                callback("partial".to_string());
+                match &self.0 {
+                    Some(err) => Err(err.clone()),
+                    None => pending().await,
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_cancel() {
+        let body = CancelBody {
+            request_id: Uuid::new_v4().to_string(),
+        };
+        let request = InFlightRequest::register("user", &body.request_id).unwrap();
+        assert!(InFlightRequest::register("user", &body.request_id).is_err());
+        let statuses = Mutex::new(vec![]);
+        let callback = |payload: ChunkPayload| {
+            statuses.lock().unwrap().push(payload.status);
+        };
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            // only the user who started the request may stop it
+            assert!(!message_cancel("other", &body).result);
+            assert!(message_cancel("user", &body).result);
+        };
+        let options = GenerationOptions::default();
+        let candidates = [candidate("model", PartialProvider(None))];
+        let retry_settings = RetrySettings::default();
+        let (reply, _) = tokio::join!(
+            stream_reply(
+                &callback,
+                &request,
+                &retry_settings,
+                &candidates,
+                &[],
+                &options
+            ),
+            cancel
+        );
+        assert_eq!(Some(MessageStatus::Cancelled), reply.status);
+        assert_eq!("partial", reply.content);
+        assert_eq!(vec![0, 2], *statuses.lock().unwrap());
+        // finished requests are forgotten
+        drop(request);
+        assert!(!message_cancel("user", &body).result);
+    }
+
+    #[tokio::test]
+    async fn test_message_error() {
+        let request = InFlightRequest::register("user", &Uuid::new_v4().to_string()).unwrap();
+        let payloads = Mutex::new(vec![]);
+        let callback = |payload: ChunkPayload| {
+            payloads.lock().unwrap().push(payload);
+        };
+        let err = ChatError::RateLimit {
+            message: "slow down".to_string(),
+        };
+        // the fallback is not asked once text was sent
+        let candidates = [
+            candidate("model", PartialProvider(Some(err.clone()))),
+            candidate("fallback", ReplyProvider),
+        ];
+        let retry_settings = RetrySettings::default();
+        let options = GenerationOptions::default();
+        let reply = stream_reply(
+            &callback,
+            &request,
+            &retry_settings,
+            &candidates,
+            &[],
+            &options,
+        )
+        .await;
+        // the partial reply is kept with the error
+        assert_eq!("partial", reply.content);
+        assert_eq!(Some("model".to_string()), reply.model_id);
+        assert_eq!(Some(MessageStatus::Failed), reply.status);
+        assert_eq!(Some(err.clone()), reply.error);
+        let payloads = payloads.lock().unwrap();
+        assert_eq!(2, payloads.len());
+        assert_eq!(-1, payloads[1].status);
+        assert_eq!(Some(err.clone()), payloads[1].error);
+        // stored with the message
+        let json = serde_json::to_string(&reply).unwrap();
+        let message: Message = serde_json::from_str(&json).unwrap();
+        assert_eq!(reply, message);
+        // and surfaced in the response
+        let response = reply_response("chat", 1, &reply);
+        assert!(response.is_error());
+        assert_eq!("rateLimit", response.r#type);
+        assert_eq!(Some(err), response.result.unwrap().error);
+    }
+
+    // streams a whole reply
+    struct ReplyProvider;
+
+    impl ChatProvider for ReplyProvider {
+        fn chat<'a>(
+            &'a self,
+            _messages: &'a [Message],
+            _model: &'a str,
+            _options: &'a GenerationOptions,
+        ) -> BoxFuture<'a, Result<String, ChatError>> {
+            Box::pin(async { Ok("answer".to_string()) })
+        }
+
+        fn chat_stream<'a>(
+            &'a self,
+            callback: &'a mut ChunkCallback<'_>,
+            _messages: &'a [Message],
+            _model: &'a str,
+            _options: &'a GenerationOptions,
+        ) -> BoxFuture<'a, Result<(), ChatError>> {
+            Box::pin(async move {
+                callback("answer".to_string());
+                Ok(())
+            })
+        }
+    }
+
+    // fails every request before any text, counting them
+    struct FailingProvider(ChatError, &'static AtomicU32);
+
+    impl ChatProvider for FailingProvider {
+        fn chat<'a>(
+            &'a self,
+            _messages: &'a [Message],
+            _model: &'a str,
+            _options: &'a GenerationOptions,
+        ) -> BoxFuture<'a, Result<String, ChatError>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async { Err(self.0.clone()) })
+        }
+
+        fn chat_stream<'a>(
+            &'a self,
+            _callback: &'a mut ChunkCallback<'_>,
+            _messages: &'a [Message],
+            _model: &'a str,
+            _options: &'a GenerationOptions,
+        ) -> BoxFuture<'a, Result<(), ChatError>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async { Err(self.0.clone()) })
+        }
+    }
+
+    fn candidate(model_id: &str, provider: impl ChatProvider + 'static) -> Candidate {
+        Candidate {
+            source_id: "source".to_string(),
+            model: AiModel {
+                id: model_id.to_string(),
+                name: model_id.to_string(),
+                source_id: "source".to_string(),
+                enable: true,
+                create_time: 0,
+                update_time: 0,
+                state: 1,
+            },
+            provider: Box::new(provider),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_fallback() {
+        let retry_settings = RetrySettings {
+            max_retries: 2,
+            initial_delay_ms: 1,
+            max_delay_ms: 2,
+        };
+        let options = GenerationOptions::default();
+        // 1. transient errors are retried before the fallback answers
+        static RATE_LIMITED: AtomicU32 = AtomicU32::new(0);
+        let rate_limit = ChatError::RateLimit {
+            message: "slow down".to_string(),
+        };
+        let candidates = [
+            candidate("model", FailingProvider(rate_limit.clone(), &RATE_LIMITED)),
+            candidate("fallback", ReplyProvider),
+        ];
+        let request = InFlightRequest::register("user", &Uuid::new_v4().to_string()).unwrap();
+        let statuses = Mutex::new(vec![]);
+        let callback = |payload: ChunkPayload| {
+            statuses.lock().unwrap().push(payload.status);
+        };
+        let reply = stream_reply(
+            &callback,
+            &request,
+            &retry_settings,
+            &candidates,
+            &[],
+            &options,
+        )
+        .await;
+        assert_eq!("answer", reply.content);
+        assert_eq!(None, reply.status);
+        assert_eq!(Some("fallback".to_string()), reply.model_id);
+        assert_eq!(3, RATE_LIMITED.load(Ordering::SeqCst));
+        // failed attempts are not sent to the callback
+        assert_eq!(vec![0, 1], *statuses.lock().unwrap());
+        let reply = chat_reply(&retry_settings, &candidates, &[], &options).await;
+        assert_eq!(Some("fallback".to_string()), reply.model_id);
+        assert_eq!(6, RATE_LIMITED.load(Ordering::SeqCst));
+        // 2. other errors move to the fallback at once
+        static UNAUTHORIZED: AtomicU32 = AtomicU32::new(0);
+        let auth = ChatError::Auth {
+            message: "invalid key".to_string(),
+        };
+        let candidates = [
+            candidate("model", FailingProvider(auth.clone(), &UNAUTHORIZED)),
+            candidate("fallback", ReplyProvider),
+        ];
+        let reply = chat_reply(&retry_settings, &candidates, &[], &options).await;
+        assert_eq!("answer", reply.content);
+        assert_eq!(1, UNAUTHORIZED.load(Ordering::SeqCst));
+        // 3. the error of the last model is kept when all fail
+        let reply = chat_reply(&retry_settings, &candidates[..1], &[], &options).await;
+        assert_eq!(Some(MessageStatus::Failed), reply.status);
+        assert_eq!(Some(auth), reply.error);
+        assert_eq!(Some("model".to_string()), reply.model_id);
+        // 4. backoff doubles up to the max
+        let retry_settings = RetrySettings::default();
+        let delays: Vec<u128> = (0..6)
+            .map(|attempt| retry_delay(&retry_settings, attempt).as_millis())
+            .collect();
+        assert_eq!(vec![500, 1000, 2000, 4000, 8000, 8000], delays);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_candidates() {
+        let temp_dir = temp_dir();
+        let file_path = &temp_dir
+            .join(".fatherbox")
+            .join("test-chat-fallbacks.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(file_path).unwrap();
+        }
+        let db = &init_connection(file_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let source_id = create_source(
+            db,
+            &SourceCreateBody {
+                name: "local".to_string(),
+                key: "".to_string(),
+                url: "http://localhost:11434".to_string(),
+                provider: Some("ollama".to_string()),
+            },
+        )
+        .await
+        .result
+        .unwrap()
+        .id;
+        let mut model_ids = vec![];
+        for name in ["llama3.1:8b", "qwen2.5:7b", "mistral:7b"] {
+            let body = ModelCreateBody {
+                name: name.to_string(),
+                source_id: source_id.clone(),
+            };
+            model_ids.push(create_model(db, &body).await.result.unwrap().id);
+        }
+        let model_ref = |model_id: &String| ModelRef {
+            source_id: source_id.clone(),
+            model_id: model_id.clone(),
+        };
+        let register_body = |username: &str| RegisterBody {
+            username: username.to_string(),
+            password: "password".to_string(),
+            nickname: username.to_string(),
+        };
+        let admin = &register(db, &register_body(DEFAULT_USERNAME))
+            .await
+            .result
+            .unwrap()
+            .id;
+        let other = &register(db, &register_body("other"))
+            .await
+            .result
+            .unwrap()
+            .id;
+        // 1. no fallbacks
+        assert!(get_fallbacks(db).await.result.is_empty());
+        let mut settings = ChatSettings::default();
+        let candidates = get_candidates(db, &settings, &source_id, &model_ids[0])
+            .await
+            .unwrap();
+        assert_eq!(1, candidates.len());
+        // 2. global fallbacks, the requested model and disabled ones are skipped
+        let body = UpdateFallbacksBody {
+            fallbacks: model_ids.iter().map(model_ref).collect(),
+        };
+        assert!(update_fallbacks(db, other, &body).await.is_forbidden());
+        assert!(get_fallbacks(db).await.result.is_empty());
+        assert!(update_fallbacks(db, admin, &body).await.is_success());
+        assert_eq!(body.fallbacks, get_fallbacks(db).await.result);
+        let body = ModelEnableBody {
+            id: model_ids[2].clone(),
+            enable: false,
+        };
+        assert!(enable_model(db, &body).await.is_success());
+        let candidates = get_candidates(db, &settings, &source_id, &model_ids[0])
+            .await
+            .unwrap();
+        let ids: Vec<&str> = candidates.iter().map(|c| c.model.id.as_str()).collect();
+        assert_eq!(vec![model_ids[0].as_str(), model_ids[1].as_str()], ids);
+        // 3. fallbacks of the chat replace the global ones
+        settings.fallbacks = vec![model_ref(&model_ids[0])];
+        let candidates = get_candidates(db, &settings, &source_id, &model_ids[1])
+            .await
+            .unwrap();
+        let ids: Vec<&str> = candidates.iter().map(|c| c.model.id.as_str()).collect();
+        assert_eq!(vec![model_ids[1].as_str(), model_ids[0].as_str()], ids);
+        // 4. too many fallbacks are rejected
+        let body = UpdateFallbacksBody {
+            fallbacks: vec![model_ref(&model_ids[0]); 9],
+        };
+        assert!(update_fallbacks(db, admin, &body).await.is_error());
+    }
 }
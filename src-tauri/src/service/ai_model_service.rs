@@ -1,11 +1,15 @@
 use chrono::Utc;
+use reqwest::header::CONTENT_TYPE;
 use sea_orm::ActiveValue::Set;
 use sea_orm::DatabaseConnection;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 use crate::dao::ai_model_dao::AiModelService;
-use crate::dto::ai_model::{CreateBody, EnableBody, UpdateBody};
+use crate::dao::ai_source_dao::AiConnectionService;
+use crate::dto::ai_model::{CreateBody, EnableBody, PullBody, UpdateBody};
 use crate::entity::ai_model::{ActiveModel, Model};
+use crate::service::chat_provider_service::{check_status, ProviderType};
 use crate::AppResponse;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
@@ -14,6 +18,14 @@ pub struct GeneralBody {
     pub key: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PullProgress {
+    pub status: String,
+    pub completed: Option<u64>,
+    pub total: Option<u64>,
+}
+
 pub async fn create(db: &DatabaseConnection, body: &CreateBody) -> AppResponse<Option<Model>> {
     let active_model = ActiveModel {
         id: Set(uuid::Uuid::new_v4().to_string()),
@@ -84,11 +96,107 @@ pub async fn enable(db: &DatabaseConnection, body: &EnableBody) -> AppResponse<O
     }
 }
 
+/// Pull a model into an ollama source, report the download progress to the callback
+/// and add the model to the source once it is available.
+pub async fn pull<F>(
+    callback: F,
+    db: &DatabaseConnection,
+    body: &PullBody,
+) -> AppResponse<Option<Model>>
+where
+    F: Fn(PullProgress),
+{
+    let source = match AiConnectionService::get(db, &body.source_id).await {
+        Ok(Some(source)) => source,
+        Ok(None) => return AppResponse::error(None, "ai source not found"),
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    if ProviderType::parse(&source.provider) != Some(ProviderType::Ollama) {
+        return AppResponse::error(None, "only ollama sources can pull models");
+    }
+    if body.name.trim().is_empty() {
+        return AppResponse::error(None, "model name is empty");
+    }
+    let request = json!({ "model": body.name, "stream": true });
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/pull", source.url.trim_end_matches('/')))
+        .header(CONTENT_TYPE, "application/json")
+        .body(request.to_string())
+        .send()
+        .await
+        .map_err(|err| err.to_string());
+    let mut response = match response {
+        Ok(response) => match check_status(&body.name, response).await {
+            Ok(response) => response,
+            Err(err) => return AppResponse::error(None, &err.to_string()),
+        },
+        Err(err) => return AppResponse::error(None, &err),
+    };
+    let mut buffer: Vec<u8> = vec![];
+    loop {
+        let done = match response.chunk().await {
+            Ok(Some(chunk)) => {
+                buffer.extend_from_slice(&chunk);
+                false
+            }
+            Ok(None) => {
+                buffer.push(b'\n');
+                true
+            }
+            Err(err) => return AppResponse::error(None, &err.to_string()),
+        };
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_pull_line(line.trim()) {
+                Ok(progress) => callback(progress),
+                Err(err) => return AppResponse::error(None, &err),
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    // keep a single row per model name in the source
+    match AiModelService::list(db, &source.id).await {
+        Ok(models) => {
+            if let Some(model) = models.into_iter().find(|model| model.name == body.name) {
+                return AppResponse::success(Some(model));
+            }
+        }
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    }
+    create(
+        db,
+        &CreateBody {
+            name: body.name.clone(),
+            source_id: source.id,
+        },
+    )
+    .await
+}
+
+/// A line of the ollama pull stream, which carries either a progress or an error.
+fn parse_pull_line(line: &str) -> Result<PullProgress, String> {
+    let value: Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    if let Some(err) = value["error"].as_str() {
+        return Err(err.to_string());
+    }
+    Ok(PullProgress {
+        status: value["status"].as_str().unwrap_or_default().to_string(),
+        completed: value["completed"].as_u64(),
+        total: value["total"].as_u64(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use crate::dto::ai_model::{CreateBody, UpdateBody};
     use crate::dto::ai_source::CreateBody as ConnectionCreateBody;
-    use crate::service::ai_model_service::{create, delete, get, update};
+    use crate::service::ai_model_service::{create, delete, get, parse_pull_line, update};
     use crate::service::ai_source_service::{create as create_connection, delete as delete_connection};
     use crate::util::db_util::init_test_database;
 
@@ -112,6 +220,7 @@ mod tests {
                 name: c_name.to_string(),
                 key: c_key.to_string(),
                 url: c_url.to_string(),
+                provider: None,
             },
         )
         .await
@@ -171,4 +280,20 @@ mod tests {
         // delete connection
         delete_connection(db, c_id).await;
     }
+
+    #[test]
+    fn test_parse_pull_line() {
+        let progress = parse_pull_line(
+            r#"{"status":"pulling 6a0746a1ec1a","digest":"sha256:6a07","total":4661211424,"completed":1048576}"#,
+        )
+        .unwrap();
+        assert_eq!("pulling 6a0746a1ec1a", progress.status);
+        assert_eq!(Some(1048576), progress.completed);
+        assert_eq!(Some(4661211424), progress.total);
+        let progress = parse_pull_line(r#"{"status":"success"}"#).unwrap();
+        assert_eq!("success", progress.status);
+        assert_eq!(None, progress.total);
+        let err = parse_pull_line(r#"{"error":"pull model manifest: file does not exist"}"#);
+        assert_eq!(Err("pull model manifest: file does not exist".to_string()), err);
+    }
 }
@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use crate::dao::ai_source_dao::AiConnectionService;
 use crate::dto::ai_source::{CreateBody, EnableBody, UpdateBody};
 use crate::entity::ai_source::{ActiveModel, Model};
+use crate::service::chat_provider_service::ProviderType;
 use crate::{AppResponse, BUILD_IN_CONNECTION_NAMES};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
@@ -26,12 +27,20 @@ pub async fn create(
     if is_build_in(&body.name) {
         return AppResponse::error(None, "build-in connection cannot be create");
     }
+    let provider = match &body.provider {
+        None => ProviderType::OpenAi,
+        Some(provider) => match ProviderType::parse(provider) {
+            Some(provider) => provider,
+            None => return AppResponse::error(None, "unknown provider type"),
+        },
+    };
     let active_model = ActiveModel {
         id: Set(uuid::Uuid::new_v4().to_string()),
         name: Set(body.name.clone()),
         build_in: Set(false),
         url: Set(body.url.clone()),
         key: Set(body.key.clone()),
+        provider: Set(provider.as_str().to_string()),
         enable: Set(true),
         sync: Set(false),
         create_time: Set(Utc::now().timestamp()),
@@ -97,6 +106,12 @@ pub async fn update(
         active_model.name = Set(body.name.clone());
         active_model.url = Set(body.url.clone());
     }
+    if let Some(provider) = &body.provider {
+        match ProviderType::parse(provider) {
+            Some(provider) => active_model.provider = Set(provider.as_str().to_string()),
+            None => return AppResponse::error(None, "unknown provider type"),
+        }
+    }
     match AiConnectionService::update(db, active_model).await {
         Ok(model) => AppResponse::success(Some(model)),
         Err(err) => AppResponse::error(None, &err.to_string()),
@@ -149,6 +164,7 @@ mod tests {
                 name: name.to_string(),
                 key: key.to_string(),
                 url: url.to_string(),
+                provider: None,
             },
         )
         .await;
@@ -160,6 +176,7 @@ mod tests {
         assert_eq!(name, model.name);
         assert_eq!(key, model.key);
         assert_eq!(url, model.url);
+        assert_eq!("openai", model.provider);
         // 2. test get
         let result = get(&db, id).await;
         if result.is_error() {
@@ -181,6 +198,7 @@ mod tests {
                 name: new_name.to_string(),
                 key: new_key.to_string(),
                 url: new_url.to_string(),
+                provider: Some("anthropic".to_string()),
             },
         )
         .await;
@@ -191,6 +209,7 @@ mod tests {
         assert_eq!(new_name, model.name);
         assert_eq!(new_key, model.key);
         assert_eq!(new_url, model.url);
+        assert_eq!("anthropic", model.provider);
         // 4. test delete
         let result = delete(&db, id).await;
         if result.is_error() {
@@ -229,6 +248,7 @@ mod tests {
                 name: name.to_string(),
                 key: key.to_string(),
                 url: url.to_string(),
+                provider: None,
             },
         )
             .await;
@@ -0,0 +1,498 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+use log::{error, info};
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::migration::check_known;
+use crate::service::permission_service::check_admin;
+use crate::util::db_util::init_connection;
+use crate::util::fs_util::{remove_path, write_atomic_with};
+use crate::{
+    AppResponse, BackupSettings, BACKUP_PATH, DATA_DB_NAME, DATA_PATH, FILE_PATH, RESTORE_PATH,
+};
+
+// archive layout: the manifest, data/data.db and the files tree as under the root path
+const MANIFEST_NAME: &str = "backup.json";
+const BACKUP_VERSION: u32 = 1;
+const BACKUP_PREFIX: &str = "fatherbox-";
+const BACKUP_SUFFIX: &str = ".zip";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+// written into the restore dir once it is validated
+const RESTORE_READY_NAME: &str = ".ready";
+// name of the dir the replaced data is moved to, kept in the restore dir so a restore
+// interrupted half way is resumed with the same dir on next start
+const RESTORE_ASIDE_NAME: &str = ".aside";
+const RESTORE_NAMES: [&str; 2] = [DATA_PATH, FILE_PATH];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreBody {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupInfo {
+    pub name: String,
+    pub size: u64,
+    pub create_time: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    version: u32,
+    create_time: i64,
+}
+
+// backups of the same second differ by a suffix, e.g. fatherbox-20261018-120000-1a2b3c4d.zip
+fn backup_time(name: &str) -> Option<i64> {
+    let stem = name
+        .strip_prefix(BACKUP_PREFIX)?
+        .strip_suffix(BACKUP_SUFFIX)?;
+    let time = stem.splitn(3, '-').take(2).collect::<Vec<_>>().join("-");
+    let time = NaiveDateTime::parse_from_str(&time, BACKUP_TIME_FORMAT).ok()?;
+    Some(Local.from_local_datetime(&time).single()?.timestamp())
+}
+
+// backups in the backup dir, newest first
+fn read_backups(root_path: &Path) -> io::Result<Vec<BackupInfo>> {
+    let backup_path = root_path.join(BACKUP_PATH);
+    if !backup_path.exists() {
+        return Ok(vec![]);
+    }
+    let mut backups = vec![];
+    for entry in fs::read_dir(backup_path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(create_time) = backup_time(&name) {
+            backups.push(BackupInfo {
+                name,
+                size: entry.metadata()?.len(),
+                create_time,
+            });
+        }
+    }
+    backups.sort_by(|a, b| b.create_time.cmp(&a.create_time));
+    Ok(backups)
+}
+
+// add the dir to the archive under prefix, temp files of unfinished writes are skipped
+fn add_dir<W: Write + io::Seek>(
+    zip: &mut ZipWriter<W>,
+    dir: &Path,
+    prefix: &str,
+) -> io::Result<()> {
+    let options = SimpleFileOptions::default();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = format!("{}/{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            zip.add_directory(&path, options)
+                .map_err(io::Error::other)?;
+            add_dir(zip, &entry.path(), &path)?;
+        } else if !(name.starts_with('.') && name.ends_with(".tmp")) {
+            zip.start_file(&path, options).map_err(io::Error::other)?;
+            io::copy(&mut File::open(entry.path())?, zip)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_backup(file: &mut File, root_path: &Path, snapshot: &Path) -> io::Result<()> {
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+    let manifest = Manifest {
+        version: BACKUP_VERSION,
+        create_time: Utc::now().timestamp(),
+    };
+    zip.start_file(MANIFEST_NAME, options)
+        .map_err(io::Error::other)?;
+    zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+    zip.start_file(format!("{}/{}", DATA_PATH, DATA_DB_NAME), options)
+        .map_err(io::Error::other)?;
+    io::copy(&mut File::open(snapshot)?, &mut zip)?;
+    zip.add_directory(FILE_PATH, options)
+        .map_err(io::Error::other)?;
+    add_dir(&mut zip, &root_path.join(FILE_PATH), FILE_PATH)?;
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+/// Archive a snapshot of the data db with the files tree into the backup dir, only the owner
+/// of the install may do so as the backup holds the data of every user.
+pub async fn create_backup(
+    db: &DatabaseConnection,
+    root_path: &PathBuf,
+    user_id: &str,
+) -> AppResponse<Option<BackupInfo>> {
+    if let Err(err) = check_admin(db, user_id).await {
+        return err.response(None);
+    }
+    take_backup(db, root_path).await
+}
+
+// the db is snapshot first, blobs never change once written so every blob it references is
+// still found when the tree is copied, unless it is purged in between
+async fn take_backup(
+    db: &DatabaseConnection,
+    root_path: &PathBuf,
+) -> AppResponse<Option<BackupInfo>> {
+    let backup_path = root_path.join(BACKUP_PATH);
+    if let Err(err) = fs::create_dir_all(&backup_path) {
+        return AppResponse::error(None, &err.to_string());
+    }
+    let snapshot = backup_path.join(format!(".{}.db.tmp", Uuid::new_v4()));
+    let result = db
+        .execute(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            "VACUUM INTO ?",
+            [snapshot.to_string_lossy().to_string().into()],
+        ))
+        .await;
+    if let Err(err) = result {
+        remove_path(&snapshot);
+        return AppResponse::error(None, &err.to_string());
+    }
+    // a manual backup may be taken in the same second as a scheduled one
+    let name = format!(
+        "{}{}-{}{}",
+        BACKUP_PREFIX,
+        Local::now().format(BACKUP_TIME_FORMAT),
+        &Uuid::new_v4().simple().to_string()[..8],
+        BACKUP_SUFFIX
+    );
+    let path = backup_path.join(&name);
+    let result = write_atomic_with(&path, |file| write_backup(file, root_path, &snapshot));
+    remove_path(&snapshot);
+    match result {
+        Ok(size) => {
+            info!("create backup success, path: {}", path.display());
+            AppResponse::success(Some(BackupInfo {
+                name: name.clone(),
+                size,
+                create_time: backup_time(&name).unwrap_or_default(),
+            }))
+        }
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+pub async fn list_backups(
+    db: &DatabaseConnection,
+    root_path: &PathBuf,
+    user_id: &str,
+) -> AppResponse<Vec<BackupInfo>> {
+    if let Err(err) = check_admin(db, user_id).await {
+        return err.response(vec![]);
+    }
+    match read_backups(root_path) {
+        Ok(backups) => AppResponse::success(backups),
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+/// Keep the newest max_backups backups, return how many were removed.
+pub fn prune_backups(root_path: &PathBuf, max_backups: usize) -> AppResponse<u64> {
+    let backups = match read_backups(root_path) {
+        Ok(backups) => backups,
+        Err(err) => return AppResponse::error(0, &err.to_string()),
+    };
+    let mut count = 0;
+    for backup in backups.iter().skip(max_backups) {
+        remove_path(&root_path.join(BACKUP_PATH).join(&backup.name));
+        count += 1;
+    }
+    AppResponse::success(count)
+}
+
+/// Take a backup when the newest one is older than the interval, then prune old ones.
+pub async fn run_scheduled_backup(
+    db: &DatabaseConnection,
+    root_path: &PathBuf,
+    settings: &BackupSettings,
+) -> AppResponse<Option<BackupInfo>> {
+    if settings.interval_hours <= 0 {
+        return AppResponse::success(None);
+    }
+    let newest = match read_backups(root_path) {
+        Ok(backups) => backups.first().map(|backup| backup.create_time),
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let due_time = Utc::now().timestamp() - settings.interval_hours * 60 * 60;
+    if newest.is_some_and(|newest| newest > due_time) {
+        return AppResponse::success(None);
+    }
+    let response = take_backup(db, root_path).await;
+    if response.is_success() {
+        let pruned = prune_backups(root_path, settings.max_backups);
+        if !pruned.is_success() {
+            error!("prune backups failed, err: {}", pruned.message);
+        }
+    }
+    response
+}
+
+// extract the archive into dir and check it holds a db this binary can open
+async fn stage_restore(archive_path: &Path, dir: &Path) -> Result<(), anyhow::Error> {
+    let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+    let mut content = String::new();
+    archive
+        .by_name(MANIFEST_NAME)?
+        .read_to_string(&mut content)?;
+    let manifest: Manifest = serde_json::from_str(&content)?;
+    if manifest.version > BACKUP_VERSION {
+        return Err(anyhow::anyhow!(
+            "backup version {} is newer than supported version {}",
+            manifest.version,
+            BACKUP_VERSION
+        ));
+    }
+    archive.extract(dir)?;
+    let db_path = dir.join(DATA_PATH).join(DATA_DB_NAME);
+    if !db_path.exists() || !dir.join(FILE_PATH).is_dir() {
+        return Err(anyhow::anyhow!("backup misses the data db or the files"));
+    }
+    let db = init_connection(&db_path).await?;
+    let result = db
+        .query_one(Statement::from_string(
+            db.get_database_backend(),
+            "PRAGMA integrity_check",
+        ))
+        .await?;
+    let integrity: String = match result {
+        Some(row) => row.try_get_by_index(0)?,
+        None => String::new(),
+    };
+    if integrity != "ok" {
+        return Err(anyhow::anyhow!("data db is corrupted, {}", integrity));
+    }
+    check_known(&db).await?;
+    db.close().await?;
+    Ok(())
+}
+
+/// Validate the backup and stage it, it replaces the current data on next start.
+pub async fn restore_backup(
+    db: &DatabaseConnection,
+    root_path: &PathBuf,
+    user_id: &str,
+    body: &RestoreBody,
+) -> AppResponse<String> {
+    if let Err(err) = check_admin(db, user_id).await {
+        return err.response("".to_string());
+    }
+    if body.name.contains(['/', '\\']) || backup_time(&body.name).is_none() {
+        return AppResponse::error("".to_string(), "invalid backup name");
+    }
+    let archive_path = root_path.join(BACKUP_PATH).join(&body.name);
+    if !archive_path.exists() {
+        return AppResponse::error("".to_string(), "backup not found");
+    }
+    let restore_path = root_path.join(RESTORE_PATH);
+    remove_path(&restore_path);
+    if let Err(err) = stage_restore(&archive_path, &restore_path).await {
+        remove_path(&restore_path);
+        return AppResponse::error("".to_string(), &format!("invalid backup, {}", err));
+    }
+    if let Err(err) = File::create(restore_path.join(RESTORE_READY_NAME)) {
+        remove_path(&restore_path);
+        return AppResponse::error("".to_string(), &err.to_string());
+    }
+    info!("backup {} is staged, restart to restore it", body.name);
+    AppResponse::success(body.name.clone())
+}
+
+/// Swap a staged restore in before the data db is opened, the replaced data is moved aside.
+pub fn apply_pending_restore(root_path: &Path) -> Result<bool, anyhow::Error> {
+    let restore_path = root_path.join(RESTORE_PATH);
+    if !restore_path.join(RESTORE_READY_NAME).exists() {
+        // an unfinished stage is never applied
+        remove_path(&restore_path);
+        return Ok(false);
+    }
+    let aside_name_path = restore_path.join(RESTORE_ASIDE_NAME);
+    let aside_name = match fs::read_to_string(&aside_name_path) {
+        Ok(aside_name) => aside_name,
+        Err(_) => {
+            let aside_name = format!(
+                "pre-restore-{}-{}",
+                Local::now().format(BACKUP_TIME_FORMAT),
+                &Uuid::new_v4().simple().to_string()[..8]
+            );
+            fs::write(&aside_name_path, &aside_name)?;
+            aside_name
+        }
+    };
+    let aside_path = root_path.join(aside_name);
+    fs::create_dir_all(&aside_path)?;
+    if let Err(err) = swap_restore(root_path, &restore_path, &aside_path) {
+        // put the replaced data back, the restore has to be staged again
+        rollback_restore(root_path, &restore_path, &aside_path)?;
+        let _ = fs::remove_dir(&aside_path);
+        remove_path(&restore_path);
+        return Err(err.into());
+    }
+    remove_path(&restore_path);
+    info!(
+        "restore backup success, replaced data is kept at {}",
+        aside_path.display()
+    );
+    Ok(true)
+}
+
+// names already moved out of the restore dir were swapped by an earlier start
+fn swap_restore(root_path: &Path, restore_path: &Path, aside_path: &Path) -> io::Result<()> {
+    for name in RESTORE_NAMES {
+        if !restore_path.join(name).exists() {
+            continue;
+        }
+        if root_path.join(name).exists() && !aside_path.join(name).exists() {
+            fs::rename(root_path.join(name), aside_path.join(name))?;
+        }
+        fs::rename(restore_path.join(name), root_path.join(name))?;
+    }
+    Ok(())
+}
+
+fn rollback_restore(root_path: &Path, restore_path: &Path, aside_path: &Path) -> io::Result<()> {
+    for name in RESTORE_NAMES {
+        if !restore_path.join(name).exists() && root_path.join(name).exists() {
+            fs::rename(root_path.join(name), restore_path.join(name))?;
+        }
+        if aside_path.join(name).exists() && !root_path.join(name).exists() {
+            fs::rename(aside_path.join(name), root_path.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+
+    use crate::migration::migrate;
+    use crate::service::backup_service::{
+        apply_pending_restore, create_backup, list_backups, prune_backups, restore_backup,
+        RestoreBody, RESTORE_ASIDE_NAME,
+    };
+    use crate::service::user_service::{register, RegisterBody};
+    use crate::service::workspace_service::{create_workspace, list_workspaces};
+    use crate::util::db_util::init_connection;
+    use crate::{DATA_DB_NAME, DATA_PATH, DEFAULT_USERNAME, FILE_PATH, RESTORE_PATH};
+
+    #[tokio::test]
+    async fn test_backup() {
+        let temp_dir = temp_dir();
+        let root_path = &temp_dir.join(".fatherbox").join("test-backup");
+        if root_path.exists() {
+            fs::remove_dir_all(root_path).unwrap();
+        }
+        let db_path = &root_path.join(DATA_PATH).join(DATA_DB_NAME);
+        let db = &init_connection(db_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let register_body = |username: &str| RegisterBody {
+            username: username.to_string(),
+            password: "password".to_string(),
+            nickname: username.to_string(),
+        };
+        let admin = &register(db, &register_body(DEFAULT_USERNAME))
+            .await
+            .result
+            .unwrap()
+            .id;
+        let other = &register(db, &register_body("other"))
+            .await
+            .result
+            .unwrap()
+            .id;
+        create_workspace(db, "test-user", "kept").await;
+        let blob_path = root_path.join(FILE_PATH).join("test-user").join("blob");
+        fs::create_dir_all(blob_path.parent().unwrap()).unwrap();
+        fs::write(&blob_path, b"kept").unwrap();
+        // 1. backup holds the db snapshot and the files
+        let backup = create_backup(db, root_path, admin).await.result.unwrap();
+        assert_eq!(1, list_backups(db, root_path, admin).await.result.len());
+        // a backup of the same second does not replace it
+        create_backup(db, root_path, admin).await.result.unwrap();
+        assert_eq!(2, list_backups(db, root_path, admin).await.result.len());
+        // 2. other users can not see, take or restore backups
+        assert!(create_backup(db, root_path, other).await.is_forbidden());
+        assert!(list_backups(db, root_path, other).await.is_forbidden());
+        let body = &RestoreBody {
+            name: backup.name.clone(),
+        };
+        assert!(restore_backup(db, root_path, other, body)
+            .await
+            .is_forbidden());
+        assert!(!root_path.join(RESTORE_PATH).exists());
+        // 3. later changes are undone by a restore on next start
+        create_workspace(db, "test-user", "lost").await;
+        fs::write(&blob_path, b"lost").unwrap();
+        let invalid_body = &RestoreBody {
+            name: "../data.db".to_string(),
+        };
+        assert!(!restore_backup(db, root_path, admin, invalid_body)
+            .await
+            .is_success());
+        assert!(restore_backup(db, root_path, admin, body)
+            .await
+            .is_success());
+        assert!(root_path.join(RESTORE_PATH).exists());
+        db.clone().close().await.unwrap();
+        // 4. a swap failing half way is rolled back and the current data is kept
+        let restore_path = &root_path.join(RESTORE_PATH);
+        let aside_path = &root_path.join("pre-restore-test");
+        fs::write(restore_path.join(RESTORE_ASIDE_NAME), "pre-restore-test").unwrap();
+        fs::create_dir_all(aside_path.join(FILE_PATH).join("blocker")).unwrap();
+        assert!(apply_pending_restore(root_path).is_err());
+        assert!(!restore_path.exists());
+        assert_eq!(b"lost".to_vec(), fs::read(&blob_path).unwrap());
+        fs::remove_dir_all(aside_path).unwrap();
+        // 5. a swap interrupted half way is resumed on next start
+        let db = &init_connection(db_path).await.unwrap();
+        assert_eq!(2, list_workspaces(db, "test-user").await.result.len());
+        assert!(restore_backup(db, root_path, admin, body)
+            .await
+            .is_success());
+        db.clone().close().await.unwrap();
+        fs::write(restore_path.join(RESTORE_ASIDE_NAME), "pre-restore-test").unwrap();
+        fs::create_dir_all(aside_path).unwrap();
+        fs::rename(root_path.join(DATA_PATH), aside_path.join(DATA_PATH)).unwrap();
+        fs::rename(restore_path.join(DATA_PATH), root_path.join(DATA_PATH)).unwrap();
+        assert!(apply_pending_restore(root_path).unwrap());
+        assert!(!root_path.join(RESTORE_PATH).exists());
+        assert!(!apply_pending_restore(root_path).unwrap());
+        let db = &init_connection(db_path).await.unwrap();
+        let workspaces = list_workspaces(db, "test-user").await.result;
+        assert_eq!(
+            vec!["kept"],
+            workspaces
+                .iter()
+                .map(|workspace| workspace.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(b"kept".to_vec(), fs::read(&blob_path).unwrap());
+        assert!(aside_path.join(DATA_PATH).join(DATA_DB_NAME).exists());
+        assert_eq!(
+            b"lost".to_vec(),
+            fs::read(aside_path.join(FILE_PATH).join("test-user").join("blob")).unwrap()
+        );
+        // 6. retention keeps the newest
+        assert_eq!(0, prune_backups(root_path, 2).result);
+        assert_eq!(1, prune_backups(root_path, 1).result);
+        assert_eq!(1, prune_backups(root_path, 0).result);
+        assert!(list_backups(db, root_path, admin).await.result.is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::error;
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbErr, TransactionTrait};
+use sha2::{Digest, Sha256};
+
+use crate::dao::blob_dao::BlobService;
+use crate::entity::file::Model;
+use crate::util::fs_util::{copy_atomic, remove_path, write_atomic};
+use crate::BLOB_PATH;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredBlob {
+    pub hash: String,
+    pub size: i64,
+}
+
+pub fn blob_path(user_path: &Path, hash: &str) -> PathBuf {
+    user_path.join(BLOB_PATH).join(&hash[..2]).join(hash)
+}
+
+/// Content path of the file, dirs and files written before the blob store live at wid/id.
+pub fn file_path(user_path: &Path, model: &Model) -> PathBuf {
+    match &model.hash {
+        Some(hash) => blob_path(user_path, hash),
+        None => user_path.join(&model.wid).join(&model.id),
+    }
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Store content and reference it once, identical content is only written once.
+/// The reference is taken before the blob is looked for on disk, it holds the write lock of the
+/// db until committed so remove_unreferenced can not unlink the blob in between.
+pub async fn put_bytes<C: ConnectionTrait>(
+    db: &C,
+    user_path: &Path,
+    content: &[u8],
+) -> Result<StoredBlob, anyhow::Error> {
+    let hash = format!("{:x}", Sha256::digest(content));
+    let size = content.len() as i64;
+    BlobService::add_ref(db, &hash, size, 1).await?;
+    let path = blob_path(user_path, &hash);
+    if !path.exists() {
+        write_atomic(&path, content)?;
+    }
+    Ok(StoredBlob { hash, size })
+}
+
+/// Store a copy of the file at from and reference it once, see put_bytes.
+pub async fn put_file<C: ConnectionTrait>(
+    db: &C,
+    user_path: &Path,
+    from: &Path,
+) -> Result<StoredBlob, anyhow::Error> {
+    let hash = hash_file(from)?;
+    let size = from.metadata()?.len() as i64;
+    BlobService::add_ref(db, &hash, size, 1).await?;
+    let path = blob_path(user_path, &hash);
+    if !path.exists() {
+        copy_atomic(from, &path)?;
+    }
+    Ok(StoredBlob { hash, size })
+}
+
+/// Drop one reference, return true when the blob should be removed after commit.
+pub async fn release<C: ConnectionTrait>(db: &C, hash: &str) -> Result<bool, DbErr> {
+    BlobService::release(db, hash).await
+}
+
+/// Remove blobs nobody references, call after the transaction releasing them is finished.
+pub async fn remove_unreferenced(db: &DatabaseConnection, user_path: &Path, hashes: &[String]) {
+    for hash in hashes {
+        if let Err(err) = remove_if_unreferenced(db, user_path, hash).await {
+            error!("remove blob {} failed, err: {}", hash, err);
+        }
+    }
+}
+
+// checked and unlinked under the write lock of the db, a put of the same content either commits
+// its reference first and keeps the blob or waits and writes the blob again
+async fn remove_if_unreferenced(
+    db: &DatabaseConnection,
+    user_path: &Path,
+    hash: &str,
+) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+    if BlobService::remove_unreferenced(&txn, hash).await? {
+        remove_path(&blob_path(user_path, hash));
+    }
+    txn.commit().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+
+    use sea_orm::{ConnectionTrait, Schema, TransactionTrait};
+
+    use crate::dao::blob_dao::BlobService;
+    use crate::entity;
+    use crate::service::blob_service::{
+        blob_path, put_bytes, put_file, release, remove_unreferenced,
+    };
+    use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+
+    #[tokio::test]
+    async fn test_blob() {
+        let temp_dir = temp_dir();
+        let base_path = &temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-blob");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        let file_path = &base_path.join("test-blob.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        let builder = db.get_database_backend();
+        let schema = Schema::new(builder);
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Blob)))
+            .await
+            .unwrap();
+        // 1. same content is stored once
+        let stored = put_bytes(db, user_path, b"hello").await.unwrap();
+        assert_eq!(5, stored.size);
+        let path = blob_path(user_path, &stored.hash);
+        fs::write(base_path.join("test-blob-source"), b"hello").unwrap();
+        let copied = put_file(db, user_path, &base_path.join("test-blob-source"))
+            .await
+            .unwrap();
+        assert_eq!(stored, copied);
+        let blob = BlobService::get_blob(db, &stored.hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(2, blob.ref_count);
+        // 2. rolled back reference does not keep the blob
+        let txn = db.begin().await.unwrap();
+        let other = put_bytes(&txn, user_path, b"world").await.unwrap();
+        drop(txn);
+        remove_unreferenced(db, user_path, &[other.hash.clone()]).await;
+        assert!(!blob_path(user_path, &other.hash).exists());
+        // 3. blob is removed with its last reference
+        assert!(!release(db, &stored.hash).await.unwrap());
+        remove_unreferenced(db, user_path, &[stored.hash.clone()]).await;
+        assert!(path.exists());
+        assert!(release(db, &stored.hash).await.unwrap());
+        remove_unreferenced(db, user_path, &[stored.hash.clone()]).await;
+        assert!(!path.exists());
+        // 4. blob referenced again before the remove runs is kept
+        let stored = put_bytes(db, user_path, b"again").await.unwrap();
+        assert!(release(db, &stored.hash).await.unwrap());
+        let txn = db.begin().await.unwrap();
+        put_bytes(&txn, user_path, b"again").await.unwrap();
+        let remove = tokio::spawn({
+            let db = db.clone();
+            let user_path = user_path.clone();
+            let hashes = vec![stored.hash.clone()];
+            async move { remove_unreferenced(&db, &user_path, &hashes).await }
+        });
+        txn.commit().await.unwrap();
+        remove.await.unwrap();
+        assert!(blob_path(user_path, &stored.hash).exists());
+    }
+}
@@ -0,0 +1,783 @@
+use async_openai::config::OpenAIConfig;
+use async_openai::error::OpenAIError;
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestToolMessageArgs,
+    ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs, Stop,
+};
+use async_openai::Client;
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use log::{debug, error};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::entity::ai_source::Model as AiSourceModel;
+use crate::service::ai_chat_service::{Message, MessageRole};
+
+// anthropic requires an upper bound of the reply
+const ANTHROPIC_MAX_TOKENS: u32 = 4096;
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Receives the text of the reply piece by piece, the stream tells how it ended.
+pub type ChunkCallback<'a> = dyn FnMut(String) + Send + 'a;
+
+/// Why a chat request failed, sent with the last chunk and kept on the failed reply.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq, Error)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ChatError {
+    // the key of the source is missing, wrong or lacks access
+    #[error("authentication failed: {message}")]
+    Auth { message: String },
+    #[error("rate limited: {message}")]
+    RateLimit { message: String },
+    // the source could not be reached or the connection broke
+    #[error("network error: {message}")]
+    Network { message: String },
+    #[error("model {model} not found: {message}")]
+    ModelMissing { model: String, message: String },
+    // the history and the reply do not fit into the context window of the model
+    #[error("context too long: {message}")]
+    ContextOverflow { message: String },
+    // anything else the provider reported, the status is unknown for errors sent in the stream
+    #[error("provider error {}: {body}", status.map_or("-".into(), |status| status.to_string()))]
+    Provider { status: Option<u16>, body: String },
+}
+
+impl ChatError {
+    /// Classify an error reported by the provider for the model by its http status and body.
+    pub fn from_response(model: &str, status: Option<u16>, body: &str) -> ChatError {
+        let text = body.to_lowercase();
+        let message = body.to_string();
+        match status {
+            Some(401) | Some(403) => ChatError::Auth { message },
+            Some(429) => ChatError::RateLimit { message },
+            _ if is_context_overflow(&text) => ChatError::ContextOverflow { message },
+            _ if text.contains("model")
+                && (text.contains("not found") || text.contains("does not exist")) =>
+            {
+                ChatError::ModelMissing {
+                    model: model.to_string(),
+                    message,
+                }
+            }
+            _ => ChatError::Provider {
+                status,
+                body: message,
+            },
+        }
+    }
+
+    fn from_reqwest(model: &str, err: reqwest::Error) -> ChatError {
+        match err.status() {
+            Some(status) => {
+                ChatError::from_response(model, Some(status.as_u16()), &err.to_string())
+            }
+            None => ChatError::Network {
+                message: err.to_string(),
+            },
+        }
+    }
+
+    fn from_openai(model: &str, err: OpenAIError) -> ChatError {
+        match err {
+            // async-openai depends on another version of reqwest
+            OpenAIError::Reqwest(err) => match err.status() {
+                Some(status) => {
+                    ChatError::from_response(model, Some(status.as_u16()), &err.to_string())
+                }
+                None => ChatError::Network {
+                    message: err.to_string(),
+                },
+            },
+            OpenAIError::ApiError(err) => match err.code.as_deref().or(err.r#type.as_deref()) {
+                Some("invalid_api_key") | Some("authentication_error") => ChatError::Auth {
+                    message: err.message,
+                },
+                Some("rate_limit_exceeded") | Some("insufficient_quota") => ChatError::RateLimit {
+                    message: err.message,
+                },
+                Some("model_not_found") => ChatError::ModelMissing {
+                    model: model.to_string(),
+                    message: err.message,
+                },
+                Some("context_length_exceeded") => ChatError::ContextOverflow {
+                    message: err.message,
+                },
+                _ => ChatError::from_response(model, None, &err.message),
+            },
+            // the event source only reports the status of a failed stream
+            OpenAIError::StreamError(message) => {
+                if let Some(status) = message.strip_prefix("Invalid status code: ") {
+                    let status = status.split_whitespace().next().unwrap_or_default();
+                    ChatError::from_response(model, status.parse().ok(), &message)
+                } else if message.starts_with("Transport error") {
+                    ChatError::Network { message }
+                } else {
+                    ChatError::from_response(model, None, &message)
+                }
+            }
+            err => ChatError::Provider {
+                status: None,
+                body: err.to_string(),
+            },
+        }
+    }
+
+    /// Whether the same request may succeed when sent again later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ChatError::RateLimit { .. } | ChatError::Network { .. } => true,
+            // 529 is anthropic's overloaded
+            ChatError::Provider {
+                status: Some(status),
+                ..
+            } => *status == 408 || *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Name of the error as serialized, for the type of a response.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ChatError::Auth { .. } => "auth",
+            ChatError::RateLimit { .. } => "rateLimit",
+            ChatError::Network { .. } => "network",
+            ChatError::ModelMissing { .. } => "modelMissing",
+            ChatError::ContextOverflow { .. } => "contextOverflow",
+            ChatError::Provider { .. } => "provider",
+        }
+    }
+}
+
+fn is_context_overflow(text: &str) -> bool {
+    [
+        "context length",
+        "context_length",
+        "context window",
+        "prompt is too long",
+    ]
+    .iter()
+    .any(|pattern| text.contains(pattern))
+}
+
+/// Sampling parameters of a request, unset ones are left to the provider.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationOptions {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    pub seed: Option<i64>,
+}
+
+/// Protocol spoken by an ai source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProviderType {
+    // openai compatible chat completions, also served by deepseek and ollama under /v1
+    OpenAi,
+    // native ollama /api/chat
+    Ollama,
+    // anthropic messages api
+    Anthropic,
+}
+
+impl ProviderType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderType::OpenAi => "openai",
+            ProviderType::Ollama => "ollama",
+            ProviderType::Anthropic => "anthropic",
+        }
+    }
+
+    pub fn parse(provider: &str) -> Option<ProviderType> {
+        match provider {
+            "openai" => Some(ProviderType::OpenAi),
+            "ollama" => Some(ProviderType::Ollama),
+            "anthropic" => Some(ProviderType::Anthropic),
+            _ => None,
+        }
+    }
+}
+
+/// A chat backend, the messages are the history ending with the user prompt.
+pub trait ChatProvider: Send + Sync {
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        model: &'a str,
+        options: &'a GenerationOptions,
+    ) -> BoxFuture<'a, Result<String, ChatError>>;
+
+    /// Stream the reply to the callback, the first error ends the stream.
+    fn chat_stream<'a>(
+        &'a self,
+        callback: &'a mut ChunkCallback<'_>,
+        messages: &'a [Message],
+        model: &'a str,
+        options: &'a GenerationOptions,
+    ) -> BoxFuture<'a, Result<(), ChatError>>;
+}
+
+/// Provider for the protocol of the source.
+pub fn new_provider(source: &AiSourceModel) -> Result<Box<dyn ChatProvider>, String> {
+    let url = source.url.trim_end_matches('/').to_string();
+    let key = source.key.clone();
+    match ProviderType::parse(&source.provider) {
+        Some(ProviderType::OpenAi) => Ok(Box::new(OpenAiProvider { url, key })),
+        Some(ProviderType::Ollama) => Ok(Box::new(OllamaProvider { url })),
+        Some(ProviderType::Anthropic) => Ok(Box::new(AnthropicProvider { url, key })),
+        None => Err(format!("unknown provider type {}", source.provider)),
+    }
+}
+
+pub struct OpenAiProvider {
+    url: String,
+    key: String,
+}
+
+impl OpenAiProvider {
+    fn client(&self) -> Client<OpenAIConfig> {
+        let config = OpenAIConfig::new()
+            .with_api_base(&self.url)
+            .with_api_key(&self.key);
+        Client::with_config(config)
+    }
+}
+
+fn openai_message(msg: &Message) -> Result<ChatCompletionRequestMessage, OpenAIError> {
+    let content = msg.content.clone();
+    let message = match msg.role {
+        MessageRole::System => ChatCompletionRequestSystemMessageArgs::default()
+            .content(content)
+            .build()?
+            .into(),
+        MessageRole::User => ChatCompletionRequestUserMessageArgs::default()
+            .content(content)
+            .build()?
+            .into(),
+        MessageRole::Assistant => ChatCompletionRequestAssistantMessageArgs::default()
+            .content(content)
+            .build()?
+            .into(),
+        MessageRole::Tool => ChatCompletionRequestToolMessageArgs::default()
+            .content(content)
+            .tool_call_id(msg.tool_call_id.clone().unwrap_or_default())
+            .build()?
+            .into(),
+    };
+    Ok(message)
+}
+
+fn openai_request_args(
+    messages: &[Message],
+    model: &str,
+    options: &GenerationOptions,
+) -> Result<CreateChatCompletionRequestArgs, OpenAIError> {
+    let request_messages = messages
+        .iter()
+        .map(openai_message)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut args = CreateChatCompletionRequestArgs::default();
+    args.model(model.to_string()).messages(request_messages);
+    if let Some(temperature) = options.temperature {
+        args.temperature(temperature);
+    }
+    if let Some(top_p) = options.top_p {
+        args.top_p(top_p);
+    }
+    if let Some(max_tokens) = options.max_tokens {
+        args.max_completion_tokens(max_tokens);
+    }
+    if !options.stop.is_empty() {
+        args.stop(Stop::StringArray(options.stop.clone()));
+    }
+    if let Some(seed) = options.seed {
+        args.seed(seed);
+    }
+    Ok(args)
+}
+
+impl ChatProvider for OpenAiProvider {
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        model: &'a str,
+        options: &'a GenerationOptions,
+    ) -> BoxFuture<'a, Result<String, ChatError>> {
+        Box::pin(async move {
+            let request = openai_request_args(messages, model, options)
+                .and_then(|args| args.build())
+                .map_err(|err| ChatError::from_openai(model, err))?;
+            debug!("request {:?}", request);
+            let response = self
+                .client()
+                .chat()
+                .create(request)
+                .await
+                .map_err(|err| ChatError::from_openai(model, err))?;
+            debug!("response {:?}", response);
+            Ok(response
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|choice| choice.message.content)
+                .unwrap_or_default())
+        })
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        callback: &'a mut ChunkCallback<'_>,
+        messages: &'a [Message],
+        model: &'a str,
+        options: &'a GenerationOptions,
+    ) -> BoxFuture<'a, Result<(), ChatError>> {
+        Box::pin(async move {
+            let request = openai_request_args(messages, model, options)
+                .and_then(|args| args.build())
+                .map_err(|err| ChatError::from_openai(model, err))?;
+            debug!("request {:?}", request);
+            let mut stream = self
+                .client()
+                .chat()
+                .create_stream(request)
+                .await
+                .map_err(|err| ChatError::from_openai(model, err))?;
+            while let Some(result) = stream.next().await {
+                let response = result.map_err(|err| {
+                    error!("stream err: {:?}", err);
+                    ChatError::from_openai(model, err)
+                })?;
+                response.choices.iter().for_each(|chat_choice| {
+                    if let Some(ref content) = chat_choice.delta.content {
+                        debug!("stream body: {:?}", content);
+                        callback(content.to_string());
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+}
+
+pub struct OllamaProvider {
+    url: String,
+}
+
+impl OllamaProvider {
+    fn request_body(
+        messages: &[Message],
+        model: &str,
+        options: &GenerationOptions,
+        stream: bool,
+    ) -> Value {
+        let messages: Vec<Value> = messages
+            .iter()
+            .map(|msg| json!({"role": msg.role.as_str(), "content": msg.content}))
+            .collect();
+        // unset parameters are left to the model file
+        let mut parameters = json!({});
+        if let Some(temperature) = options.temperature {
+            parameters["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = options.top_p {
+            parameters["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = options.max_tokens {
+            parameters["num_predict"] = json!(max_tokens);
+        }
+        if !options.stop.is_empty() {
+            parameters["stop"] = json!(options.stop);
+        }
+        if let Some(seed) = options.seed {
+            parameters["seed"] = json!(seed);
+        }
+        json!({"model": model, "messages": messages, "stream": stream, "options": parameters})
+    }
+
+    async fn send(&self, model: &str, body: &Value) -> Result<reqwest::Response, ChatError> {
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/chat", self.url))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|err| ChatError::from_reqwest(model, err))?;
+        check_status(model, response).await
+    }
+}
+
+impl ChatProvider for OllamaProvider {
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        model: &'a str,
+        options: &'a GenerationOptions,
+    ) -> BoxFuture<'a, Result<String, ChatError>> {
+        Box::pin(async move {
+            let body = Self::request_body(messages, model, options, false);
+            debug!("request {}", body);
+            let text = self
+                .send(model, &body)
+                .await?
+                .text()
+                .await
+                .map_err(|err| ChatError::from_reqwest(model, err))?;
+            let response: Value = serde_json::from_str(&text)
+                .map_err(|_| ChatError::from_response(model, None, &text))?;
+            debug!("response {}", response);
+            Ok(response["message"]["content"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string())
+        })
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        callback: &'a mut ChunkCallback<'_>,
+        messages: &'a [Message],
+        model: &'a str,
+        options: &'a GenerationOptions,
+    ) -> BoxFuture<'a, Result<(), ChatError>> {
+        Box::pin(async move {
+            let body = Self::request_body(messages, model, options, true);
+            debug!("request {}", body);
+            let response = self.send(model, &body).await?;
+            // one json object per line
+            read_lines(model, response, callback, |line| {
+                let value: Value = serde_json::from_str(line)
+                    .map_err(|_| ChatError::from_response(model, None, line))?;
+                if let Some(err) = value["error"].as_str() {
+                    return Err(ChatError::from_response(model, None, err));
+                }
+                Ok(value["message"]["content"].as_str().map(str::to_string))
+            })
+            .await
+        })
+    }
+}
+
+pub struct AnthropicProvider {
+    url: String,
+    key: String,
+}
+
+impl AnthropicProvider {
+    fn request_body(
+        messages: &[Message],
+        model: &str,
+        options: &GenerationOptions,
+        stream: bool,
+    ) -> Value {
+        // system prompts are a parameter of the request, tool results are sent by the user
+        let system: Vec<&str> = messages
+            .iter()
+            .filter(|msg| msg.role == MessageRole::System)
+            .map(|msg| msg.content.as_str())
+            .collect();
+        let messages: Vec<Value> = messages
+            .iter()
+            .filter_map(|msg| match msg.role {
+                MessageRole::System => None,
+                MessageRole::User | MessageRole::Assistant => {
+                    Some(json!({"role": msg.role.as_str(), "content": msg.content}))
+                }
+                MessageRole::Tool => Some(json!({
+                    "role": "user",
+                    "content": [{
+                        "type": "tool_result",
+                        "tool_use_id": msg.tool_call_id.clone().unwrap_or_default(),
+                        "content": msg.content,
+                    }],
+                })),
+            })
+            .collect();
+        let mut body = json!({
+            "model": model,
+            "messages": messages,
+            "max_tokens": options.max_tokens.unwrap_or(ANTHROPIC_MAX_TOKENS),
+            "stream": stream,
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system.join("\n\n"));
+        }
+        // the messages api has no seed
+        if let Some(temperature) = options.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = options.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if !options.stop.is_empty() {
+            body["stop_sequences"] = json!(options.stop);
+        }
+        body
+    }
+
+    async fn send(&self, model: &str, body: &Value) -> Result<reqwest::Response, ChatError> {
+        let response = reqwest::Client::new()
+            .post(format!("{}/v1/messages", self.url))
+            .header("x-api-key", &self.key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|err| ChatError::from_reqwest(model, err))?;
+        check_status(model, response).await
+    }
+}
+
+impl ChatProvider for AnthropicProvider {
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        model: &'a str,
+        options: &'a GenerationOptions,
+    ) -> BoxFuture<'a, Result<String, ChatError>> {
+        Box::pin(async move {
+            let body = Self::request_body(messages, model, options, false);
+            debug!("request {}", body);
+            let text = self
+                .send(model, &body)
+                .await?
+                .text()
+                .await
+                .map_err(|err| ChatError::from_reqwest(model, err))?;
+            let response: Value = serde_json::from_str(&text)
+                .map_err(|_| ChatError::from_response(model, None, &text))?;
+            debug!("response {}", response);
+            let text = response["content"]
+                .as_array()
+                .map(|blocks| {
+                    blocks
+                        .iter()
+                        .filter_map(|block| block["text"].as_str())
+                        .collect::<String>()
+                })
+                .unwrap_or_default();
+            Ok(text)
+        })
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        callback: &'a mut ChunkCallback<'_>,
+        messages: &'a [Message],
+        model: &'a str,
+        options: &'a GenerationOptions,
+    ) -> BoxFuture<'a, Result<(), ChatError>> {
+        Box::pin(async move {
+            let body = Self::request_body(messages, model, options, true);
+            debug!("request {}", body);
+            let response = self.send(model, &body).await?;
+            // server sent events, the event type is repeated in the data
+            read_lines(model, response, callback, |line| {
+                let Some(data) = line.strip_prefix("data:") else {
+                    return Ok(None);
+                };
+                let value: Value = serde_json::from_str(data.trim())
+                    .map_err(|_| ChatError::from_response(model, None, data))?;
+                match value["type"].as_str() {
+                    Some("content_block_delta") => {
+                        Ok(value["delta"]["text"].as_str().map(str::to_string))
+                    }
+                    // overloaded is anthropic's way to ask for a retry
+                    Some("error") => match value["error"]["type"].as_str() {
+                        Some("overloaded_error") | Some("rate_limit_error") => {
+                            Err(ChatError::RateLimit {
+                                message: value["error"]["message"]
+                                    .as_str()
+                                    .unwrap_or_default()
+                                    .to_string(),
+                            })
+                        }
+                        _ => Err(ChatError::from_response(
+                            model,
+                            None,
+                            &value["error"].to_string(),
+                        )),
+                    },
+                    _ => Ok(None),
+                }
+            })
+            .await
+        })
+    }
+}
+
+/// Turn an unsuccessful response into the error of the request for the model.
+pub(crate) async fn check_status(
+    model: &str,
+    response: reqwest::Response,
+) -> Result<reqwest::Response, ChatError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(ChatError::from_response(
+        model,
+        Some(status.as_u16()),
+        &body,
+    ))
+}
+
+/// Feed every non empty line of the body to `parse`, which returns the text it carries.
+async fn read_lines<P>(
+    model: &str,
+    mut response: reqwest::Response,
+    callback: &mut ChunkCallback<'_>,
+    parse: P,
+) -> Result<(), ChatError>
+where
+    P: Fn(&str) -> Result<Option<String>, ChatError>,
+{
+    let mut buffer: Vec<u8> = vec![];
+    let handle = |line: &[u8], callback: &mut ChunkCallback<'_>| {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        if let Some(text) = parse(line)? {
+            debug!("stream body: {:?}", text);
+            callback(text);
+        }
+        Ok(())
+    };
+    loop {
+        let chunk = response.chunk().await.map_err(|err| {
+            error!("stream err: {:?}", err);
+            ChatError::from_reqwest(model, err)
+        })?;
+        let Some(chunk) = chunk else {
+            break;
+        };
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            handle(&line, callback)?;
+        }
+    }
+    handle(&buffer, callback)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_openai::types::ChatCompletionRequestMessage;
+
+    use crate::service::ai_chat_service::{Message, MessageRole};
+    use crate::service::chat_provider_service::{
+        openai_request_args, AnthropicProvider, ChatError, GenerationOptions, OllamaProvider,
+        ProviderType,
+    };
+    use async_openai::error::OpenAIError;
+
+    #[test]
+    fn test_request_body() {
+        for provider in [
+            ProviderType::OpenAi,
+            ProviderType::Ollama,
+            ProviderType::Anthropic,
+        ] {
+            assert_eq!(Some(provider), ProviderType::parse(provider.as_str()));
+        }
+        assert_eq!(None, ProviderType::parse("unknown"));
+
+        let messages = vec![
+            Message::new(MessageRole::System, "be brief"),
+            Message::new(MessageRole::User, "hi"),
+            Message::new(MessageRole::Assistant, "hello"),
+        ];
+        let options = GenerationOptions {
+            temperature: Some(0.5),
+            max_tokens: Some(256),
+            stop: vec!["END".to_string()],
+            ..Default::default()
+        };
+        let body = OllamaProvider::request_body(&messages, "llama3.1:8b", &options, true);
+        assert_eq!(0.5, body["options"]["temperature"]);
+        assert_eq!(256, body["options"]["num_predict"]);
+        assert!(body["options"]["seed"].is_null());
+        assert_eq!("llama3.1:8b", body["model"]);
+        assert_eq!(true, body["stream"]);
+        assert_eq!("system", body["messages"][0]["role"]);
+        assert_eq!("assistant", body["messages"][2]["role"]);
+        let body = AnthropicProvider::request_body(
+            &messages,
+            "claude",
+            &GenerationOptions::default(),
+            false,
+        );
+        assert_eq!("be brief", body["system"]);
+        assert_eq!(2, body["messages"].as_array().unwrap().len());
+        assert_eq!("user", body["messages"][0]["role"]);
+        assert_eq!("assistant", body["messages"][1]["role"]);
+        assert_eq!(4096, body["max_tokens"]);
+        // typed messages for openai compatible sources
+        let request = openai_request_args(&messages, "gpt", &options)
+            .and_then(|args| args.build())
+            .unwrap();
+        assert!(matches!(
+            request.messages[0],
+            ChatCompletionRequestMessage::System(_)
+        ));
+        assert!(matches!(
+            request.messages[2],
+            ChatCompletionRequestMessage::Assistant(_)
+        ));
+        assert_eq!(Some(0.5), request.temperature);
+        assert_eq!(Some(256), request.max_completion_tokens);
+    }
+
+    #[test]
+    fn test_chat_error() {
+        let err = ChatError::from_response("gpt", Some(401), "invalid x-api-key");
+        assert_eq!("auth", err.kind());
+        let err = ChatError::from_response("gpt", Some(429), "too many requests");
+        assert_eq!("rateLimit", err.kind());
+        let err = ChatError::from_response(
+            "llama3",
+            Some(404),
+            r#"{"error":"model \"llama3\" not found, try pulling it first"}"#,
+        );
+        assert!(matches!(err, ChatError::ModelMissing { ref model, .. } if model == "llama3"));
+        let err =
+            ChatError::from_response("claude", Some(400), "prompt is too long: 250000 tokens");
+        assert_eq!("contextOverflow", err.kind());
+        let err = ChatError::from_response("gpt", Some(500), "internal error");
+        assert_eq!(
+            ChatError::Provider {
+                status: Some(500),
+                body: "internal error".to_string()
+            },
+            err
+        );
+        // failed openai streams only carry the status
+        let err = OpenAIError::StreamError("Invalid status code: 401 Unauthorized".to_string());
+        assert_eq!("auth", ChatError::from_openai("gpt", err).kind());
+        let err = OpenAIError::StreamError("Transport error: connection refused".to_string());
+        assert_eq!("network", ChatError::from_openai("gpt", err).kind());
+        // tagged by kind for the frontend
+        let json = serde_json::to_value(ChatError::Provider {
+            status: None,
+            body: "oops".to_string(),
+        })
+        .unwrap();
+        assert_eq!("provider", json["kind"]);
+        assert!(json["status"].is_null());
+        assert_eq!("oops", json["body"]);
+    }
+}
@@ -0,0 +1,197 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::service::ai_chat_service::Message;
+use crate::util::fs_util::write_atomic;
+
+/// A message of the chat with the message it follows.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageNode {
+    pub id: String,
+    // none for the first messages of the branches
+    pub parent_id: Option<String>,
+    #[serde(flatten)]
+    pub message: Message,
+}
+
+/// Messages of a chat, edits and regenerations add a branch next to the one they replace.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatTree {
+    // in the order they were added, so siblings are oldest first
+    pub nodes: Vec<MessageNode>,
+    // last message of the branch shown and sent to the model
+    pub active_id: Option<String>,
+}
+
+impl ChatTree {
+    /// Single branch of the messages of a chat written before branches existed.
+    pub fn from_messages(messages: Vec<Message>) -> ChatTree {
+        let mut tree = ChatTree::default();
+        for message in messages {
+            let parent_id = tree.active_id.clone();
+            tree.push(parent_id.as_deref(), message);
+        }
+        tree
+    }
+
+    pub fn get(&self, id: &str) -> Option<&MessageNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    /// Add the message after the parent as the last message of the active branch.
+    pub fn push(&mut self, parent_id: Option<&str>, message: Message) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.nodes.push(MessageNode {
+            id: id.clone(),
+            parent_id: parent_id.map(str::to_string),
+            message,
+        });
+        self.active_id = Some(id.clone());
+        id
+    }
+
+    /// Messages from the first one of the branch to the given one.
+    pub fn path_to(&self, id: Option<&str>) -> Vec<&MessageNode> {
+        let mut path = vec![];
+        let mut next = id.and_then(|id| self.get(id));
+        // a broken file must not loop forever
+        while let Some(node) = next {
+            if path.len() == self.nodes.len() {
+                break;
+            }
+            path.push(node);
+            next = node.parent_id.as_deref().and_then(|id| self.get(id));
+        }
+        path.reverse();
+        path
+    }
+
+    pub fn active_path(&self) -> Vec<&MessageNode> {
+        self.path_to(self.active_id.as_deref())
+    }
+
+    /// History of the active branch as sent to the model.
+    pub fn active_messages(&self) -> Vec<Message> {
+        self.active_path()
+            .into_iter()
+            .map(|node| node.message.clone())
+            .collect()
+    }
+
+    pub fn children(&self, parent_id: Option<&str>) -> Vec<&MessageNode> {
+        self.nodes
+            .iter()
+            .filter(|node| node.parent_id.as_deref() == parent_id)
+            .collect()
+    }
+
+    /// Messages following the same message as the given one, itself included.
+    pub fn siblings(&self, id: &str) -> Option<Vec<&MessageNode>> {
+        let node = self.get(id)?;
+        Some(self.children(node.parent_id.as_deref()))
+    }
+
+    /// Show the branch through the message, below it the newest messages are followed.
+    pub fn switch(&mut self, id: &str) -> bool {
+        if self.get(id).is_none() {
+            return false;
+        }
+        let mut active_id = id.to_string();
+        // a broken file must not loop forever
+        for _ in 0..self.nodes.len() {
+            match self.children(Some(&active_id)).last() {
+                Some(child) => active_id = child.id.clone(),
+                None => break,
+            }
+        }
+        self.active_id = Some(active_id);
+        true
+    }
+}
+
+/// Read the chat, an empty file is an empty chat and a list of messages a single branch.
+pub fn parse_chat(content: &str) -> Result<ChatTree, serde_json::Error> {
+    if content.trim().is_empty() {
+        return Ok(ChatTree::default());
+    }
+    let value: Value = serde_json::from_str(content)?;
+    match value {
+        Value::Array(_) => Ok(ChatTree::from_messages(serde_json::from_value(value)?)),
+        _ => serde_json::from_value(value),
+    }
+}
+
+pub fn load_chat(file_path: &Path) -> Result<ChatTree, anyhow::Error> {
+    let content = fs::read_to_string(file_path)?;
+    Ok(parse_chat(&content)?)
+}
+
+pub fn save_chat(file_path: &Path, tree: &ChatTree) -> Result<u64, anyhow::Error> {
+    let json_str = serde_json::to_string(tree)?;
+    Ok(write_atomic(file_path, json_str.as_bytes())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::service::ai_chat_service::{Message, MessageRole};
+    use crate::service::chat_tree_service::{parse_chat, ChatTree};
+
+    #[test]
+    fn test_chat_tree() {
+        // 1. chats written before branches are a single branch
+        let tree = parse_chat(
+            r#"[{"role":"user","content":"hi"},{"role":"assistant","content":"hello"}]"#,
+        )
+        .unwrap();
+        assert_eq!(2, tree.nodes.len());
+        assert_eq!(None, tree.nodes[0].parent_id);
+        assert_eq!(Some(&tree.nodes[0].id), tree.nodes[1].parent_id.as_ref());
+        assert_eq!(Some(&tree.nodes[1].id), tree.active_id.as_ref());
+        assert_eq!(ChatTree::default(), parse_chat("").unwrap());
+        // 2. a regenerated reply is a sibling and becomes active
+        let mut tree = tree;
+        let prompt_id = tree.nodes[0].id.clone();
+        let first_id = tree.nodes[1].id.clone();
+        let second_id = tree.push(
+            Some(&prompt_id),
+            Message::new(MessageRole::Assistant, "hey"),
+        );
+        let contents: Vec<String> = tree
+            .active_messages()
+            .into_iter()
+            .map(|message| message.content)
+            .collect();
+        assert_eq!(vec!["hi", "hey"], contents);
+        let siblings = tree.siblings(&first_id).unwrap();
+        assert_eq!(
+            vec![&first_id, &second_id],
+            siblings.iter().map(|node| &node.id).collect::<Vec<_>>()
+        );
+        // 3. an edited prompt is a sibling of the first prompt
+        let edit_id = tree.push(None, Message::new(MessageRole::User, "hello?"));
+        tree.push(Some(&edit_id), Message::new(MessageRole::Assistant, "yes"));
+        assert_eq!(2, tree.siblings(&prompt_id).unwrap().len());
+        assert_eq!(2, tree.active_path().len());
+        assert_eq!("yes", tree.active_path()[1].message.content);
+        // 4. switching follows the newest messages below the chosen one
+        assert!(tree.switch(&prompt_id));
+        assert_eq!(Some(&second_id), tree.active_id.as_ref());
+        assert!(tree.switch(&first_id));
+        assert_eq!("hello", tree.active_path()[1].message.content);
+        assert!(!tree.switch("unknown"));
+        // 5. stored with the branches
+        let json = serde_json::to_string(&tree).unwrap();
+        assert_eq!(tree, parse_chat(&json).unwrap());
+        // 6. a message following itself does not hang
+        let loop_id = tree.push(None, Message::new(MessageRole::User, "loop"));
+        tree.nodes.last_mut().unwrap().parent_id = Some(loop_id.clone());
+        assert!(tree.switch(&loop_id));
+        assert_eq!(Some(&loop_id), tree.active_id.as_ref());
+    }
+}
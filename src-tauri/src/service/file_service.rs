@@ -1,24 +1,42 @@
 use chrono::Utc;
 use log::error;
-use sea_orm::{DatabaseConnection, DbErr, Set};
+use sea_orm::{
+    ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbErr, Set, TransactionTrait,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::fs::copy;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tauri::api::dir::is_dir;
 use uuid::Uuid;
 
+use crate::dao::blob_dao::BlobService;
+use crate::dao::chat_setting_dao::ChatSettingService;
 use crate::dao::file_dao::FileService;
-use crate::dto::file::{CopyBody, CreateBody, GeneralBody, ListByPageBody, ListByPidBody, ListGeneralBody, PageResult, UpdateBody, UpdateContentBody, UpdateNameBody};
+use crate::dto::file::{CopyBody, CreateBody, GeneralBody, ListByPageBody, ListByPidBody, ListGeneralBody, PageResult, TrashBody, UpdateBody, UpdateContentBody, UpdateNameBody};
 use crate::entity::file::{ActiveModel, Model};
-use crate::{AppResponse, DIR_TYPE, RESPONSE_CODE_ERROR, RESPONSE_CODE_SUCCESS};
+use crate::service::blob_service::{
+    file_path, put_bytes, put_file, release, remove_unreferenced, StoredBlob,
+};
+use crate::service::file_version_service::{keep_version, remove_file_versions};
+use crate::service::permission_service::{
+    check_file, check_workspace, list_workspace_ids, AccessError, Role,
+};
+use crate::service::search_service::{index_file, remove_indexed_files, rename_file};
+use crate::util::fs_util::{copy_atomic, remove_path};
+use crate::{
+    AppResponse, VersionSettings, CHAT_ZONE, DIR_TYPE, FILE_STATE_NORMAL, FILE_STATE_TRASHED,
+    RESPONSE_CODE_ERROR, RESPONSE_CODE_SUCCESS,
+};
 
 pub async fn get_workspace_files(
     db: &DatabaseConnection,
+    user_id: &str,
     general_body: &ListGeneralBody,
 ) -> AppResponse<Vec<Model>> {
+    if let Err(err) = check_workspace(db, user_id, &general_body.wid, Role::Viewer).await {
+        return err.response(vec![]);
+    }
     match FileService::list_files(db, general_body).await {
         Ok(result) => AppResponse::success(result),
         Err(err) => AppResponse::error(vec![], &err.to_string()),
@@ -27,9 +45,15 @@ pub async fn get_workspace_files(
 
 pub async fn get_workspace_files_by_pid(
     db: &DatabaseConnection,
+    user_id: &str,
     body: &ListByPidBody,
 ) -> AppResponse<Vec<Model>> {
-    match FileService::list_files_by_pid(db, body).await {
+    // pid may be empty, only look into workspaces of the user
+    let wids = match list_workspace_ids(db, user_id).await {
+        Ok(wids) => wids,
+        Err(err) => return AppResponse::error(vec![], &err.to_string()),
+    };
+    match FileService::list_files_by_pid(db, body, &wids).await {
         Ok(result) => AppResponse::success(result),
         Err(err) => AppResponse::error(vec![], &err.to_string()),
     }
@@ -37,273 +61,592 @@ pub async fn get_workspace_files_by_pid(
 
 pub async fn get_workspace_files_by_page(
     db: &DatabaseConnection,
+    user_id: &str,
     body: &ListByPageBody,
 ) -> AppResponse<PageResult> {
-    match FileService::list_files_by_page(db, body).await {
+    let empty = || PageResult {
+        total: 0,
+        items: vec![],
+    };
+    let wids = match list_workspace_ids(db, user_id).await {
+        Ok(wids) => wids,
+        Err(err) => return AppResponse::error(empty(), &err.to_string()),
+    };
+    match FileService::list_files_by_page(db, body, &wids).await {
         Ok(result) => AppResponse::success(result),
-        Err(err) => AppResponse::error(
-            PageResult {
-                total: 0,
-                items: vec![],
-            },
-            &err.to_string(),
-        ),
+        Err(err) => AppResponse::error(empty(), &err.to_string()),
     }
 }
 
 pub async fn get_file(
     db: &DatabaseConnection,
+    user_id: &str,
     general_body: &GeneralBody,
 ) -> AppResponse<Option<Model>> {
-    match FileService::get_file(db, &general_body.id).await {
-        Ok(option_model) => AppResponse::success(option_model),
-        Err(err) => AppResponse::error(None::<Model>, &err.to_string()),
+    match check_file(db, user_id, &general_body.id, Role::Viewer).await {
+        Ok(model) => AppResponse::success(Some(model)),
+        Err(AccessError::NotFound(_)) => AppResponse::success(None),
+        Err(err) => err.response(None),
     }
 }
 
-pub async fn get_path(user_path: &PathBuf, general_body: &GeneralBody) -> AppResponse<String> {
-    let file_path = &user_path.join(&general_body.wid).join(&general_body.id);
-    AppResponse::success(file_path.to_str().unwrap().to_string())
+pub async fn get_path(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    user_id: &str,
+    general_body: &GeneralBody,
+) -> AppResponse<String> {
+    match check_file(db, user_id, &general_body.id, Role::Viewer).await {
+        Ok(model) => {
+            AppResponse::success(file_path(user_path, &model).to_str().unwrap().to_string())
+        }
+        Err(err) => err.response("".to_string()),
+    }
 }
 
 pub async fn copy_file(
     db: &DatabaseConnection,
     user_path: &PathBuf,
+    user_id: &str,
     body: &CopyBody,
 ) -> AppResponse<Option<Model>> {
     // todo check parent first
     // if type is dir and parent is file, no allow to create
-
-    let get_from_file_result = FileService::get_file(db, &body.from_id).await;
-    if get_from_file_result.is_err() {
-        return AppResponse::error(None::<Model>, &get_from_file_result.err().unwrap().to_string());
-    }
-    let from_file = get_from_file_result.unwrap().unwrap();
-    let copy_response = match FileService::create_file(
-        db,
-        ActiveModel {
-            id: Set(Uuid::new_v4().to_string()),
-            name: Set(body.name.clone()),
-            r#type: Set(from_file.r#type.clone()),
-            pid: Set(from_file.pid.clone()),
-            wid: Set(from_file.wid.clone()),
-            zone: Set(from_file.zone.clone()),
-            size: Set(0),
-            create_time: Set(Utc::now().timestamp()),
-            update_time: Set(Utc::now().timestamp()),
-            state: Set(1),
-            ..Default::default()
-        },
-    )
-        .await
-    {
-        Ok(model) => AppResponse::success(Some(model)),
-        Err(err) => AppResponse::error(None::<Model>, &err.to_string()),
+    let from_file = match check_file(db, user_id, &body.from_id, Role::Viewer).await {
+        Ok(model) => model,
+        Err(err) => return err.response(None),
     };
-    if copy_response.is_error() {
-        return copy_response;
-    }
-    let file_model = copy_response.result.unwrap();
-    let from_file_path = &user_path.join(&file_model.wid).join(&body.from_id);
-    let file_path = &user_path.join(&file_model.wid).join(&file_model.id);
+    let from_file_path = &file_path(user_path, &from_file);
     if !from_file_path.exists() {
         return AppResponse::error(
             None,
             "source file not found, please make sure the file exists",
         );
     }
-    if !file_path.exists() {
-        if file_model.r#type == DIR_TYPE {
-            fs::create_dir_all(file_path).unwrap();
-        } else {
-            let result = copy(from_file_path, file_path);
-            if result.is_err() {
-                error!("copy file on disk failed, err: {}", result.err().unwrap());
-                return AppResponse::error(
-                    None,
-                    "copy file on disk failed, please check your disk space and permissions",
-                );
-            }
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let id = Uuid::new_v4().to_string();
+    let dir_path = &user_path.join(&from_file.wid).join(&id);
+    // copy of a stored file only references its blob
+    let stored = if from_file.r#type == DIR_TYPE {
+        fs::create_dir_all(dir_path)
+            .map(|_| None)
+            .map_err(anyhow::Error::from)
+    } else if let Some(hash) = &from_file.hash {
+        BlobService::add_ref(&txn, hash, from_file.size, 1)
+            .await
+            .map(|_| {
+                Some(StoredBlob {
+                    hash: hash.clone(),
+                    size: from_file.size,
+                })
+            })
+            .map_err(anyhow::Error::from)
+    } else {
+        // source was written before the blob store, move it in and reference it from both rows
+        match adopt_legacy_file(&txn, user_path, &from_file).await {
+            Ok(stored) => BlobService::add_ref(&txn, &stored.hash, stored.size, 1)
+                .await
+                .map(|_| Some(stored))
+                .map_err(anyhow::Error::from),
+            Err(err) => Err(err),
         }
+    };
+    let stored = match stored {
+        Ok(stored) => stored,
+        Err(err) => {
+            error!("copy file on disk failed, err: {}", err);
+            return AppResponse::error(
+                None,
+                "copy file on disk failed, please check your disk space and permissions",
+            );
+        }
+    };
+    let active_model = ActiveModel {
+        id: Set(id),
+        name: Set(body.name.clone()),
+        r#type: Set(from_file.r#type.clone()),
+        pid: Set(from_file.pid.clone()),
+        wid: Set(from_file.wid.clone()),
+        zone: Set(from_file.zone.clone()),
+        size: Set(stored.as_ref().map_or(0, |stored| stored.size)),
+        hash: Set(stored.as_ref().map(|stored| stored.hash.clone())),
+        create_time: Set(Utc::now().timestamp()),
+        update_time: Set(Utc::now().timestamp()),
+        state: Set(1),
+        ..Default::default()
+    };
+    let response = commit_file(db, txn, user_path, dir_path, active_model, &stored).await;
+    if response.is_success() && stored.is_some() && from_file.hash.is_none() {
+        remove_path(from_file_path);
     }
-    AppResponse::success(Some(file_model))
+    response
+}
+
+// move a file written before the blob store into it, referenced once from its own row
+async fn adopt_legacy_file(
+    txn: &DatabaseTransaction,
+    user_path: &PathBuf,
+    model: &Model,
+) -> Result<StoredBlob, anyhow::Error> {
+    let stored = put_file(txn, user_path, &file_path(user_path, model)).await?;
+    FileService::update_file_hash(txn, &model.id, &stored.hash, stored.size).await?;
+    Ok(stored)
 }
 
 pub async fn create_file(
-        db: &DatabaseConnection,
-        user_path: &PathBuf,
-        general_body: &CreateBody,
-    ) -> AppResponse<Option<Model>> {
-        // todo check parent first
-        // if type is dir and parent is file, no allow to create
-        let create_response = match FileService::create_file(
-            db,
-            ActiveModel {
-                id: Set(Uuid::new_v4().to_string()),
-                name: Set(general_body.name.clone()),
-                r#type: Set(general_body.r#type.to_string()),
-                pid: Set(general_body.pid.to_string()),
-                wid: Set(general_body.wid.to_string()),
-                zone: Set(general_body.zone.to_string()),
-                size: Set(0),
-                create_time: Set(Utc::now().timestamp()),
-                update_time: Set(Utc::now().timestamp()),
-                state: Set(1),
-                ..Default::default()
-            },
-        )
-            .await
-        {
-            Ok(model) => AppResponse::success(Some(model)),
-            Err(err) => AppResponse::error(None::<Model>, &err.to_string()),
-        };
-        if create_response.is_error() {
-            return create_response;
-        }
-        let file_model = create_response.result.unwrap();
-        let file_path = &user_path.join(&general_body.wid).join(&file_model.id);
-        if !file_path.exists() {
-            if general_body.r#type == DIR_TYPE {
-                fs::create_dir_all(file_path).unwrap();
-            } else {
-                let result = File::create(file_path);
-                if result.is_err() {
-                    error!("create file on disk failed, err: {}", result.err().unwrap());
-                    return AppResponse::error(
-                        None,
-                        "create file on disk failed, please check your disk space and permissions",
-                    );
-                }
-            }
-        }
-        // direct return if type is dir
-        if file_model.r#type == DIR_TYPE {
-            return AppResponse::success(Some(file_model));
-        }
-        let mut size = 0i64;
-        // insert content or copy file
-        if general_body.content.is_some() {
-            let open_result = File::options().write(true).open(file_path);
-            if open_result.is_err() {
-                error!(
-                "open file on disk failed, err: {}",
-                open_result.err().unwrap()
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    user_id: &str,
+    general_body: &CreateBody,
+) -> AppResponse<Option<Model>> {
+    if let Err(err) = check_parent(db, user_id, &general_body.wid, &general_body.pid).await {
+        return err.response(None);
+    }
+    // todo check parent first
+    // if type is dir and parent is file, no allow to create
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let id = Uuid::new_v4().to_string();
+    let dir_path = &user_path.join(&general_body.wid).join(&id);
+    // insert content or copy file
+    let stored = if general_body.r#type == DIR_TYPE {
+        fs::create_dir_all(dir_path)
+            .map(|_| None)
+            .map_err(anyhow::Error::from)
+    } else if let Some(content) = &general_body.content {
+        put_bytes(&txn, user_path, content).await.map(Some)
+    } else if let Some(path) = &general_body.path {
+        put_file(&txn, user_path, Path::new(path)).await.map(Some)
+    } else {
+        put_bytes(&txn, user_path, &[]).await.map(Some)
+    };
+    let stored = match stored {
+        Ok(stored) => stored,
+        Err(err) => {
+            // txn is dropped here, nothing is kept
+            error!("create file on disk failed, err: {}", err);
+            return AppResponse::error(
+                None,
+                "create file on disk failed, please check your disk space and permissions",
             );
-                return AppResponse::error(
-                    None,
-                    "open file on disk failed, please check your disk space and permissions",
-                );
-            }
-            let mut file = open_result.unwrap();
-            let content = general_body.content.as_ref().unwrap();
-            if content.len() > 0 {
-                file.write_all(content).unwrap();
+        }
+    };
+    let active_model = ActiveModel {
+        id: Set(id),
+        name: Set(general_body.name.clone()),
+        r#type: Set(general_body.r#type.to_string()),
+        pid: Set(general_body.pid.to_string()),
+        wid: Set(general_body.wid.to_string()),
+        zone: Set(general_body.zone.to_string()),
+        size: Set(stored.as_ref().map_or(0, |stored| stored.size)),
+        hash: Set(stored.as_ref().map(|stored| stored.hash.clone())),
+        create_time: Set(Utc::now().timestamp()),
+        update_time: Set(Utc::now().timestamp()),
+        state: Set(1),
+        ..Default::default()
+    };
+    commit_file(db, txn, user_path, dir_path, active_model, &stored).await
+}
+
+// insert the row and commit, drop what was written on disk if the row can not be kept
+async fn commit_file(
+    db: &DatabaseConnection,
+    txn: DatabaseTransaction,
+    user_path: &PathBuf,
+    dir_path: &Path,
+    active_model: ActiveModel,
+    stored: &Option<StoredBlob>,
+) -> AppResponse<Option<Model>> {
+    // a failed transaction is dropped first, its write lock keeps blobs from being removed
+    let result = match FileService::create_file(&txn, active_model).await {
+        Ok(model) => txn.commit().await.map(|_| model),
+        Err(err) => {
+            drop(txn);
+            Err(err)
+        }
+    };
+    match result {
+        Ok(model) => {
+            index_file(db, user_path, &model).await;
+            AppResponse::success(Some(model))
+        }
+        Err(err) => {
+            match stored {
+                Some(stored) => remove_unreferenced(db, user_path, &[stored.hash.clone()]).await,
+                None => remove_path(dir_path),
             }
-            size = file.metadata().unwrap().len() as i64;
-        } else if general_body.path.is_some() {
-            // extract this
-            let path = general_body.path.as_ref().unwrap();
-            copy(path, file_path).unwrap();
-            size = file_path.metadata().unwrap().len() as i64;
-        }
-        // update file model size
-        match FileService::update_file_size(db, &file_model.id, size).await {
-            Ok(_) => AppResponse::success(Some(file_model)),
-            Err(err) => AppResponse::error(None, &err.to_string()),
+            AppResponse::error(None, &err.to_string())
         }
+    }
 }
 
 pub async fn update_file_content(
     db: &DatabaseConnection,
     user_path: &PathBuf,
+    settings: &VersionSettings,
+    user_id: &str,
     body: &UpdateContentBody,
 ) -> AppResponse<Option<Model>> {
-    let get_result = FileService::get_file(db, &body.id).await;
-    if get_result.is_err() {
-        return AppResponse::error(None, &get_result.err().unwrap().to_string());
-    }
-    let option_model = get_result.unwrap();
-    if option_model.is_none() {
-        return AppResponse::error(None, "file not found");
-    }
-    let mut model = option_model.unwrap();
+    let mut model = match check_file(db, user_id, &body.id, Role::Editor).await {
+        Ok(model) => model,
+        Err(err) => return err.response(None),
+    };
     if model.r#type == DIR_TYPE {
         return AppResponse::error(None, "dir can not update");
     }
-    let file_path = &user_path.join(&model.wid).join(&model.id);
-    let open_result = File::options().write(true).truncate(true).open(file_path);
-    if open_result.is_err() {
-        error!(
-            "open file on disk failed, err: {}",
-            open_result.err().unwrap()
-        );
-        return AppResponse::error(
-            None,
-            "open file on disk failed, please check your disk space and permissions",
-        );
-    }
-    let mut file = open_result.unwrap();
-    let content = &body.content;
-    if content.len() > 0 {
-        file.write_all(content).unwrap();
-    }
-    let size = file.metadata().unwrap().len() as i64;
-    model.size = size;
-    match FileService::update_file_size(db, &body.id, size).await {
-        Ok(_) => AppResponse::success(Some(model)),
-        Err(err) => AppResponse::error(None, &err.to_string()),
+    let old_file_path = &file_path(user_path, &model);
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    // old content stays in place until the new one is fully written
+    let stored = match put_bytes(&txn, user_path, &body.content).await {
+        Ok(stored) => stored,
+        Err(err) => {
+            error!("write file on disk failed, err: {}", err);
+            return AppResponse::error(
+                None,
+                "write file on disk failed, please check your disk space and permissions",
+            );
+        }
+    };
+    let result = if model.hash.as_ref() == Some(&stored.hash) {
+        // content did not change, no version to keep
+        release(&txn, &stored.hash)
+            .await
+            .map(|_| vec![])
+            .map_err(anyhow::Error::from)
+    } else {
+        match FileService::update_file_hash(&txn, &body.id, &stored.hash, stored.size).await {
+            Ok(_) => keep_version(&txn, user_path, settings, &model).await,
+            Err(err) => Err(err.into()),
+        }
+    };
+    let result = match result {
+        Ok(hashes) => txn.commit().await.map(|_| hashes).map_err(anyhow::Error::from),
+        Err(err) => {
+            drop(txn);
+            Err(err)
+        }
+    };
+    let hashes = match result {
+        Ok(hashes) => hashes,
+        Err(err) => {
+            remove_unreferenced(db, user_path, &[stored.hash.clone()]).await;
+            return AppResponse::error(None, &err.to_string());
+        }
+    };
+    // pruned versions, a legacy file now lives in the blob store
+    remove_unreferenced(db, user_path, &hashes).await;
+    if model.hash.is_none() {
+        remove_path(old_file_path);
     }
+    model.size = stored.size;
+    model.hash = Some(stored.hash);
+    index_file(db, user_path, &model).await;
+    AppResponse::success(Some(model))
 }
 
 pub async fn update_file_name(
     db: &DatabaseConnection,
+    user_id: &str,
     body: &UpdateNameBody,
 ) -> AppResponse<Option<Model>> {
-    let get_result = FileService::get_file(db, &body.id).await;
-    if get_result.is_err() {
-        return AppResponse::error(None, &get_result.err().unwrap().to_string());
-    }
-    let option_model = get_result.unwrap();
-    if option_model.is_none() {
-        return AppResponse::error(None, "file not found");
-    }
-    let model = option_model.unwrap();
+    let model = match check_file(db, user_id, &body.id, Role::Editor).await {
+        Ok(model) => model,
+        Err(err) => return err.response(None),
+    };
     match FileService::update_file_name(db, &body.id, &body.name).await {
-        Ok(_) => AppResponse::success(Some(model)),
+        Ok(_) => {
+            rename_file(db, &body.id, &body.name).await;
+            AppResponse::success(Some(model))
+        }
         Err(err) => AppResponse::error(None, &err.to_string()),
     }
 }
 
 pub async fn update_file(
     db: &DatabaseConnection,
+    user_id: &str,
     body: &UpdateBody,
 ) -> AppResponse<Option<Model>> {
-    let get_result = FileService::get_file(db, &body.id).await;
-    if get_result.is_err() {
-        return AppResponse::error(None, &get_result.err().unwrap().to_string());
-    }
-    let option_model = get_result.unwrap();
-    if option_model.is_none() {
-        return AppResponse::error(None, "file not found");
+    let model = match check_file(db, user_id, &body.id, Role::Editor).await {
+        Ok(model) => model,
+        Err(err) => return err.response(None),
+    };
+    // file can only be moved inside its workspace
+    if let Err(err) = check_parent(db, user_id, &model.wid, &body.pid).await {
+        return err.response(None);
     }
-    let model = option_model.unwrap();
     match FileService::update_file(db, &body.id, &body.name, &body.pid).await {
-        Ok(_) => AppResponse::success(Some(model)),
+        Ok(_) => {
+            rename_file(db, &body.id, &body.name).await;
+            AppResponse::success(Some(model))
+        }
         Err(err) => AppResponse::error(None, &err.to_string()),
     }
 }
 
 pub async fn delete_file(
     db: &DatabaseConnection,
+    user_id: &str,
     general_body: &GeneralBody,
 ) -> AppResponse<String> {
-    // todo if exist files, do not allow to delete
-    match FileService::delete_file(db, &general_body.id).await {
+    let model = match check_file(db, user_id, &general_body.id, Role::Editor).await {
+        Ok(model) => model,
+        Err(err) => return err.response("".to_string()),
+    };
+    if model.state != FILE_STATE_NORMAL {
+        return AppResponse::error("".to_string(), "file is already in trash");
+    }
+    // move the whole sub tree to trash, blobs are kept until purged
+    let ids = match list_tree_ids(db, &model.id, FILE_STATE_NORMAL, &None).await {
+        Ok(ids) => ids,
+        Err(err) => return AppResponse::error("".to_string(), &err.to_string()),
+    };
+    let delete_time = Some(Utc::now().timestamp());
+    let trash_id = Some(Uuid::new_v4().to_string());
+    let result =
+        FileService::update_files_state(db, &ids, FILE_STATE_TRASHED, delete_time, trash_id).await;
+    match result {
         Ok(_) => AppResponse::success("".to_string()),
         Err(err) => AppResponse::error("".to_string(), &err.to_string()),
     }
 }
 
+// parent is the workspace root or a file of the same workspace
+async fn check_parent(
+    db: &DatabaseConnection,
+    user_id: &str,
+    wid: &str,
+    pid: &str,
+) -> Result<(), AccessError> {
+    check_workspace(db, user_id, wid, Role::Editor).await?;
+    if pid.is_empty() || pid == wid {
+        return Ok(());
+    }
+    match check_file(db, user_id, pid, Role::Editor).await? {
+        parent if parent.wid == wid => Ok(()),
+        _ => Err(AccessError::Forbidden("file")),
+    }
+}
+
+// ids of the file and all its descendants in state, items trashed by an other delete are skipped
+async fn list_tree_ids(
+    db: &DatabaseConnection,
+    id: &str,
+    state: i8,
+    trash_id: &Option<String>,
+) -> Result<Vec<String>, DbErr> {
+    let mut ids = vec![id.to_string()];
+    let mut visited: HashSet<String> = HashSet::from([id.to_string()]);
+    let mut pids = vec![id.to_string()];
+    while !pids.is_empty() {
+        let children = FileService::list_children(db, &pids, state).await?;
+        pids = vec![];
+        for child in children {
+            if child.trash_id != *trash_id || !visited.insert(child.id.clone()) {
+                continue;
+            }
+            ids.push(child.id.clone());
+            pids.push(child.id);
+        }
+    }
+    Ok(ids)
+}
+
+pub async fn list_trash(
+    db: &DatabaseConnection,
+    user_id: &str,
+    body: &TrashBody,
+) -> AppResponse<Vec<Model>> {
+    if let Err(err) = check_workspace(db, user_id, &body.wid, Role::Viewer).await {
+        return err.response(vec![]);
+    }
+    let models = match FileService::list_trash(db, &body.wid).await {
+        Ok(models) => models,
+        Err(err) => return AppResponse::error(vec![], &err.to_string()),
+    };
+    // only roots of each delete, their children come back with them
+    let trash_ids: HashMap<String, Option<String>> = models
+        .iter()
+        .map(|model| (model.id.clone(), model.trash_id.clone()))
+        .collect();
+    AppResponse::success(
+        models
+            .into_iter()
+            .filter(|model| trash_ids.get(&model.pid) != Some(&model.trash_id))
+            .collect(),
+    )
+}
+
+pub async fn restore_file(
+    db: &DatabaseConnection,
+    user_id: &str,
+    general_body: &GeneralBody,
+) -> AppResponse<Option<Model>> {
+    let mut model = match check_file(db, user_id, &general_body.id, Role::Editor).await {
+        Ok(model) => model,
+        Err(err) => return err.response(None),
+    };
+    if model.state != FILE_STATE_TRASHED {
+        return AppResponse::error(None, "file is not in trash");
+    }
+    let ids = match list_tree_ids(db, &model.id, FILE_STATE_TRASHED, &model.trash_id).await {
+        Ok(ids) => ids,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    // parent is gone or still in trash, restore to workspace root
+    let parent_exists = match FileService::get_file(db, &model.pid).await {
+        Ok(parent) => parent.is_some_and(|parent| parent.state == FILE_STATE_NORMAL),
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let parent_is_root = model.pid.is_empty() || model.pid == model.wid;
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    if !parent_exists && !parent_is_root {
+        if let Err(err) = FileService::update_file_pid(&txn, &model.id, "").await {
+            return AppResponse::error(None, &err.to_string());
+        }
+        model.pid = "".to_string();
+    }
+    let result = FileService::update_files_state(&txn, &ids, FILE_STATE_NORMAL, None, None).await;
+    if let Err(err) = result {
+        return AppResponse::error(None, &err.to_string());
+    }
+    match txn.commit().await {
+        Ok(_) => {
+            model.state = FILE_STATE_NORMAL;
+            model.delete_time = None;
+            model.trash_id = None;
+            AppResponse::success(Some(model))
+        }
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+pub async fn empty_trash(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    user_id: &str,
+    body: &TrashBody,
+) -> AppResponse<u64> {
+    if let Err(err) = check_workspace(db, user_id, &body.wid, Role::Editor).await {
+        return err.response(0);
+    }
+    match FileService::list_trash(db, &body.wid).await {
+        Ok(models) => remove_files(db, user_path, models).await,
+        Err(err) => AppResponse::error(0, &err.to_string()),
+    }
+}
+
+/// Remove trashed files deleted more than retention seconds ago.
+pub async fn purge_trash(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    retention_seconds: i64,
+) -> AppResponse<u64> {
+    let delete_before = Utc::now().timestamp() - retention_seconds;
+    match FileService::list_expired_trash(db, delete_before).await {
+        Ok(models) => remove_files(db, user_path, models).await,
+        Err(err) => AppResponse::error(0, &err.to_string()),
+    }
+}
+
+// rows go first, a blob left by a failed remove is only garbage on disk
+async fn remove_files(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    models: Vec<Model>,
+) -> AppResponse<u64> {
+    if models.is_empty() {
+        return AppResponse::success(0);
+    }
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(err) => return AppResponse::error(0, &err.to_string()),
+    };
+    let hashes = match delete_file_rows(&txn, &models).await {
+        Ok(hashes) => hashes,
+        Err(err) => return AppResponse::error(0, &err.to_string()),
+    };
+    if let Err(err) = txn.commit().await {
+        return AppResponse::error(0, &err.to_string());
+    }
+    clean_removed_files(db, user_path, &models, &hashes).await;
+    AppResponse::success(models.len() as u64)
+}
+
+/// Delete rows of the files and their versions, hashes of the blobs no longer referenced are returned.
+pub async fn delete_file_rows<C: ConnectionTrait>(
+    txn: &C,
+    models: &[Model],
+) -> Result<Vec<String>, DbErr> {
+    let ids: Vec<String> = models.iter().map(|model| model.id.clone()).collect();
+    FileService::delete_files(txn, &ids).await?;
+    ChatSettingService::delete_settings(txn, &ids).await?;
+    let mut hashes = remove_file_versions(txn, &ids).await?;
+    for model in models {
+        if let Some(hash) = &model.hash {
+            if release(txn, hash).await? {
+                hashes.push(hash.clone());
+            }
+        }
+    }
+    Ok(hashes)
+}
+
+/// Remove what is left on disk and in the index once the deleted rows are committed.
+pub async fn clean_removed_files(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    models: &[Model],
+    hashes: &[String],
+) {
+    remove_unreferenced(db, user_path, hashes).await;
+    let ids: Vec<String> = models.iter().map(|model| model.id.clone()).collect();
+    remove_indexed_files(db, &ids).await;
+    for model in models.iter().filter(|model| model.hash.is_none()) {
+        remove_path(&file_path(user_path, model));
+    }
+}
+
+/// Move all files of a workspace to an other one, files written before the blob store are
+/// adopted first as their content lives in the directory of the workspace. Chat transcripts
+/// stay out of the blob store and are copied to the directory of the target workspace.
+pub async fn move_workspace_files(
+    txn: &DatabaseTransaction,
+    user_path: &PathBuf,
+    from_wid: &str,
+    to_wid: &str,
+) -> Result<u64, anyhow::Error> {
+    for model in FileService::list_files_by_wid(txn, from_wid).await? {
+        // dirs have no content but are expected on disk at wid/id
+        if model.r#type == DIR_TYPE {
+            fs::create_dir_all(user_path.join(to_wid).join(&model.id))?;
+            continue;
+        }
+        let legacy = model.hash.is_none();
+        let path = file_path(user_path, &model);
+        if !legacy || !path.exists() {
+            continue;
+        }
+        if model.zone == CHAT_ZONE {
+            copy_atomic(&path, &user_path.join(to_wid).join(&model.id))?;
+        } else {
+            adopt_legacy_file(txn, user_path, &model).await?;
+        }
+    }
+    Ok(FileService::move_workspace_files(txn, from_wid, to_wid).await?)
+}
+
 pub async fn list_workspace_zones(db: &DatabaseConnection, wid: &str) -> AppResponse<Vec<String>> {
     match FileService::list_workspace_zones(db, wid).await {
         Ok(result) => AppResponse::success(result),
@@ -314,16 +657,28 @@ pub async fn list_workspace_zones(db: &DatabaseConnection, wid: &str) -> AppResp
 #[cfg(test)]
 mod test {
     use std::env::temp_dir;
+    use std::fs;
 
-    use sea_orm::{ConnectionTrait, Schema};
+    use sea_orm::{ConnectionTrait, Schema, Set};
+    use sha2::{Digest, Sha256};
     use uuid::Uuid;
 
+    use crate::dao::blob_dao::BlobService;
+    use crate::dao::file_dao::FileService;
+    use crate::dao::workspace_member_dao::WorkspaceMemberService;
+    use crate::entity::file::ActiveModel;
+    use crate::entity::workspace_member;
+    use crate::service::blob_service::{blob_path, file_path as content_path};
+    use crate::service::permission_service::Role;
     use crate::service::file_service::{
-        create_file, delete_file, get_file, get_workspace_files, list_workspace_zones, CreateBody,
-        GeneralBody, ListGeneralBody,
+        copy_file, create_file, delete_file, empty_trash, get_file, get_path, get_workspace_files,
+        get_workspace_files_by_pid, list_trash, list_workspace_zones, purge_trash, restore_file,
+        update_file_content, CopyBody, CreateBody, GeneralBody, ListByPidBody, ListGeneralBody,
+        TrashBody, UpdateContentBody,
     };
+    use crate::service::workspace_service::create_workspace;
     use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
-    use crate::{entity, DIR_TYPE, FILE_TYPE};
+    use crate::{entity, VersionSettings, BLOB_PATH, DIR_TYPE, FILE_TYPE};
 
     #[tokio::test]
     async fn test_files() {
@@ -341,8 +696,21 @@ mod test {
         db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::File)))
             .await
             .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Workspace)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Blob)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::FileVersion)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::ChatSetting)))
+            .await
+            .unwrap();
         // create workspace file,not do this
-        let wid = Uuid::new_v4().to_string();
+        let user_id = "test-user";
+        let wid = create_workspace(db, user_id, "test").await.result.unwrap().id;
         let zone_1 = "zone-1";
         let zone_2 = "zone-2";
         // create dir
@@ -350,6 +718,7 @@ mod test {
         let mut dir_model = create_file(
             db,
             user_path,
+            user_id,
             &CreateBody {
                 name: zone_1_dir_1.to_string(),
                 pid: "".to_string(),
@@ -369,6 +738,7 @@ mod test {
         // get dir
         dir_model = get_file(
             db,
+            user_id,
             &GeneralBody {
                 wid: wid.clone(),
                 id: dir_model.id.clone(),
@@ -384,6 +754,7 @@ mod test {
         let file_model = create_file(
             db,
             user_path,
+            user_id,
             &CreateBody {
                 name: "test_file".to_string(),
                 pid: dir_model.id.clone(),
@@ -400,6 +771,7 @@ mod test {
         // get all workspace files include file & dir
         let zone_1_models = get_workspace_files(
             db,
+            user_id,
             &ListGeneralBody {
                 wid: wid.clone(),
                 zone: zone_1.to_string(),
@@ -415,6 +787,7 @@ mod test {
         assert_eq!(zone_1, zones[0]);
         let zone_2_models = get_workspace_files(
             db,
+            user_id,
             &ListGeneralBody {
                 wid: wid.clone(),
                 zone: zone_2.to_string(),
@@ -427,6 +800,7 @@ mod test {
         // get all workspace files
         let zone_1_file_models = get_workspace_files(
             db,
+            user_id,
             &ListGeneralBody {
                 wid: wid.clone(),
                 zone: zone_1.to_string(),
@@ -438,6 +812,7 @@ mod test {
         assert_eq!(1, zone_1_file_models.len());
         let zone_2_file_models = get_workspace_files(
             db,
+            user_id,
             &ListGeneralBody {
                 wid: wid.clone(),
                 zone: zone_2.to_string(),
@@ -450,6 +825,7 @@ mod test {
         // get all workspace dirs
         let zone_1_dir_models = get_workspace_files(
             db,
+            user_id,
             &ListGeneralBody {
                 wid: wid.clone(),
                 zone: zone_1.to_string(),
@@ -464,6 +840,7 @@ mod test {
         // delete file
         let delete_file_response = delete_file(
             db,
+            user_id,
             &GeneralBody {
                 wid: wid.clone(),
                 id: file_model.id.clone(),
@@ -474,6 +851,7 @@ mod test {
         // delete dir
         let delete_dir_response = delete_file(
             db,
+            user_id,
             &GeneralBody {
                 wid: wid.clone(),
                 id: dir_model.id.clone(),
@@ -484,6 +862,7 @@ mod test {
         // get all files include dir
         let zone_1_after_delete_models = get_workspace_files(
             db,
+            user_id,
             &ListGeneralBody {
                 wid: wid.clone(),
                 zone: zone_1.to_string(),
@@ -494,4 +873,540 @@ mod test {
         .result;
         assert_eq!(0, zone_1_after_delete_models.len());
     }
+
+    #[tokio::test]
+    async fn test_files_io_failure() {
+        let temp_dir = temp_dir();
+        let base_path = &temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-file-io-failure");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        fs::create_dir_all(user_path).unwrap();
+        let file_path = &base_path.join("test-file-io-failure.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        let builder = db.get_database_backend();
+        let schema = Schema::new(builder);
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::File)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Workspace)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Blob)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::FileVersion)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::ChatSetting)))
+            .await
+            .unwrap();
+        let user_id = "test-user";
+        let wid = create_workspace(db, user_id, "test").await.result.unwrap().id;
+        let list_body = &ListGeneralBody {
+            wid: wid.clone(),
+            zone: "".to_string(),
+            r#type: None,
+        };
+        let create_body = CreateBody {
+            name: "test_file".to_string(),
+            pid: "".to_string(),
+            wid: wid.clone(),
+            r#type: FILE_TYPE.to_string(),
+            zone: "".to_string(),
+            content: Some(b"hello".to_vec()),
+            path: None,
+        };
+        // 1. workspace dir and blob store are regular files, disk step fails and row is rolled back
+        fs::write(user_path.join(&wid), b"").unwrap();
+        fs::write(user_path.join(BLOB_PATH), b"").unwrap();
+        for r#type in [DIR_TYPE, FILE_TYPE] {
+            let body = CreateBody {
+                r#type: r#type.to_string(),
+                ..create_body.clone()
+            };
+            assert!(create_file(db, user_path, user_id, &body).await.is_error());
+        }
+        assert_eq!(0, get_workspace_files(db, user_id, list_body).await.result.len());
+        fs::remove_file(user_path.join(&wid)).unwrap();
+        fs::remove_file(user_path.join(BLOB_PATH)).unwrap();
+        // 2. source path is missing
+        let body = CreateBody {
+            content: None,
+            path: Some(user_path.join("missing").to_string_lossy().to_string()),
+            ..create_body.clone()
+        };
+        assert!(create_file(db, user_path, user_id, &body).await.is_error());
+        assert_eq!(0, get_workspace_files(db, user_id, list_body).await.result.len());
+        // 3. create and copy
+        let model = create_file(db, user_path, user_id, &create_body)
+            .await
+            .result
+            .unwrap();
+        assert_eq!(5, model.size);
+        let copy_body = &CopyBody {
+            from_id: model.id.clone(),
+            name: "test_file_copy".to_string(),
+            pid: "".to_string(),
+        };
+        let copy_model = copy_file(db, user_path, user_id, copy_body)
+            .await
+            .result
+            .unwrap();
+        assert_eq!(5, copy_model.size);
+        assert_eq!(2, get_workspace_files(db, user_id, list_body).await.result.len());
+        // 4. blob dir of new content is occupied by a file, size and hash are unchanged
+        let hash = format!("{:x}", Sha256::digest(b"hello world"));
+        let blob_dir = blob_path(user_path, &hash).parent().unwrap().to_path_buf();
+        fs::write(&blob_dir, b"").unwrap();
+        assert!(update_file_content(
+            db,
+            user_path,
+            &VersionSettings::default(),
+            user_id,
+            &UpdateContentBody {
+                id: model.id.clone(),
+                content: b"hello world".to_vec(),
+            },
+        )
+        .await
+        .is_error());
+        let general_body = &GeneralBody {
+            wid: wid.clone(),
+            id: model.id.clone(),
+        };
+        let get_model = get_file(db, user_id, general_body).await.result.unwrap();
+        assert_eq!(5, get_model.size);
+        assert_eq!(model.hash, get_model.hash);
+        fs::remove_file(&blob_dir).unwrap();
+        // 5. source is missing on disk
+        fs::remove_file(blob_path(user_path, model.hash.as_ref().unwrap())).unwrap();
+        assert!(copy_file(db, user_path, user_id, copy_body).await.is_error());
+        assert_eq!(2, get_workspace_files(db, user_id, list_body).await.result.len());
+    }
+
+    #[tokio::test]
+    async fn test_files_dedup() {
+        let temp_dir = temp_dir();
+        let base_path = &temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-file-dedup");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        let file_path = &base_path.join("test-file-dedup.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        let builder = db.get_database_backend();
+        let schema = Schema::new(builder);
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::File)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Workspace)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Blob)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::FileVersion)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::ChatSetting)))
+            .await
+            .unwrap();
+        let user_id = "test-user";
+        let wid = create_workspace(db, user_id, "test").await.result.unwrap().id;
+        let create_body = &CreateBody {
+            name: "test_file".to_string(),
+            pid: "".to_string(),
+            wid: wid.clone(),
+            r#type: FILE_TYPE.to_string(),
+            zone: "".to_string(),
+            content: Some(b"hello".to_vec()),
+            path: None,
+        };
+        let general_body = |id: &str| GeneralBody {
+            wid: wid.clone(),
+            id: id.to_string(),
+        };
+        let ref_count = |hash: String| async move {
+            BlobService::get_blob(db, &hash)
+                .await
+                .unwrap()
+                .map_or(0, |blob| blob.ref_count)
+        };
+        // 1. identical uploads share one blob, copy only adds a reference
+        let first = create_file(db, user_path, user_id, create_body).await.result.unwrap();
+        let second = create_file(db, user_path, user_id, create_body).await.result.unwrap();
+        let hash = first.hash.clone().unwrap();
+        assert_eq!(first.hash, second.hash);
+        let copy = copy_file(
+            db,
+            user_path,
+            user_id,
+            &CopyBody {
+                from_id: first.id.clone(),
+                name: "test_file_copy".to_string(),
+                pid: "".to_string(),
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        assert_eq!(first.hash, copy.hash);
+        assert_eq!(3, ref_count(hash.clone()).await);
+        let path = get_path(db, user_path, user_id, &general_body(&copy.id)).await.result;
+        assert_eq!(blob_path(user_path, &hash).to_str().unwrap(), path);
+        // 2. update references the new content, the old one is kept by a version
+        let updated = update_file_content(
+            db,
+            user_path,
+            &VersionSettings::default(),
+            user_id,
+            &UpdateContentBody {
+                id: second.id.clone(),
+                content: b"hello world".to_vec(),
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        assert_eq!(3, ref_count(hash.clone()).await);
+        assert_eq!(1, ref_count(updated.hash.clone().unwrap()).await);
+        // 3. file written before the blob store is adopted on copy
+        let legacy = FileService::create_file(
+            db,
+            ActiveModel {
+                id: Set(Uuid::new_v4().to_string()),
+                name: Set("legacy".to_string()),
+                r#type: Set(FILE_TYPE.to_string()),
+                pid: Set("".to_string()),
+                wid: Set(wid.clone()),
+                zone: Set("".to_string()),
+                size: Set(6),
+                create_time: Set(0),
+                update_time: Set(0),
+                state: Set(1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let legacy_path = &user_path.join(&wid).join(&legacy.id);
+        fs::create_dir_all(user_path.join(&wid)).unwrap();
+        fs::write(legacy_path, b"legacy").unwrap();
+        let legacy_copy = copy_file(
+            db,
+            user_path,
+            user_id,
+            &CopyBody {
+                from_id: legacy.id.clone(),
+                name: "legacy_copy".to_string(),
+                pid: "".to_string(),
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        let legacy = get_file(db, user_id, &general_body(&legacy.id)).await.result.unwrap();
+        assert!(legacy.hash.is_some());
+        assert_eq!(legacy.hash, legacy_copy.hash);
+        assert_eq!(2, ref_count(legacy.hash.clone().unwrap()).await);
+        assert!(!legacy_path.exists());
+        // 4. blob is removed with its last reference
+        for model in [&first, &second, &copy, &legacy, &legacy_copy] {
+            assert!(delete_file(db, user_id, &general_body(&model.id)).await.is_success());
+        }
+        assert_eq!(
+            5,
+            empty_trash(db, user_path, user_id, &TrashBody { wid: wid.clone() })
+                .await
+                .result
+        );
+        assert_eq!(0, ref_count(hash.clone()).await);
+        assert!(!blob_path(user_path, &hash).exists());
+        assert!(!blob_path(user_path, &updated.hash.unwrap()).exists());
+    }
+
+    #[tokio::test]
+    async fn test_files_trash() {
+        let temp_dir = temp_dir();
+        let base_path = &temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-file-trash");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        let file_path = &base_path.join("test-file-trash.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        let builder = db.get_database_backend();
+        let schema = Schema::new(builder);
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::File)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Workspace)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Blob)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::FileVersion)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::ChatSetting)))
+            .await
+            .unwrap();
+        let user_id = "test-user";
+        let wid = create_workspace(db, user_id, "test").await.result.unwrap().id;
+        let create_body = CreateBody {
+            name: "".to_string(),
+            pid: "".to_string(),
+            wid: wid.clone(),
+            r#type: DIR_TYPE.to_string(),
+            zone: "".to_string(),
+            content: None,
+            path: None,
+        };
+        let list_body = &ListGeneralBody {
+            wid: wid.clone(),
+            zone: "".to_string(),
+            r#type: None,
+        };
+        let trash_body = &TrashBody { wid: wid.clone() };
+        let general_body = |id: &str| GeneralBody {
+            wid: wid.clone(),
+            id: id.to_string(),
+        };
+        // dir > sub_dir > file, and a sibling file
+        let dir = create_file(db, user_path, user_id, &create_body).await.result.unwrap();
+        let sub_dir = create_file(
+            db,
+            user_path,
+            user_id,
+            &CreateBody {
+                pid: dir.id.clone(),
+                ..create_body.clone()
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        let file = create_file(
+            db,
+            user_path,
+            user_id,
+            &CreateBody {
+                pid: sub_dir.id.clone(),
+                r#type: FILE_TYPE.to_string(),
+                content: Some(b"hello".to_vec()),
+                ..create_body.clone()
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        let sibling = create_file(
+            db,
+            user_path,
+            user_id,
+            &CreateBody {
+                r#type: FILE_TYPE.to_string(),
+                ..create_body.clone()
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        // 1. delete dir moves the whole tree to trash
+        assert!(delete_file(db, user_id, &general_body(&dir.id)).await.is_success());
+        assert_eq!(1, get_workspace_files(db, user_id, list_body).await.result.len());
+        let trash = list_trash(db, user_id, trash_body).await.result;
+        assert_eq!(1, trash.len());
+        assert_eq!(dir.id, trash[0].id);
+        assert!(delete_file(db, user_id, &general_body(&file.id)).await.is_error());
+        // 2. restore brings the tree back
+        assert!(restore_file(db, user_id, &general_body(&dir.id)).await.is_success());
+        assert_eq!(4, get_workspace_files(db, user_id, list_body).await.result.len());
+        assert_eq!(0, list_trash(db, user_id, trash_body).await.result.len());
+        // 3. file deleted on its own stays in trash when its dir is restored
+        assert!(delete_file(db, user_id, &general_body(&file.id)).await.is_success());
+        assert!(delete_file(db, user_id, &general_body(&dir.id)).await.is_success());
+        assert_eq!(2, list_trash(db, user_id, trash_body).await.result.len());
+        assert!(restore_file(db, user_id, &general_body(&dir.id)).await.is_success());
+        assert_eq!(3, get_workspace_files(db, user_id, list_body).await.result.len());
+        // 4. restore file whose parent is in trash goes to workspace root
+        assert!(delete_file(db, user_id, &general_body(&dir.id)).await.is_success());
+        let restored = restore_file(db, user_id, &general_body(&file.id))
+            .await
+            .result
+            .unwrap();
+        assert_eq!("", restored.pid);
+        assert_eq!(2, get_workspace_files(db, user_id, list_body).await.result.len());
+        // 5. empty trash removes rows and blobs
+        let dir_path = user_path.join(&wid).join(&dir.id);
+        assert!(dir_path.exists());
+        assert_eq!(2, empty_trash(db, user_path, user_id, trash_body).await.result);
+        assert!(!dir_path.exists());
+        assert!(get_file(db, user_id, &general_body(&sub_dir.id)).await.result.is_none());
+        // 6. purge after retention
+        assert!(delete_file(db, user_id, &general_body(&sibling.id)).await.is_success());
+        assert_eq!(0, purge_trash(db, user_path, 60).await.result);
+        assert_eq!(1, purge_trash(db, user_path, 0).await.result);
+        assert!(!content_path(user_path, &sibling).exists());
+        assert!(content_path(user_path, &file).exists());
+    }
+
+    #[tokio::test]
+    async fn test_files_permission() {
+        let temp_dir = temp_dir();
+        let base_path = &temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-file-permission");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        let file_path = &base_path.join("test-file-permission.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        let builder = db.get_database_backend();
+        let schema = Schema::new(builder);
+        for statement in [
+            schema.create_table_from_entity(entity::prelude::File),
+            schema.create_table_from_entity(entity::prelude::Workspace),
+            schema.create_table_from_entity(entity::prelude::Blob),
+            schema.create_table_from_entity(entity::prelude::FileVersion),
+            schema.create_table_from_entity(entity::prelude::WorkspaceMember),
+            schema.create_table_from_entity(entity::prelude::ChatSetting),
+        ] {
+            db.execute(builder.build(&statement)).await.unwrap();
+        }
+        let user_a = "user-a";
+        let user_b = "user-b";
+        let wid_a = create_workspace(db, user_a, "a").await.result.unwrap().id;
+        let wid_b = create_workspace(db, user_b, "b").await.result.unwrap().id;
+        let create_body = CreateBody {
+            name: "secret".to_string(),
+            pid: "".to_string(),
+            wid: wid_b.clone(),
+            r#type: FILE_TYPE.to_string(),
+            zone: "".to_string(),
+            content: Some(b"secret".to_vec()),
+            path: None,
+        };
+        let file_b = create_file(db, user_path, user_b, &create_body)
+            .await
+            .result
+            .unwrap();
+        let general_body = &GeneralBody {
+            wid: wid_b.clone(),
+            id: file_b.id.clone(),
+        };
+        // 1. user a can not read files of user b
+        assert!(get_file(db, user_a, general_body).await.is_forbidden());
+        assert!(get_path(db, user_path, user_a, general_body).await.is_forbidden());
+        let list_body = &ListGeneralBody {
+            wid: wid_b.clone(),
+            zone: "".to_string(),
+            r#type: None,
+        };
+        assert!(get_workspace_files(db, user_a, list_body).await.is_forbidden());
+        let pid_body = &ListByPidBody {
+            pid: "".to_string(),
+            r#type: None,
+        };
+        assert!(get_workspace_files_by_pid(db, user_a, pid_body)
+            .await
+            .result
+            .is_empty());
+        assert_eq!(
+            1,
+            get_workspace_files_by_pid(db, user_b, pid_body)
+                .await
+                .result
+                .len()
+        );
+        // 2. nor write, copy or create into them
+        assert!(create_file(db, user_path, user_a, &create_body)
+            .await
+            .is_forbidden());
+        let mut body_a = create_body.clone();
+        body_a.wid = wid_a.clone();
+        body_a.pid = file_b.id.clone();
+        assert!(create_file(db, user_path, user_a, &body_a).await.is_forbidden());
+        let copy_body = &CopyBody {
+            from_id: file_b.id.clone(),
+            name: "copy".to_string(),
+            pid: "".to_string(),
+        };
+        assert!(copy_file(db, user_path, user_a, copy_body)
+            .await
+            .is_forbidden());
+        let update_body = &UpdateContentBody {
+            id: file_b.id.clone(),
+            content: b"hacked".to_vec(),
+        };
+        assert!(
+            update_file_content(db, user_path, &VersionSettings::default(), user_a, update_body)
+                .await
+                .is_forbidden()
+        );
+        // 3. nor delete them
+        assert!(delete_file(db, user_a, general_body).await.is_forbidden());
+        let trash_body = &TrashBody { wid: wid_b.clone() };
+        assert!(empty_trash(db, user_path, user_a, trash_body)
+            .await
+            .is_forbidden());
+        let file_b = get_file(db, user_b, general_body).await.result.unwrap();
+        assert_eq!(file_b.hash, Some(format!("{:x}", Sha256::digest(b"secret"))));
+        assert!(delete_file(db, user_b, general_body).await.is_success());
+        assert!(list_trash(db, user_a, trash_body).await.is_forbidden());
+        assert!(restore_file(db, user_a, general_body).await.is_forbidden());
+        assert!(restore_file(db, user_b, general_body).await.is_success());
+        // 4. viewers of the shared workspace read but can not write
+        let member = workspace_member::ActiveModel {
+            id: Set(Uuid::new_v4().to_string()),
+            wid: Set(wid_b.clone()),
+            uid: Set(user_a.to_string()),
+            role: Set(Role::Viewer.as_str().to_string()),
+            create_time: Set(0),
+            update_time: Set(0),
+        };
+        WorkspaceMemberService::create_member(db, member)
+            .await
+            .unwrap();
+        assert!(get_file(db, user_a, general_body).await.is_success());
+        assert_eq!(
+            1,
+            get_workspace_files_by_pid(db, user_a, pid_body)
+                .await
+                .result
+                .len()
+        );
+        assert!(
+            update_file_content(db, user_path, &VersionSettings::default(), user_a, update_body)
+                .await
+                .is_forbidden()
+        );
+        assert!(delete_file(db, user_a, general_body).await.is_forbidden());
+        // 5. editors write
+        WorkspaceMemberService::update_role(db, &wid_b, user_a, Role::Editor.as_str())
+            .await
+            .unwrap();
+        assert!(
+            update_file_content(db, user_path, &VersionSettings::default(), user_a, update_body)
+                .await
+                .is_success()
+        );
+        assert!(create_file(db, user_path, user_a, &create_body)
+            .await
+            .is_success());
+    }
 }
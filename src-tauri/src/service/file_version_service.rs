@@ -0,0 +1,424 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::Utc;
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbErr, Set, TransactionTrait};
+use similar::TextDiff;
+use uuid::Uuid;
+
+use crate::dao::file_version_dao::FileVersionService;
+use crate::dto::file::{
+    UpdateContentBody, VersionBody, VersionContent, VersionDiffBody, VersionListBody,
+};
+use crate::entity::file;
+use crate::entity::file_version::{ActiveModel, Model};
+use crate::service::blob_service::{blob_path, file_path, put_file, release, remove_unreferenced};
+use crate::service::file_service::update_file_content;
+use crate::service::permission_service::{check_file, AccessError, Role};
+use crate::{AppResponse, VersionSettings};
+
+/// Keep the current content of the file as a version before it is replaced,
+/// return hashes of blobs released by pruning, remove them after commit.
+pub async fn keep_version<C: ConnectionTrait>(
+    db: &C,
+    user_path: &PathBuf,
+    settings: &VersionSettings,
+    model: &file::Model,
+) -> Result<Vec<String>, anyhow::Error> {
+    // the version takes over the reference of the file
+    let (hash, size) = match &model.hash {
+        Some(hash) => (hash.clone(), model.size),
+        None => {
+            let legacy_path = file_path(user_path, model);
+            if !legacy_path.exists() {
+                return Ok(vec![]);
+            }
+            let stored = put_file(db, user_path, &legacy_path).await?;
+            (stored.hash, stored.size)
+        }
+    };
+    let versions = FileVersionService::list_versions(db, &model.id).await?;
+    let version = versions.first().map_or(1, |latest| latest.version + 1);
+    let now = Utc::now().timestamp();
+    let created = FileVersionService::create_version(
+        db,
+        ActiveModel {
+            id: Set(Uuid::new_v4().to_string()),
+            fid: Set(model.id.clone()),
+            version: Set(version),
+            hash: Set(hash),
+            size: Set(size),
+            create_time: Set(now),
+        },
+    )
+    .await?;
+    let create_before = now - settings.max_age_days * 24 * 60 * 60;
+    let expired: Vec<Model> = std::iter::once(created)
+        .chain(versions)
+        .enumerate()
+        .filter(|(index, version)| {
+            *index as u64 >= settings.max_versions || version.create_time < create_before
+        })
+        .map(|(_, version)| version)
+        .collect();
+    Ok(remove_versions(db, &expired).await?)
+}
+
+/// Drop versions of the files, e.g. when they are removed from trash.
+pub async fn remove_file_versions<C: ConnectionTrait>(
+    db: &C,
+    fids: &[String],
+) -> Result<Vec<String>, DbErr> {
+    let versions = FileVersionService::list_versions_by_fids(db, fids).await?;
+    remove_versions(db, &versions).await
+}
+
+// delete rows and release blobs, return hashes no longer referenced
+async fn remove_versions<C: ConnectionTrait>(
+    db: &C,
+    versions: &[Model],
+) -> Result<Vec<String>, DbErr> {
+    if versions.is_empty() {
+        return Ok(vec![]);
+    }
+    let ids: Vec<String> = versions.iter().map(|version| version.id.clone()).collect();
+    FileVersionService::delete_versions(db, &ids).await?;
+    let mut hashes = vec![];
+    for version in versions {
+        if release(db, &version.hash).await? {
+            hashes.push(version.hash.clone());
+        }
+    }
+    Ok(hashes)
+}
+
+/// Remove versions older than max age, files which are no longer edited keep theirs until then.
+pub async fn purge_versions(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    settings: &VersionSettings,
+) -> AppResponse<u64> {
+    let create_before = Utc::now().timestamp() - settings.max_age_days * 24 * 60 * 60;
+    let versions = match FileVersionService::list_versions_before(db, create_before).await {
+        Ok(versions) => versions,
+        Err(err) => return AppResponse::error(0, &err.to_string()),
+    };
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(err) => return AppResponse::error(0, &err.to_string()),
+    };
+    let hashes = match remove_versions(&txn, &versions).await {
+        Ok(hashes) => hashes,
+        Err(err) => return AppResponse::error(0, &err.to_string()),
+    };
+    if let Err(err) = txn.commit().await {
+        return AppResponse::error(0, &err.to_string());
+    }
+    remove_unreferenced(db, user_path, &hashes).await;
+    AppResponse::success(versions.len() as u64)
+}
+
+// version the user may access through its file
+async fn check_version(
+    db: &DatabaseConnection,
+    user_id: &str,
+    id: &str,
+) -> Result<Model, AccessError> {
+    let version = match FileVersionService::get_version(db, id).await? {
+        Some(version) => version,
+        None => return Err(AccessError::NotFound("version")),
+    };
+    check_file(db, user_id, &version.fid, Role::Viewer).await?;
+    Ok(version)
+}
+
+pub async fn list_versions(
+    db: &DatabaseConnection,
+    user_id: &str,
+    body: &VersionListBody,
+) -> AppResponse<Vec<Model>> {
+    if let Err(err) = check_file(db, user_id, &body.fid, Role::Viewer).await {
+        return err.response(vec![]);
+    }
+    match FileVersionService::list_versions(db, &body.fid).await {
+        Ok(versions) => AppResponse::success(versions),
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+pub async fn get_version(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    user_id: &str,
+    body: &VersionBody,
+) -> AppResponse<Option<VersionContent>> {
+    let version = match check_version(db, user_id, &body.id).await {
+        Ok(version) => version,
+        Err(err) => return err.response(None),
+    };
+    match fs::read(blob_path(user_path, &version.hash)) {
+        Ok(content) => AppResponse::success(Some(VersionContent { version, content })),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+/// Make the version the current content, the replaced content is kept as a new version.
+pub async fn restore_version(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    settings: &VersionSettings,
+    user_id: &str,
+    body: &VersionBody,
+) -> AppResponse<Option<file::Model>> {
+    let response = get_version(db, user_path, user_id, body).await;
+    let version_content = match response.result {
+        Some(version_content) => version_content,
+        None => {
+            return AppResponse {
+                code: response.code,
+                r#type: response.r#type,
+                message: response.message,
+                result: None,
+            }
+        }
+    };
+    let update_body = UpdateContentBody {
+        id: version_content.version.fid,
+        content: version_content.content,
+    };
+    update_file_content(db, user_path, settings, user_id, &update_body).await
+}
+
+/// Unified line diff from one version to an other, or to the current content.
+pub async fn diff_versions(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    user_id: &str,
+    body: &VersionDiffBody,
+) -> AppResponse<String> {
+    let from = match check_version(db, user_id, &body.from_id).await {
+        Ok(version) => version,
+        Err(err) => return err.response("".to_string()),
+    };
+    let to_path = match &body.to_id {
+        Some(to_id) => match check_version(db, user_id, to_id).await {
+            Ok(version) => blob_path(user_path, &version.hash),
+            Err(err) => return err.response("".to_string()),
+        },
+        None => match check_file(db, user_id, &from.fid, Role::Viewer).await {
+            Ok(model) => file_path(user_path, &model),
+            Err(err) => return err.response("".to_string()),
+        },
+    };
+    let from_content = match fs::read(blob_path(user_path, &from.hash)) {
+        Ok(content) => String::from_utf8_lossy(&content).to_string(),
+        Err(err) => return AppResponse::error("".to_string(), &err.to_string()),
+    };
+    let to_content = match fs::read(to_path) {
+        Ok(content) => String::from_utf8_lossy(&content).to_string(),
+        Err(err) => return AppResponse::error("".to_string(), &err.to_string()),
+    };
+    let to_name = match &body.to_id {
+        Some(to_id) => to_id.clone(),
+        None => "current".to_string(),
+    };
+    AppResponse::success(
+        TextDiff::from_lines(&from_content, &to_content)
+            .unified_diff()
+            .header(&from.id, &to_name)
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+
+    use sea_orm::{ConnectionTrait, Schema};
+    use uuid::Uuid;
+
+    use crate::dao::blob_dao::BlobService;
+    use crate::dto::file::{
+        CreateBody, GeneralBody, TrashBody, UpdateContentBody, VersionBody, VersionDiffBody,
+        VersionListBody,
+    };
+    use crate::entity;
+    use crate::service::blob_service::blob_path;
+    use crate::service::file_service::{
+        create_file, delete_file, empty_trash, update_file_content,
+    };
+    use crate::service::file_version_service::{
+        diff_versions, get_version, list_versions, purge_versions, restore_version,
+    };
+    use crate::service::workspace_service::create_workspace;
+    use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+    use crate::{VersionSettings, FILE_TYPE};
+
+    #[tokio::test]
+    async fn test_file_versions() {
+        let temp_dir = temp_dir();
+        let base_path = &temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-file-version");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        let file_path = &base_path.join("test-file-version.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        let builder = db.get_database_backend();
+        let schema = Schema::new(builder);
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::File)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Workspace)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Blob)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::FileVersion)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::ChatSetting)))
+            .await
+            .unwrap();
+        let settings = &VersionSettings {
+            max_versions: 2,
+            max_age_days: 1,
+        };
+        let user_id = "test-user";
+        let wid = create_workspace(db, user_id, "test")
+            .await
+            .result
+            .unwrap()
+            .id;
+        let model = create_file(
+            db,
+            user_path,
+            user_id,
+            &CreateBody {
+                name: "test_file".to_string(),
+                pid: "".to_string(),
+                wid: wid.clone(),
+                r#type: FILE_TYPE.to_string(),
+                zone: "".to_string(),
+                content: Some(b"line 1\n".to_vec()),
+                path: None,
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        let first_hash = model.hash.clone().unwrap();
+        let update = |content: &str| UpdateContentBody {
+            id: model.id.clone(),
+            content: content.as_bytes().to_vec(),
+        };
+        let list_body = &VersionListBody {
+            fid: model.id.clone(),
+        };
+        // 1. each save keeps the replaced content, saving the same content keeps nothing
+        for content in ["line 1\nline 2\n", "line 1\nline 2\n", "line 1\nline 3\n"] {
+            assert!(
+                update_file_content(db, user_path, settings, user_id, &update(content))
+                    .await
+                    .is_success()
+            );
+        }
+        let versions = list_versions(db, user_id, list_body).await.result;
+        assert_eq!(2, versions.len());
+        assert_eq!(2, versions[0].version);
+        let version = get_version(
+            db,
+            user_path,
+            user_id,
+            &VersionBody {
+                id: versions[1].id.clone(),
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        assert_eq!(b"line 1\n".to_vec(), version.content);
+        // 2. diff to current content
+        let diff = diff_versions(
+            db,
+            user_path,
+            user_id,
+            &VersionDiffBody {
+                from_id: versions[0].id.clone(),
+                to_id: None,
+            },
+        )
+        .await
+        .result;
+        assert!(diff.contains("-line 2\n"));
+        assert!(diff.contains("+line 3\n"));
+        // 3. oldest version is dropped beyond max versions
+        assert!(
+            update_file_content(db, user_path, settings, user_id, &update("line 4\n"))
+                .await
+                .is_success()
+        );
+        let versions = list_versions(db, user_id, list_body).await.result;
+        assert_eq!(
+            vec![3, 2],
+            versions.iter().map(|v| v.version).collect::<Vec<_>>()
+        );
+        assert!(BlobService::get_blob(db, &first_hash)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(!blob_path(user_path, &first_hash).exists());
+        // 4. restore, current content becomes a new version
+        let restored = restore_version(
+            db,
+            user_path,
+            settings,
+            user_id,
+            &VersionBody {
+                id: versions[1].id.clone(),
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        assert_eq!(versions[1].hash, restored.hash.unwrap());
+        let versions = list_versions(db, user_id, list_body).await.result;
+        assert_eq!(4, versions[0].version);
+        // 5. versions past max age are purged
+        let expired = &VersionSettings {
+            max_versions: 2,
+            max_age_days: -1,
+        };
+        assert_eq!(2, purge_versions(db, user_path, expired).await.result);
+        assert!(list_versions(db, user_id, list_body)
+            .await
+            .result
+            .is_empty());
+        // 6. versions go with their file
+        assert!(
+            update_file_content(db, user_path, settings, user_id, &update("line 5\n"))
+                .await
+                .is_success()
+        );
+        let general_body = &GeneralBody {
+            wid: wid.clone(),
+            id: model.id.clone(),
+        };
+        delete_file(db, user_id, general_body).await;
+        assert_eq!(
+            1,
+            empty_trash(db, user_path, user_id, &TrashBody { wid })
+                .await
+                .result
+        );
+        assert!(list_versions(db, user_id, list_body)
+            .await
+            .result
+            .is_empty());
+    }
+}
@@ -0,0 +1,568 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::Utc;
+use log::error;
+use sea_orm::{DatabaseConnection, DbErr, Set};
+use uuid::Uuid;
+
+use crate::dao::blob_dao::BlobService;
+use crate::dao::file_dao::FileService;
+use crate::dao::file_version_dao::FileVersionService;
+use crate::dto::file::{FsckBody, FsckIssue, FsckIssueKind, FsckReport};
+use crate::entity::file::{ActiveModel, Model};
+use crate::service::blob_service::{blob_path, file_path, hash_file, remove_unreferenced};
+use crate::{
+    AppResponse, BLOB_PATH, CHAT_ZONE, DIR_TYPE, FILE_STATE_NORMAL, FILE_TYPE, LOST_FOUND_NAME,
+};
+
+fn is_root(model: &Model) -> bool {
+    // chat files use workspace id as parent
+    model.pid.is_empty() || model.pid == model.wid
+}
+
+fn issue(kind: FsckIssueKind, wid: &str, id: &str, message: String) -> FsckIssue {
+    FsckIssue {
+        kind,
+        wid: wid.to_string(),
+        id: id.to_string(),
+        message,
+        repaired: false,
+    }
+}
+
+/// Check the file table against blobs under user_path, optionally repair what can be repaired.
+/// Every workspace of every user is checked, so it is only run from the command line.
+pub async fn fsck(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    body: &FsckBody,
+) -> AppResponse<Option<FsckReport>> {
+    let models = match FileService::list_all_files(db).await {
+        Ok(models) => models,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let versions = match FileVersionService::list_all_versions(db).await {
+        Ok(versions) => versions,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let blobs = match BlobService::list_blobs(db).await {
+        Ok(blobs) => blobs,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let by_id: HashMap<&str, &Model> = models
+        .iter()
+        .map(|model| (model.id.as_str(), model))
+        .collect();
+    // references of every blob with its size, counted from file and version rows
+    let mut refs: HashMap<String, (i64, i64)> = HashMap::new();
+    for (hash, size) in models
+        .iter()
+        .filter_map(|model| model.hash.as_ref().map(|hash| (hash, model.size)))
+        .chain(versions.iter().map(|version| (&version.hash, version.size)))
+    {
+        refs.entry(hash.clone()).or_insert((0, size)).0 += 1;
+    }
+    let mut issues = vec![];
+    // 1. rows without blobs, size mismatch
+    for model in &models {
+        // chat files are written on first message and do not track size
+        if model.zone == CHAT_ZONE {
+            continue;
+        }
+        match fs::metadata(file_path(user_path, model)) {
+            Ok(metadata) if metadata.is_dir() != (model.r#type == DIR_TYPE) => {
+                issues.push(issue(
+                    FsckIssueKind::MissingBlob,
+                    &model.wid,
+                    &model.id,
+                    format!("blob is not a {}", model.r#type),
+                ));
+            }
+            Ok(metadata) if !metadata.is_dir() && metadata.len() as i64 != model.size => {
+                // a blob of another size than its row is corrupt unless its hash still matches
+                let corrupt = match &model.hash {
+                    Some(hash) => match hash_file(&file_path(user_path, model)) {
+                        Ok(actual) => (actual != *hash).then(|| format!("blob hash is {}", actual)),
+                        Err(err) => Some(err.to_string()),
+                    },
+                    None => None,
+                };
+                issues.push(match corrupt {
+                    Some(message) => {
+                        issue(FsckIssueKind::CorruptBlob, &model.wid, &model.id, message)
+                    }
+                    None => issue(
+                        FsckIssueKind::SizeMismatch,
+                        &model.wid,
+                        &model.id,
+                        format!("size is {}, blob size is {}", model.size, metadata.len()),
+                    ),
+                });
+            }
+            Ok(_) => {}
+            Err(err) => {
+                issues.push(issue(
+                    FsckIssueKind::MissingBlob,
+                    &model.wid,
+                    &model.id,
+                    err.to_string(),
+                ));
+            }
+        }
+    }
+    // 2. blobs without rows
+    let mut checked_blobs = 0u64;
+    if let Ok(workspace_entries) = fs::read_dir(user_path) {
+        for workspace_entry in workspace_entries.flatten() {
+            // blob store is checked against the blob table below
+            if !workspace_entry.path().is_dir() || workspace_entry.file_name() == BLOB_PATH {
+                continue;
+            }
+            let wid = workspace_entry.file_name().to_string_lossy().to_string();
+            let entries = match fs::read_dir(workspace_entry.path()) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    error!("read workspace dir {} failed, err: {}", wid, err);
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                checked_blobs += 1;
+                let id = entry.file_name().to_string_lossy().to_string();
+                if by_id.get(id.as_str()).is_some_and(|model| model.wid == wid) {
+                    continue;
+                }
+                issues.push(issue(
+                    FsckIssueKind::OrphanBlob,
+                    &wid,
+                    &id,
+                    "blob has no file row".to_string(),
+                ));
+            }
+        }
+    }
+    // 3. blob store against the blob table and the rows referencing it
+    let mut hashes: HashSet<String> = blobs
+        .iter()
+        .map(|blob| blob.hash.clone())
+        .chain(refs.keys().cloned())
+        .collect();
+    if let Ok(prefix_entries) = fs::read_dir(user_path.join(BLOB_PATH)) {
+        for prefix_entry in prefix_entries.flatten() {
+            let entries = match fs::read_dir(prefix_entry.path()) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    error!(
+                        "read blob dir {:?} failed, err: {}",
+                        prefix_entry.path(),
+                        err
+                    );
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                let hash = entry.file_name().to_string_lossy().to_string();
+                // temp file of a blob being written
+                if hash.starts_with('.') || hash.len() < 2 {
+                    continue;
+                }
+                checked_blobs += 1;
+                hashes.insert(hash);
+            }
+        }
+    }
+    let ref_counts: HashMap<&str, i64> = blobs
+        .iter()
+        .map(|blob| (blob.hash.as_str(), blob.ref_count))
+        .collect();
+    let mut hashes: Vec<String> = hashes.into_iter().collect();
+    hashes.sort();
+    for hash in hashes {
+        let actual = refs.get(&hash).map_or(0, |(count, _)| *count);
+        let ref_count = ref_counts.get(hash.as_str()).copied().unwrap_or(0);
+        if actual == 0 {
+            issues.push(issue(
+                FsckIssueKind::UnreferencedBlob,
+                "",
+                &hash,
+                format!("blob has ref count {}, no row references it", ref_count),
+            ));
+        } else if actual != ref_count {
+            issues.push(issue(
+                FsckIssueKind::RefCountMismatch,
+                "",
+                &hash,
+                format!("ref count is {}, referenced by {} rows", ref_count, actual),
+            ));
+        }
+    }
+    // 4. dangling parents
+    for model in &models {
+        if !is_root(model) && !by_id.contains_key(model.pid.as_str()) {
+            issues.push(issue(
+                FsckIssueKind::DanglingParent,
+                &model.wid,
+                &model.id,
+                format!("parent {} not found", model.pid),
+            ));
+        }
+    }
+    // 5. cycles, reported once at the node where they are found
+    let mut done: HashSet<&str> = HashSet::new();
+    for model in &models {
+        let mut path: Vec<&str> = vec![];
+        let mut current = model.id.as_str();
+        loop {
+            if done.contains(current) {
+                break;
+            }
+            if let Some(pos) = path.iter().position(|id| *id == current) {
+                issues.push(issue(
+                    FsckIssueKind::Cycle,
+                    &model.wid,
+                    current,
+                    format!("parent cycle: {}", path[pos..].join(" -> ")),
+                ));
+                break;
+            }
+            path.push(current);
+            match by_id.get(current) {
+                Some(current_model) if !is_root(current_model) => {
+                    current = current_model.pid.as_str()
+                }
+                _ => break,
+            }
+        }
+        done.extend(path);
+    }
+    if body.repair {
+        let mut lost_found_ids: HashMap<String, String> = models
+            .iter()
+            .filter(|model| {
+                model.name == LOST_FOUND_NAME
+                    && model.r#type == DIR_TYPE
+                    && model.pid.is_empty()
+                    && model.state == FILE_STATE_NORMAL
+            })
+            .map(|model| (model.wid.clone(), model.id.clone()))
+            .collect();
+        for issue in issues.iter_mut() {
+            match repair(db, user_path, &by_id, &refs, &mut lost_found_ids, issue).await {
+                Ok(repaired) => issue.repaired = repaired,
+                Err(err) => {
+                    error!(
+                        "repair {:?} of {} failed, err: {}",
+                        issue.kind, issue.id, err
+                    );
+                }
+            }
+        }
+    }
+    AppResponse::success(Some(FsckReport {
+        checked_files: models.len() as u64,
+        checked_blobs,
+        issues,
+    }))
+}
+
+async fn repair(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    by_id: &HashMap<&str, &Model>,
+    refs: &HashMap<String, (i64, i64)>,
+    lost_found_ids: &mut HashMap<String, String>,
+    issue: &FsckIssue,
+) -> Result<bool, anyhow::Error> {
+    let path = match by_id.get(issue.id.as_str()) {
+        Some(model) if model.wid == issue.wid => file_path(user_path, model),
+        _ => user_path.join(&issue.wid).join(&issue.id),
+    };
+    match issue.kind {
+        FsckIssueKind::MissingBlob => {
+            // content of a lost file can not be recovered, leave the row to the user
+            let model = by_id[issue.id.as_str()];
+            if model.r#type != DIR_TYPE || path.exists() {
+                return Ok(false);
+            }
+            fs::create_dir_all(&path)?;
+        }
+        FsckIssueKind::SizeMismatch => {
+            // size of a stored blob is part of its content, only legacy files at wid/id are resized
+            if by_id[issue.id.as_str()].hash.is_some() {
+                return Ok(false);
+            }
+            let size = fs::metadata(&path)?.len() as i64;
+            FileService::update_file_size(db, &issue.id, size).await?;
+        }
+        FsckIssueKind::OrphanBlob => {
+            let pid = lost_found(db, user_path, lost_found_ids, &issue.wid).await?;
+            let metadata = fs::metadata(&path)?;
+            let id = Uuid::new_v4().to_string();
+            fs::rename(&path, user_path.join(&issue.wid).join(&id))?;
+            let result = FileService::create_file(
+                db,
+                ActiveModel {
+                    id: Set(id.clone()),
+                    name: Set(issue.id.clone()),
+                    r#type: Set(if metadata.is_dir() {
+                        DIR_TYPE
+                    } else {
+                        FILE_TYPE
+                    }
+                    .to_string()),
+                    pid: Set(pid),
+                    wid: Set(issue.wid.clone()),
+                    zone: Set("".to_string()),
+                    size: Set(if metadata.is_dir() {
+                        0
+                    } else {
+                        metadata.len() as i64
+                    }),
+                    create_time: Set(Utc::now().timestamp()),
+                    update_time: Set(Utc::now().timestamp()),
+                    state: Set(FILE_STATE_NORMAL),
+                    ..Default::default()
+                },
+            )
+            .await;
+            if let Err(err) = result {
+                // put the blob back, it is still an orphan
+                fs::rename(user_path.join(&issue.wid).join(&id), &path)?;
+                return Err(err.into());
+            }
+        }
+        // content is lost, versions of the file may still hold a good copy
+        FsckIssueKind::CorruptBlob => return Ok(false),
+        FsckIssueKind::UnreferencedBlob => {
+            BlobService::set_ref_count(db, &issue.id, 0, 0).await?;
+            remove_unreferenced(db, user_path, &[issue.id.clone()]).await;
+            return Ok(!blob_path(user_path, &issue.id).exists());
+        }
+        FsckIssueKind::RefCountMismatch => {
+            let (count, size) = refs[&issue.id];
+            BlobService::set_ref_count(db, &issue.id, size, count).await?;
+        }
+        FsckIssueKind::DanglingParent | FsckIssueKind::Cycle => {
+            let pid = lost_found(db, user_path, lost_found_ids, &issue.wid).await?;
+            FileService::update_file_pid(db, &issue.id, &pid).await?;
+        }
+    }
+    Ok(true)
+}
+
+// lost+found dir of the workspace, created on first use
+async fn lost_found(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    lost_found_ids: &mut HashMap<String, String>,
+    wid: &str,
+) -> Result<String, DbErr> {
+    if let Some(id) = lost_found_ids.get(wid) {
+        return Ok(id.clone());
+    }
+    let model = FileService::create_file(
+        db,
+        ActiveModel {
+            id: Set(Uuid::new_v4().to_string()),
+            name: Set(LOST_FOUND_NAME.to_string()),
+            r#type: Set(DIR_TYPE.to_string()),
+            pid: Set("".to_string()),
+            wid: Set(wid.to_string()),
+            zone: Set("".to_string()),
+            size: Set(0),
+            create_time: Set(Utc::now().timestamp()),
+            update_time: Set(Utc::now().timestamp()),
+            state: Set(FILE_STATE_NORMAL),
+            ..Default::default()
+        },
+    )
+    .await?;
+    if let Err(err) = fs::create_dir_all(user_path.join(wid).join(&model.id)) {
+        return Err(DbErr::Custom(err.to_string()));
+    }
+    lost_found_ids.insert(wid.to_string(), model.id.clone());
+    Ok(model.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+
+    use sea_orm::{ConnectionTrait, Schema, Set};
+    use uuid::Uuid;
+
+    use crate::dao::blob_dao::BlobService;
+    use crate::dao::file_dao::FileService;
+    use crate::dto::file::{CreateBody, FsckBody, FsckIssueKind};
+    use crate::entity;
+    use crate::entity::file::ActiveModel;
+    use crate::service::blob_service::{blob_path, file_path as content_path};
+    use crate::service::file_service::create_file;
+    use crate::service::fsck_service::fsck;
+    use crate::service::workspace_service::create_workspace;
+    use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+    use crate::{DIR_TYPE, FILE_STATE_NORMAL, FILE_TYPE};
+
+    #[tokio::test]
+    async fn test_fsck() {
+        let temp_dir = temp_dir();
+        let base_path = &temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-fsck");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        let file_path = &base_path.join("test-fsck.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        let builder = db.get_database_backend();
+        let schema = Schema::new(builder);
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::File)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Workspace)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Blob)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::FileVersion)))
+            .await
+            .unwrap();
+        let user_id = "test-user";
+        let wid = create_workspace(db, user_id, "test")
+            .await
+            .result
+            .unwrap()
+            .id;
+        let create_body = CreateBody {
+            name: "file".to_string(),
+            pid: "".to_string(),
+            wid: wid.clone(),
+            r#type: FILE_TYPE.to_string(),
+            zone: "".to_string(),
+            content: Some(b"hello".to_vec()),
+            path: None,
+        };
+        let mut models = vec![];
+        for i in 0..5 {
+            let body = CreateBody {
+                content: Some(format!("hello {}", i).into_bytes()),
+                ..create_body.clone()
+            };
+            models.push(
+                create_file(db, user_path, user_id, &body)
+                    .await
+                    .result
+                    .unwrap(),
+            );
+        }
+        let dir = create_file(
+            db,
+            user_path,
+            user_id,
+            &CreateBody {
+                r#type: DIR_TYPE.to_string(),
+                ..create_body.clone()
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        // 1. clean store
+        let report = fsck(db, user_path, &FsckBody { repair: false })
+            .await
+            .result
+            .unwrap();
+        assert_eq!(0, report.issues.len());
+        // 2. break it
+        let workspace_path = &user_path.join(&wid);
+        fs::remove_file(content_path(user_path, &models[0])).unwrap();
+        fs::remove_dir(workspace_path.join(&dir.id)).unwrap();
+        fs::write(content_path(user_path, &models[1]), b"hello world").unwrap();
+        fs::write(workspace_path.join("orphan"), b"orphan").unwrap();
+        // file written before the blob store
+        let legacy_id = Uuid::new_v4().to_string();
+        FileService::create_file(
+            db,
+            ActiveModel {
+                id: Set(legacy_id.clone()),
+                name: Set("legacy".to_string()),
+                r#type: Set(FILE_TYPE.to_string()),
+                pid: Set("".to_string()),
+                wid: Set(wid.clone()),
+                zone: Set("".to_string()),
+                size: Set(5),
+                create_time: Set(0),
+                update_time: Set(0),
+                state: Set(FILE_STATE_NORMAL),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        fs::write(workspace_path.join(&legacy_id), b"hello world").unwrap();
+        let unreferenced = &blob_path(user_path, &"ab".repeat(32));
+        fs::create_dir_all(unreferenced.parent().unwrap()).unwrap();
+        fs::write(unreferenced, b"unreferenced").unwrap();
+        BlobService::set_ref_count(db, models[2].hash.as_ref().unwrap(), 7, 3)
+            .await
+            .unwrap();
+        FileService::update_file_pid(db, &models[2].id, "missing")
+            .await
+            .unwrap();
+        FileService::update_file_pid(db, &models[3].id, &models[4].id)
+            .await
+            .unwrap();
+        FileService::update_file_pid(db, &models[4].id, &models[3].id)
+            .await
+            .unwrap();
+        let report = fsck(db, user_path, &FsckBody { repair: false })
+            .await
+            .result
+            .unwrap();
+        let count = |kind: FsckIssueKind| {
+            report
+                .issues
+                .iter()
+                .filter(|issue| issue.kind == kind)
+                .count()
+        };
+        assert_eq!(2, count(FsckIssueKind::MissingBlob));
+        assert_eq!(1, count(FsckIssueKind::SizeMismatch));
+        assert_eq!(1, count(FsckIssueKind::CorruptBlob));
+        assert_eq!(1, count(FsckIssueKind::UnreferencedBlob));
+        assert_eq!(1, count(FsckIssueKind::RefCountMismatch));
+        assert_eq!(1, count(FsckIssueKind::OrphanBlob));
+        assert_eq!(1, count(FsckIssueKind::DanglingParent));
+        assert_eq!(1, count(FsckIssueKind::Cycle));
+        // 3. repair, only the lost and the corrupt file remain
+        let report = fsck(db, user_path, &FsckBody { repair: true })
+            .await
+            .result
+            .unwrap();
+        assert_eq!(
+            2,
+            report.issues.iter().filter(|issue| !issue.repaired).count()
+        );
+        let report = fsck(db, user_path, &FsckBody { repair: false })
+            .await
+            .result
+            .unwrap();
+        let mut ids: Vec<&str> = report
+            .issues
+            .iter()
+            .map(|issue| issue.id.as_str())
+            .collect();
+        ids.sort();
+        let mut expected = vec![models[0].id.as_str(), models[1].id.as_str()];
+        expected.sort();
+        assert_eq!(expected, ids);
+        assert!(!workspace_path.join("orphan").exists());
+        assert!(!unreferenced.exists());
+    }
+}
@@ -7,3 +7,14 @@ pub mod zone_service;
 pub mod setting_service;
 pub mod ai_source_service;
 pub mod ai_model_service;
+pub mod session_service;
+pub mod fsck_service;
+pub mod blob_service;
+pub mod file_version_service;
+pub mod search_service;
+pub mod permission_service;
+pub mod workspace_archive_service;
+pub mod backup_service;
+pub mod chat_provider_service;
+pub mod prompt_template_service;
+pub mod chat_tree_service;
@@ -0,0 +1,139 @@
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbErr};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::dao::file_dao::FileService;
+use crate::dao::user_dao::UserService;
+use crate::dao::workspace_dao::WorkspaceService;
+use crate::dao::workspace_member_dao::WorkspaceMemberService;
+use crate::entity::{file, workspace};
+use crate::{AppResponse, DEFAULT_USERNAME, LOCAL_USER_TYPE};
+
+#[derive(Error, Debug)]
+pub enum AccessError {
+    #[error("{0} not found")]
+    NotFound(&'static str),
+
+    #[error("no permission to access this {0}")]
+    Forbidden(&'static str),
+
+    #[error(transparent)]
+    Db(#[from] DbErr),
+}
+
+impl AccessError {
+    pub fn response<T>(&self, result: T) -> AppResponse<T> {
+        match self {
+            AccessError::Forbidden(_) => AppResponse::forbidden(result, &self.to_string()),
+            _ => AppResponse::error(result, &self.to_string()),
+        }
+    }
+}
+
+/// Role of a user in a workspace, each role may do everything the lower ones may.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // read files and chats
+    Viewer,
+    // create, change and delete files and chats
+    Editor,
+    // manage members and delete the workspace
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+/// Role of the user in the workspace, the creator is always an owner.
+pub async fn workspace_role<C: ConnectionTrait>(
+    db: &C,
+    user_id: &str,
+    model: &workspace::Model,
+) -> Result<Option<Role>, DbErr> {
+    if model.uid == user_id {
+        return Ok(Some(Role::Owner));
+    }
+    Ok(WorkspaceMemberService::get_member(db, &model.id, user_id)
+        .await?
+        .and_then(|member| Role::parse(&member.role)))
+}
+
+/// Workspace the user has at least the role in, forbidden otherwise.
+pub async fn check_workspace<C: ConnectionTrait>(
+    db: &C,
+    user_id: &str,
+    wid: &str,
+    role: Role,
+) -> Result<workspace::Model, AccessError> {
+    let model = match WorkspaceService::get_workspace(db, wid).await? {
+        Some(model) => model,
+        None => return Err(AccessError::NotFound("workspace")),
+    };
+    match workspace_role(db, user_id, &model).await? {
+        Some(user_role) if user_role >= role => Ok(model),
+        _ => Err(AccessError::Forbidden("workspace")),
+    }
+}
+
+/// File or chat the user has at least the role on through the workspace it is in.
+pub async fn check_file<C: ConnectionTrait>(
+    db: &C,
+    user_id: &str,
+    id: &str,
+    role: Role,
+) -> Result<file::Model, AccessError> {
+    let model = match FileService::get_file(db, id).await? {
+        Some(model) => model,
+        None => return Err(AccessError::NotFound("file")),
+    };
+    match check_workspace(db, user_id, &model.wid, role).await {
+        Ok(_) => Ok(model),
+        Err(AccessError::NotFound(_)) | Err(AccessError::Forbidden(_)) => {
+            Err(AccessError::Forbidden("file"))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Only the default local user owns the install, e.g. its backups and install wide settings.
+pub async fn check_admin(db: &DatabaseConnection, user_id: &str) -> Result<(), AccessError> {
+    match UserService::get_user_model_by_id(db, user_id).await? {
+        Some(user) if user.username == DEFAULT_USERNAME && user.r#type == LOCAL_USER_TYPE => Ok(()),
+        _ => Err(AccessError::Forbidden("install")),
+    }
+}
+
+/// Ids of the workspaces the user owns or is a member of.
+pub async fn list_workspace_ids<C: ConnectionTrait>(
+    db: &C,
+    user_id: &str,
+) -> Result<Vec<String>, DbErr> {
+    let mut wids: Vec<String> = WorkspaceService::list_workspaces_by_uid(db, user_id)
+        .await?
+        .into_iter()
+        .map(|model| model.id)
+        .collect();
+    for member in WorkspaceMemberService::list_members_by_uid(db, user_id).await? {
+        if !wids.contains(&member.wid) {
+            wids.push(member.wid);
+        }
+    }
+    Ok(wids)
+}
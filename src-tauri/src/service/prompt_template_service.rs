@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ConnectionTrait, DatabaseConnection};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::dao::prompt_template_dao::PromptTemplateService;
+use crate::entity::prompt_template::{ActiveModel, Model};
+use crate::service::permission_service::{check_workspace, list_workspace_ids, AccessError, Role};
+use crate::AppResponse;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateBody {
+    pub name: String,
+    pub content: String,
+    // share the template in the workspace, global to the user when missing
+    pub wid: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateBody {
+    pub id: String,
+    pub name: String,
+    pub content: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ListBody {
+    // only global templates and the ones of this workspace, all visible templates when missing
+    pub wid: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CommonBody {
+    pub id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderBody {
+    pub id: String,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+/// Template the user may access with the role, global templates only belong to their creator.
+pub async fn check_template<C: ConnectionTrait>(
+    db: &C,
+    uid: &str,
+    id: &str,
+    role: Role,
+) -> Result<Model, AccessError> {
+    let template = match PromptTemplateService::get_template(db, id).await? {
+        Some(template) => template,
+        None => return Err(AccessError::NotFound("template")),
+    };
+    match &template.wid {
+        Some(wid) => {
+            check_workspace(db, uid, wid, role).await?;
+        }
+        None if template.uid != uid => return Err(AccessError::NotFound("template")),
+        None => {}
+    }
+    Ok(template)
+}
+
+fn check_body(name: &str, content: &str) -> Result<(), &'static str> {
+    if name.trim().is_empty() {
+        return Err("template name is empty");
+    }
+    if content.trim().is_empty() {
+        return Err("template content is empty");
+    }
+    Ok(())
+}
+
+// placeholder names are words, other text in braces is kept as it is
+fn is_variable(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Replace every `{{variable}}` placeholder, fail with the names of the ones without a value.
+pub fn render(content: &str, values: &HashMap<String, String>) -> Result<String, String> {
+    let mut missing: Vec<String> = vec![];
+    let text = render_with(content, |name| {
+        let value = values.get(name).cloned();
+        if value.is_none() && !missing.iter().any(|known| known == name) {
+            missing.push(name.to_string());
+        }
+        value
+    });
+    match missing.is_empty() {
+        true => Ok(text),
+        false => Err(format!(
+            "missing template variables: {}",
+            missing.join(", ")
+        )),
+    }
+}
+
+fn render_with<F>(content: &str, mut value: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut text = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + 2 + end].trim();
+        text.push_str(&rest[..start]);
+        let placeholder = &rest[start..start + end + 4];
+        if is_variable(name) {
+            text.push_str(&value(name).unwrap_or_default());
+        } else {
+            text.push_str(placeholder);
+        }
+        rest = &rest[start + end + 4..];
+    }
+    text.push_str(rest);
+    text
+}
+
+pub async fn create_template(
+    db: &DatabaseConnection,
+    uid: &str,
+    body: &CreateBody,
+) -> AppResponse<Option<Model>> {
+    if let Some(wid) = &body.wid {
+        if let Err(err) = check_workspace(db, uid, wid, Role::Editor).await {
+            return err.response(None);
+        }
+    }
+    if let Err(message) = check_body(&body.name, &body.content) {
+        return AppResponse::error(None, message);
+    }
+    let active_model = ActiveModel {
+        id: Set(Uuid::new_v4().to_string()),
+        uid: Set(uid.to_string()),
+        wid: Set(body.wid.clone()),
+        name: Set(body.name.clone()),
+        content: Set(body.content.clone()),
+        create_time: Set(Utc::now().timestamp()),
+        update_time: Set(Utc::now().timestamp()),
+    };
+    match PromptTemplateService::create_template(db, active_model).await {
+        Ok(model) => AppResponse::success(Some(model)),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+pub async fn list_templates(
+    db: &DatabaseConnection,
+    uid: &str,
+    body: &ListBody,
+) -> AppResponse<Vec<Model>> {
+    let wids = match &body.wid {
+        Some(wid) => match check_workspace(db, uid, wid, Role::Viewer).await {
+            Ok(_) => vec![wid.clone()],
+            Err(err) => return err.response(vec![]),
+        },
+        None => match list_workspace_ids(db, uid).await {
+            Ok(wids) => wids,
+            Err(err) => return AppResponse::error(vec![], &err.to_string()),
+        },
+    };
+    match PromptTemplateService::list_templates(db, uid, &wids).await {
+        Ok(models) => AppResponse::success(models),
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+pub async fn get_template(
+    db: &DatabaseConnection,
+    uid: &str,
+    id: &str,
+) -> AppResponse<Option<Model>> {
+    match check_template(db, uid, id, Role::Viewer).await {
+        Ok(model) => AppResponse::success(Some(model)),
+        Err(err) => err.response(None),
+    }
+}
+
+pub async fn update_template(
+    db: &DatabaseConnection,
+    uid: &str,
+    body: &UpdateBody,
+) -> AppResponse<Option<Model>> {
+    if let Err(err) = check_template(db, uid, &body.id, Role::Editor).await {
+        return err.response(None);
+    }
+    if let Err(message) = check_body(&body.name, &body.content) {
+        return AppResponse::error(None, message);
+    }
+    let active_model = ActiveModel {
+        id: Set(body.id.clone()),
+        name: Set(body.name.clone()),
+        content: Set(body.content.clone()),
+        update_time: Set(Utc::now().timestamp()),
+        ..Default::default()
+    };
+    match PromptTemplateService::update_template(db, active_model).await {
+        Ok(model) => AppResponse::success(Some(model)),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+pub async fn delete_template(db: &DatabaseConnection, uid: &str, id: &str) -> AppResponse<String> {
+    match check_template(db, uid, id, Role::Editor).await {
+        Ok(_) => {}
+        Err(AccessError::NotFound(_)) => return AppResponse::success("".to_string()),
+        Err(err) => return err.response("".to_string()),
+    }
+    match PromptTemplateService::delete_template(db, id).await {
+        Ok(_) => AppResponse::success("".to_string()),
+        Err(err) => AppResponse::error("".to_string(), &err.to_string()),
+    }
+}
+
+pub async fn render_template(
+    db: &DatabaseConnection,
+    uid: &str,
+    body: &RenderBody,
+) -> AppResponse<Option<String>> {
+    let template = match check_template(db, uid, &body.id, Role::Viewer).await {
+        Ok(template) => template,
+        Err(err) => return err.response(None),
+    };
+    match render(&template.content, &body.variables) {
+        Ok(text) => AppResponse::success(Some(text)),
+        Err(message) => AppResponse::error(None, &message),
+    }
+}
+
+/// Prompt rendered from a template usable in a chat of the workspace.
+pub async fn render_prompt(
+    db: &DatabaseConnection,
+    uid: &str,
+    wid: &str,
+    id: &str,
+    values: &HashMap<String, String>,
+) -> Result<String, String> {
+    let template = match check_template(db, uid, id, Role::Viewer).await {
+        Ok(template) => template,
+        Err(err) => return Err(err.to_string()),
+    };
+    if template
+        .wid
+        .as_ref()
+        .is_some_and(|template_wid| template_wid != wid)
+    {
+        return Err("template belongs to another workspace".to_string());
+    }
+    render(&template.content, values)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env::temp_dir;
+
+    use crate::migration::migrate;
+    use crate::service::permission_service::Role;
+    use crate::service::prompt_template_service::{
+        create_template, delete_template, get_template, list_templates, render, render_prompt,
+        update_template, CreateBody, ListBody, UpdateBody,
+    };
+    use crate::service::user_service::{register, RegisterBody};
+    use crate::service::workspace_service::{create_workspace, invite_member, MemberInviteBody};
+    use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+
+    #[test]
+    fn test_render() {
+        let content = "Translate {{ text }} into {{lang}}, keep {{text}} and {not} {{ }} {{a b}}";
+        let mut values = HashMap::new();
+        values.insert("text".to_string(), "hello".to_string());
+        assert_eq!(
+            Err("missing template variables: lang".to_string()),
+            render(content, &values)
+        );
+        values.insert("lang".to_string(), "French".to_string());
+        assert_eq!(
+            Ok("Translate hello into French, keep hello and {not} {{ }} {{a b}}".to_string()),
+            render(content, &values)
+        );
+        assert_eq!(Ok("open {{".to_string()), render("open {{", &values));
+    }
+
+    #[tokio::test]
+    async fn test_prompt_template() {
+        let temp_dir = temp_dir();
+        let file_path = &temp_dir
+            .join(".fatherbox")
+            .join("test-prompt-template.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let register_body = |username: &str| RegisterBody {
+            username: username.to_string(),
+            password: "password".to_string(),
+            nickname: username.to_string(),
+        };
+        let owner = register(db, &register_body("owner")).await.result.unwrap();
+        let guest = register(db, &register_body("guest")).await.result.unwrap();
+        let wid = create_workspace(db, &owner.id, "shared")
+            .await
+            .result
+            .unwrap()
+            .id;
+        // 1. global and workspace templates
+        let create_body = |name: &str, content: &str, wid: Option<String>| CreateBody {
+            name: name.to_string(),
+            content: content.to_string(),
+            wid,
+        };
+        let global = create_template(
+            db,
+            &owner.id,
+            &create_body("summary", "Summarize {{text}}", None),
+        )
+        .await
+        .result
+        .unwrap();
+        let shared = create_template(
+            db,
+            &owner.id,
+            &create_body("review", "Review {{code}}", Some(wid.clone())),
+        )
+        .await
+        .result
+        .unwrap();
+        assert!(
+            create_template(db, &owner.id, &create_body(" ", "text", None))
+                .await
+                .is_error()
+        );
+        let list = list_templates(db, &owner.id, &ListBody { wid: None }).await;
+        assert_eq!(2, list.result.len());
+        // 2. outsiders see nothing
+        assert!(get_template(db, &guest.id, &global.id).await.is_error());
+        assert!(!get_template(db, &guest.id, &shared.id).await.is_success());
+        assert!(list_templates(db, &guest.id, &ListBody { wid: None })
+            .await
+            .result
+            .is_empty());
+        // 3. viewers of the workspace use the shared template but can not change it
+        let invite_body = MemberInviteBody {
+            wid: wid.clone(),
+            username: "guest".to_string(),
+            role: Role::Viewer,
+        };
+        assert!(invite_member(db, &owner.id, &invite_body)
+            .await
+            .is_success());
+        let list = list_templates(
+            db,
+            &guest.id,
+            &ListBody {
+                wid: Some(wid.clone()),
+            },
+        )
+        .await;
+        assert_eq!(1, list.result.len());
+        assert_eq!(shared.id, list.result[0].id);
+        let update_body = UpdateBody {
+            id: shared.id.clone(),
+            name: "review".to_string(),
+            content: "Review {{code}} in {{lang}}".to_string(),
+        };
+        assert!(update_template(db, &guest.id, &update_body)
+            .await
+            .is_forbidden());
+        assert!(!create_template(
+            db,
+            &guest.id,
+            &create_body("mine", "text", Some(wid.clone()))
+        )
+        .await
+        .is_success());
+        // 4. update
+        let result = update_template(db, &owner.id, &update_body).await;
+        assert_eq!(update_body.content, result.result.unwrap().content);
+        // 5. render for a chat of the workspace
+        let mut values = HashMap::new();
+        values.insert("code".to_string(), "fn main() {}".to_string());
+        assert!(render_prompt(db, &guest.id, &wid, &shared.id, &values)
+            .await
+            .is_err());
+        values.insert("lang".to_string(), "rust".to_string());
+        assert_eq!(
+            Ok("Review fn main() {} in rust".to_string()),
+            render_prompt(db, &guest.id, &wid, &shared.id, &values).await
+        );
+        assert!(render_prompt(db, &owner.id, "other", &shared.id, &values)
+            .await
+            .is_err());
+        // 6. delete
+        assert!(delete_template(db, &guest.id, &global.id)
+            .await
+            .is_success());
+        assert!(get_template(db, &owner.id, &global.id).await.is_success());
+        assert!(delete_template(db, &owner.id, &global.id)
+            .await
+            .is_success());
+        assert!(get_template(db, &owner.id, &global.id).await.is_error());
+    }
+}
@@ -0,0 +1,289 @@
+use std::fs;
+use std::path::Path;
+
+use log::{error, info};
+use sea_orm::{ConnectionTrait, DatabaseConnection};
+use serde_json::Value;
+
+use crate::dao::file_dao::FileService;
+use crate::dao::search_dao::SearchService;
+use crate::dto::file::{SearchBody, SearchHit};
+use crate::entity::file::Model;
+use crate::service::blob_service::file_path;
+use crate::service::permission_service::{check_workspace, Role};
+use crate::{AppResponse, CHAT_ZONE, DIR_TYPE, SEARCH_INDEX_MAX_SIZE};
+
+// text of the file worth indexing, binary and large files only get their name indexed
+fn text_content(user_path: &Path, model: &Model) -> String {
+    if model.r#type == DIR_TYPE {
+        return String::new();
+    }
+    let path = file_path(user_path, model);
+    match fs::metadata(&path) {
+        Ok(metadata) if metadata.len() <= SEARCH_INDEX_MAX_SIZE => {}
+        _ => return String::new(),
+    }
+    let content = match fs::read(&path) {
+        Ok(content) if !content.contains(&0) => content,
+        _ => return String::new(),
+    };
+    let text = String::from_utf8(content).unwrap_or_default();
+    if model.zone != CHAT_ZONE {
+        return text;
+    }
+    // chat transcript, only message contents of every branch are searchable
+    let messages = match serde_json::from_str::<Value>(&text) {
+        Ok(Value::Array(messages)) => messages,
+        Ok(Value::Object(mut chat)) => match chat.remove("nodes") {
+            Some(Value::Array(nodes)) => nodes,
+            _ => return String::new(),
+        },
+        _ => return String::new(),
+    };
+    messages
+        .iter()
+        .filter_map(|message| message["content"].as_str())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// Index name and text content of the file, a failed index is logged and never fails the caller.
+pub async fn index_file<C: ConnectionTrait>(db: &C, user_path: &Path, model: &Model) {
+    let content = text_content(user_path, model);
+    let result = SearchService::index_file(
+        db,
+        &model.id,
+        &model.wid,
+        &model.zone,
+        &model.name,
+        &content,
+    )
+    .await;
+    if let Err(err) = result {
+        error!("index file {} failed, err: {}", model.id, err);
+    }
+}
+
+pub async fn rename_file<C: ConnectionTrait>(db: &C, id: &str, name: &str) {
+    if let Err(err) = SearchService::rename_file(db, id, name).await {
+        error!("rename indexed file {} failed, err: {}", id, err);
+    }
+}
+
+pub async fn remove_indexed_files<C: ConnectionTrait>(db: &C, ids: &[String]) {
+    if let Err(err) = SearchService::remove_files(db, ids).await {
+        error!("remove indexed files failed, err: {}", err);
+    }
+}
+
+pub async fn move_indexed_workspace<C: ConnectionTrait>(db: &C, from_wid: &str, to_wid: &str) {
+    if let Err(err) = SearchService::move_workspace(db, from_wid, to_wid).await {
+        error!("move indexed workspace {} failed, err: {}", from_wid, err);
+    }
+}
+
+/// Index all files when the index is empty, e.g. right after the index was added.
+pub async fn init_index(db: &DatabaseConnection, user_path: &Path) -> Result<(), anyhow::Error> {
+    if SearchService::count_files(db).await? > 0 {
+        return Ok(());
+    }
+    // trashed files are indexed too, search skips them until they are restored
+    let models = FileService::list_all_files(db).await?;
+    for model in &models {
+        index_file(db, user_path, model).await;
+    }
+    info!("init search index success, count: {}", models.len());
+    Ok(())
+}
+
+// every word must match, the last one may be a prefix of what is still being typed
+fn to_match_query(query: &str) -> String {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    match terms.is_empty() {
+        true => String::new(),
+        false => format!("{}*", terms.join(" ")),
+    }
+}
+
+pub async fn search(
+    db: &DatabaseConnection,
+    user_id: &str,
+    body: &SearchBody,
+) -> AppResponse<Vec<SearchHit>> {
+    if let Err(err) = check_workspace(db, user_id, &body.wid, Role::Viewer).await {
+        return err.response(vec![]);
+    }
+    let query = to_match_query(&body.query);
+    if query.is_empty() {
+        return AppResponse::success(vec![]);
+    }
+    match SearchService::search(db, body, &query).await {
+        Ok(hits) => AppResponse::success(hits),
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use crate::dto::file::{
+        CreateBody, GeneralBody, SearchBody, UpdateContentBody, UpdateNameBody,
+    };
+    use crate::migration::migrate;
+    use crate::service::file_service::{
+        create_file, delete_file, restore_file, update_file_content, update_file_name,
+    };
+    use crate::service::search_service::{search, to_match_query};
+    use crate::service::workspace_service::create_workspace;
+    use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+    use crate::{VersionSettings, CHAT_ZONE, FILE_TYPE};
+
+    #[tokio::test]
+    async fn test_search() {
+        let temp_dir = temp_dir();
+        let base_path = &temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-search");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        let file_path = &base_path.join("test-search.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let user_id = "test-user";
+        let wid = create_workspace(db, user_id, "test")
+            .await
+            .result
+            .unwrap()
+            .id;
+        let create_body = |name: &str, zone: &str, content: &[u8]| CreateBody {
+            name: name.to_string(),
+            pid: "".to_string(),
+            wid: wid.clone(),
+            r#type: FILE_TYPE.to_string(),
+            zone: zone.to_string(),
+            content: Some(content.to_vec()),
+            path: None,
+        };
+        let search_body = |query: &str, zone: Option<&str>| SearchBody {
+            wid: wid.clone(),
+            zone: zone.map(|zone| zone.to_string()),
+            query: query.to_string(),
+            page_size: 20,
+            page_num: 0,
+        };
+        let note = create_file(
+            db,
+            user_path,
+            user_id,
+            &create_body("note.md", "", b"rust is fast"),
+        )
+        .await
+        .result
+        .unwrap();
+        create_file(
+            db,
+            user_path,
+            user_id,
+            &create_body("image.png", "", b"rust\0binary"),
+        )
+        .await
+        .result
+        .unwrap();
+        let chat = create_file(
+            db,
+            user_path,
+            user_id,
+            &create_body(
+                "chat",
+                CHAT_ZONE,
+                br#"[{"role":"user","content":"how fast is rust"}]"#,
+            ),
+        )
+        .await
+        .result
+        .unwrap();
+        // 1. content of text files and chat messages, binary content is skipped
+        let hits = search(db, user_id, &search_body("rust", None)).await.result;
+        assert_eq!(2, hits.len());
+        assert!(hits.iter().any(|hit| hit.snippet.contains("<b>rust</b>")));
+        let hits = search(db, user_id, &search_body("ru", Some(CHAT_ZONE)))
+            .await
+            .result;
+        assert_eq!(
+            vec![chat.id.clone()],
+            hits.iter().map(|hit| hit.id.clone()).collect::<Vec<_>>()
+        );
+        // 2. index follows content and name updates
+        update_file_content(
+            db,
+            user_path,
+            &VersionSettings::default(),
+            user_id,
+            &UpdateContentBody {
+                id: note.id.clone(),
+                content: b"go is simple".to_vec(),
+            },
+        )
+        .await;
+        assert_eq!(
+            1,
+            search(db, user_id, &search_body("rust", None))
+                .await
+                .result
+                .len()
+        );
+        update_file_name(
+            db,
+            user_id,
+            &UpdateNameBody {
+                id: note.id.clone(),
+                name: "golang.md".to_string(),
+            },
+        )
+        .await;
+        assert_eq!(
+            1,
+            search(db, user_id, &search_body("golang", None))
+                .await
+                .result
+                .len()
+        );
+        // 3. trashed files are hidden until restored
+        let general_body = &GeneralBody {
+            wid: wid.clone(),
+            id: note.id.clone(),
+        };
+        delete_file(db, user_id, general_body).await;
+        assert!(search(db, user_id, &search_body("simple", None))
+            .await
+            .result
+            .is_empty());
+        restore_file(db, user_id, general_body).await;
+        assert_eq!(
+            1,
+            search(db, user_id, &search_body("simple", None))
+                .await
+                .result
+                .len()
+        );
+        // 4. query syntax of users is not interpreted
+        assert!(search(db, user_id, &search_body("\"rust OR", None))
+            .await
+            .is_success());
+        assert_eq!("\"a\"\"b\"*", to_match_query("a\"b"));
+        assert!(search(db, user_id, &search_body("  ", None))
+            .await
+            .result
+            .is_empty());
+    }
+}
@@ -0,0 +1,364 @@
+use chrono::Utc;
+use log::{error, warn};
+use sea_orm::ActiveValue::Set;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::dao::session_dao::SessionService;
+use crate::dao::user_dao::UserService;
+use crate::entity::session;
+use crate::util::token_util::{
+    generate_refresh_token, generate_token, hash_refresh_token, parse_refresh_token,
+};
+use crate::{
+    AppResponse, ACCESS_TOKEN_EXPIRE_SECONDS, REFRESH_TOKEN_EXPIRE_SECONDS,
+    USER_STATE_PASSWORD_EXPIRED,
+};
+
+pub const DEFAULT_DEVICE: &str = "unknown";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub id: String,
+    pub device: String,
+    pub create_time: i64,
+    pub update_time: i64,
+    pub expire_time: i64,
+    pub current: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeBody {
+    pub id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshTokenBody {
+    pub refresh_token: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionTokens {
+    pub session_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Create a session for the user, return access token and refresh token of it.
+pub async fn create_session(
+    db: &DatabaseConnection,
+    token_secret: &[u8],
+    user_id: &str,
+    device: &str,
+    password_expired: bool,
+) -> Result<SessionTokens, anyhow::Error> {
+    let session_id = Uuid::new_v4().to_string();
+    let refresh_token = generate_refresh_token(&session_id);
+    let now = Utc::now().timestamp();
+    let active_model = session::ActiveModel {
+        id: Set(session_id.clone()),
+        user_id: Set(user_id.to_string()),
+        refresh_token_hash: Set(hash_refresh_token(&refresh_token)),
+        device: Set(device.to_string()),
+        create_time: Set(now),
+        update_time: Set(now),
+        expire_time: Set(now + REFRESH_TOKEN_EXPIRE_SECONDS),
+        revoke_time: Set(None),
+        state: Set(1),
+    };
+    SessionService::create_session(db, active_model).await?;
+    Ok(SessionTokens {
+        access_token: generate_token(
+            token_secret,
+            user_id,
+            &session_id,
+            password_expired,
+            ACCESS_TOKEN_EXPIRE_SECONDS,
+        ),
+        session_id,
+        refresh_token,
+    })
+}
+
+async fn revoke_reused(
+    db: &DatabaseConnection,
+    model: &session::Model,
+) -> AppResponse<Option<SessionTokens>> {
+    warn!("refresh token reused, revoke session {}", model.id);
+    if let Err(err) = SessionService::revoke_session(db, &model.user_id, &model.id).await {
+        error!("revoke session failed, err: {}", err);
+    }
+    AppResponse::timeout(None, "Refresh token is reused, session is revoked")
+}
+
+/// Exchange a refresh token for a new token pair, the old refresh token is invalid after this.
+pub async fn rotate_session(
+    db: &DatabaseConnection,
+    token_secret: &[u8],
+    body: &RefreshTokenBody,
+) -> AppResponse<Option<SessionTokens>> {
+    let session_id = match parse_refresh_token(&body.refresh_token) {
+        Some(session_id) => session_id,
+        None => return AppResponse::timeout(None, "Refresh token is malformed"),
+    };
+    let model = match SessionService::get_session(db, session_id).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return AppResponse::timeout(None, "User session not found"),
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    if model.revoke_time.is_some() {
+        return AppResponse::timeout(None, "User session is revoked");
+    }
+    if model.expire_time <= Utc::now().timestamp() {
+        return AppResponse::timeout(None, "User session is expired");
+    }
+    let hash = hash_refresh_token(&body.refresh_token);
+    if !bool::from(hash.as_bytes().ct_eq(model.refresh_token_hash.as_bytes())) {
+        // an already rotated refresh token is replayed, the token may be stolen
+        return revoke_reused(db, &model).await;
+    }
+    let password_expired = match UserService::get_user_model_by_id(db, &model.user_id).await {
+        Ok(Some(user)) => user.state == USER_STATE_PASSWORD_EXPIRED,
+        Ok(None) => return AppResponse::timeout(None, "User not found"),
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let refresh_token = generate_refresh_token(&model.id);
+    let expire_time = Utc::now().timestamp() + REFRESH_TOKEN_EXPIRE_SECONDS;
+    match SessionService::rotate_refresh_token(
+        db,
+        &model.id,
+        &hash,
+        &hash_refresh_token(&refresh_token),
+        expire_time,
+    )
+    .await
+    {
+        // the same token was rotated concurrently, or the session revoked meanwhile
+        Ok(0) => revoke_reused(db, &model).await,
+        Ok(_) => AppResponse::success(Some(SessionTokens {
+            access_token: generate_token(
+                token_secret,
+                &model.user_id,
+                &model.id,
+                password_expired,
+                ACCESS_TOKEN_EXPIRE_SECONDS,
+            ),
+            session_id: model.id,
+            refresh_token,
+        })),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+/// Check the session of an access token is still active.
+pub async fn check_session(db: &DatabaseConnection, user_id: &str, id: &str) -> AppResponse<bool> {
+    match SessionService::get_session(db, id).await {
+        Ok(Some(model)) => {
+            if model.user_id != user_id {
+                return AppResponse::timeout(false, "User session not found");
+            }
+            if model.revoke_time.is_some() {
+                return AppResponse::timeout(false, "User session is revoked");
+            }
+            if model.expire_time <= Utc::now().timestamp() {
+                return AppResponse::timeout(false, "User session is expired");
+            }
+            AppResponse::success(true)
+        }
+        Ok(None) => AppResponse::timeout(false, "User session not found"),
+        Err(err) => AppResponse::error(false, &err.to_string()),
+    }
+}
+
+pub async fn list_sessions(
+    db: &DatabaseConnection,
+    user_id: &str,
+    current_id: &str,
+) -> AppResponse<Vec<SessionInfo>> {
+    match SessionService::list_active_sessions(db, user_id).await {
+        Ok(models) => AppResponse::success(
+            models
+                .into_iter()
+                .map(|model| SessionInfo {
+                    current: model.id == current_id,
+                    id: model.id,
+                    device: model.device,
+                    create_time: model.create_time,
+                    update_time: model.update_time,
+                    expire_time: model.expire_time,
+                })
+                .collect(),
+        ),
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+pub async fn revoke_session(
+    db: &DatabaseConnection,
+    user_id: &str,
+    id: &str,
+) -> AppResponse<String> {
+    match SessionService::revoke_session(db, user_id, id).await {
+        Ok(0) => AppResponse::error("".to_string(), "User session not found"),
+        Ok(_) => AppResponse::success("".to_string()),
+        Err(err) => AppResponse::error("".to_string(), &err.to_string()),
+    }
+}
+
+pub async fn revoke_other_sessions(
+    db: &DatabaseConnection,
+    user_id: &str,
+    current_id: &str,
+) -> AppResponse<u64> {
+    match SessionService::revoke_other_sessions(db, user_id, current_id).await {
+        Ok(count) => AppResponse::success(count),
+        Err(err) => AppResponse::error(0, &err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use sea_orm::{ConnectionTrait, Schema};
+
+    use crate::entity;
+    use crate::service::session_service::{
+        check_session, create_session, list_sessions, revoke_other_sessions, revoke_session,
+        rotate_session, RefreshTokenBody,
+    };
+    use crate::service::user_service::{create, RegisterBody};
+    use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+    use crate::util::token_util::verify_token;
+    use crate::{RESPONSE_CODE_TIMEOUT, USER_STATE_NORMAL};
+
+    #[tokio::test]
+    async fn test_session() {
+        let temp_dir = temp_dir();
+        let base_path = temp_dir.join(".fatherbox");
+        let file_path = &base_path.join("test-session.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = init_connection(&file_path).await.unwrap();
+        let builder = db.get_database_backend();
+        let schema = Schema::new(builder);
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::User)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Session)))
+            .await
+            .unwrap();
+        let token_secret = b"0123456789abcdef0123456789abcdef";
+        let user = create(
+            &db,
+            &RegisterBody {
+                username: "admin".to_string(),
+                password: "123456".to_string(),
+                nickname: "admin".to_string(),
+            },
+            USER_STATE_NORMAL,
+        )
+        .await
+        .result
+        .unwrap();
+        let user_id = &user.id;
+        // 1. create two sessions
+        let laptop = create_session(&db, token_secret, user_id, "laptop", false)
+            .await
+            .unwrap();
+        let desktop = create_session(&db, token_secret, user_id, "desktop", false)
+            .await
+            .unwrap();
+        let claims = verify_token(token_secret, &laptop.access_token).unwrap();
+        assert_eq!(laptop.session_id, claims.sid);
+        assert!(check_session(&db, user_id, &laptop.session_id).await.result);
+        let sessions = list_sessions(&db, user_id, &laptop.session_id).await.result;
+        assert_eq!(2, sessions.len());
+        assert_eq!(1, sessions.iter().filter(|session| session.current).count());
+        // 2. rotate refresh token
+        let rotated = rotate_session(
+            &db,
+            token_secret,
+            &RefreshTokenBody {
+                refresh_token: laptop.refresh_token.clone(),
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        assert_eq!(laptop.session_id, rotated.session_id);
+        assert_ne!(laptop.refresh_token, rotated.refresh_token);
+        // 3. reuse old refresh token revokes the session
+        let response = rotate_session(
+            &db,
+            token_secret,
+            &RefreshTokenBody {
+                refresh_token: laptop.refresh_token.clone(),
+            },
+        )
+        .await;
+        assert_eq!(RESPONSE_CODE_TIMEOUT, response.code);
+        let response = check_session(&db, user_id, &laptop.session_id).await;
+        assert_eq!(RESPONSE_CODE_TIMEOUT, response.code);
+        let response = rotate_session(
+            &db,
+            token_secret,
+            &RefreshTokenBody {
+                refresh_token: rotated.refresh_token.clone(),
+            },
+        )
+        .await;
+        assert_eq!(RESPONSE_CODE_TIMEOUT, response.code);
+        // 4. concurrent refreshes with the same token, only one of them rotates
+        let tablet = create_session(&db, token_secret, user_id, "tablet", false)
+            .await
+            .unwrap();
+        let body = RefreshTokenBody {
+            refresh_token: tablet.refresh_token.clone(),
+        };
+        let (first, second) = tokio::join!(
+            rotate_session(&db, token_secret, &body),
+            rotate_session(&db, token_secret, &body)
+        );
+        assert_eq!(
+            1,
+            [first, second]
+                .iter()
+                .filter(|response| response.is_success())
+                .count()
+        );
+        let response = check_session(&db, user_id, &tablet.session_id).await;
+        assert_eq!(RESPONSE_CODE_TIMEOUT, response.code);
+        // 5. revoke other sessions
+        let phone = create_session(&db, token_secret, user_id, "phone", false)
+            .await
+            .unwrap();
+        let count = revoke_other_sessions(&db, user_id, &phone.session_id)
+            .await
+            .result;
+        assert_eq!(1, count);
+        assert!(
+            !check_session(&db, user_id, &desktop.session_id)
+                .await
+                .result
+        );
+        assert!(check_session(&db, user_id, &phone.session_id).await.result);
+        // 6. other user can not revoke the session
+        assert!(revoke_session(&db, "other", &phone.session_id)
+            .await
+            .is_error());
+        assert!(check_session(&db, "other", &phone.session_id).await.code == RESPONSE_CODE_TIMEOUT);
+        // 7. logout
+        assert!(revoke_session(&db, user_id, &phone.session_id)
+            .await
+            .is_success());
+        assert_eq!(0, list_sessions(&db, user_id, "").await.result.len());
+    }
+}
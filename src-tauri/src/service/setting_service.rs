@@ -1,6 +1,6 @@
 use chrono::Utc;
 use sea_orm::ActiveValue::Set;
-use sea_orm::DatabaseConnection;
+use sea_orm::{DatabaseConnection, DbErr};
 use serde::{Deserialize, Serialize};
 
 use crate::{AppResponse, CHAT_API_SETTING_KEY, DEEP_SEEK, DEEPSEEK_BASE_URL, OLLAMA_BASE_URL, OLLAMA_NAME, OPENAI_BASE_URL, OPENAI_NAME};
@@ -24,6 +24,35 @@ pub struct ChatApiSetting {
     pub is_sync: bool,
 }
 
+/// Key prefix of the settings scoped to the workspace, they are removed together with it.
+pub fn workspace_setting_prefix(wid: &str) -> String {
+    format!("workspace.{}.", wid)
+}
+
+pub fn workspace_setting_key(wid: &str, key: &str) -> String {
+    format!("{}{}", workspace_setting_prefix(wid), key)
+}
+
+/// Key of a setting that only applies to the user.
+pub fn user_setting_key(uid: &str, key: &str) -> String {
+    format!("user.{}.{}", uid, key)
+}
+
+/// Create the setting or replace its value.
+pub async fn save_setting(db: &DatabaseConnection, key: &str, value: &str) -> Result<Model, DbErr> {
+    let active_model = ActiveModel {
+        key: Set(key.to_string()),
+        value: Set(value.as_bytes().to_vec()),
+        create_time: Set(Utc::now().timestamp()),
+        update_time: Set(Utc::now().timestamp()),
+        state: Set(1),
+    };
+    match SettingService::update_setting(db, active_model.clone()).await? {
+        Some(model) => Ok(model),
+        None => SettingService::create_setting(db, active_model).await,
+    }
+}
+
 async fn create_setting(
     db: &DatabaseConnection,
     body: &CreateOrUpdateBody,
@@ -1,5 +1,3 @@
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
 use chrono::Utc;
 use futures::future::ok;
 use futures::FutureExt;
@@ -10,10 +8,21 @@ use serde::{Deserialize, Serialize};
 use tauri::State;
 use uuid::Uuid;
 
+use crate::dao::session_dao::SessionService;
 use crate::dao::user_dao::UserService;
 use crate::entity::user;
 use crate::entity::user::Model;
-use crate::{AppResponse, LoginInfo, RESPONSE_CODE_ERROR, RESPONSE_CODE_SUCCESS};
+use crate::service::session_service::{
+    create_session, revoke_session, rotate_session, RefreshTokenBody, DEFAULT_DEVICE,
+};
+use crate::util::password_util::{
+    hash_password, is_legacy_password, verify_password, PASSWORD_MIN_LEN,
+};
+use crate::util::token_util::generate_token;
+use crate::{
+    AppResponse, LoginInfo, ACCESS_TOKEN_EXPIRE_SECONDS, RESPONSE_CODE_ERROR,
+    RESPONSE_CODE_SUCCESS, USER_STATE_NORMAL, USER_STATE_PASSWORD_EXPIRED,
+};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
 #[serde(rename_all = "camelCase")]
@@ -39,12 +48,23 @@ pub struct RegisterBody {
 pub struct LoginBody {
     pub username: String,
     pub password: String,
+    // device label of the session, e.g. host name
+    #[serde(default)]
+    pub device: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswordBody {
+    pub old_password: String,
+    pub new_password: String,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct RefreshTokenResult {
     pub data: String,
+    pub refresh_token: String,
     pub status: i8,
 }
 
@@ -55,25 +75,39 @@ pub async fn register(db: &DatabaseConnection, body: &RegisterBody) -> AppRespon
             if let Some(_) = option_model {
                 return AppResponse::error(None, "User already exists");
             }
-            create(db, body).await
+            create(db, body, USER_STATE_NORMAL).await
         }
         Err(err) => AppResponse::error(None, &err.to_string()),
     }
 }
 
-pub async fn create(db: &DatabaseConnection, body: &RegisterBody) -> AppResponse<Option<Model>> {
+pub async fn create(
+    db: &DatabaseConnection,
+    body: &RegisterBody,
+    state: i8,
+) -> AppResponse<Option<Model>> {
+    if body.password.len() < PASSWORD_MIN_LEN {
+        return AppResponse::error(None, "Password is too short");
+    }
+    let password = match hash_password(&body.password) {
+        Ok(password) => password,
+        Err(err) => {
+            error!("create user failed, err: {}", err);
+            return AppResponse::error(None, &err.to_string());
+        }
+    };
     let active_model = user::ActiveModel {
         id: Set(Uuid::new_v4().to_string()),
         username: Set(body.username.clone()),
         nickname: Set(body.nickname.clone()),
         avatar: Default::default(),
-        password: Set(body.password.clone()),
+        password: Set(password),
         mail: Default::default(),
         r#type: Set("local".to_string()),
         ref_user_id: Default::default(),
         create_time: Set(Utc::now().timestamp()),
         update_time: Set(Utc::now().timestamp()),
-        state: Set(1),
+        state: Set(state),
     };
     let result = UserService::create_user(db, active_model).await;
     match result {
@@ -84,7 +118,11 @@ pub async fn create(db: &DatabaseConnection, body: &RegisterBody) -> AppResponse
         }
     }
 }
-pub async fn login(db: &DatabaseConnection, body: &LoginBody) -> AppResponse<Option<LoginInfo>> {
+pub async fn login(
+    db: &DatabaseConnection,
+    token_secret: &[u8],
+    body: &LoginBody,
+) -> AppResponse<Option<LoginInfo>> {
     let result = UserService::get_user_by_name(db, &body.username, "local").await;
     match result {
         Ok(model_op) => {
@@ -97,7 +135,7 @@ pub async fn login(db: &DatabaseConnection, body: &LoginBody) -> AppResponse<Opt
                 }
             } else {
                 let model = model_op.unwrap();
-                if model.password != body.password {
+                if !verify_password(&body.password, &model.password) {
                     return AppResponse {
                         code: RESPONSE_CODE_ERROR,
                         r#type: "".to_string(),
@@ -105,15 +143,34 @@ pub async fn login(db: &DatabaseConnection, body: &LoginBody) -> AppResponse<Opt
                         result: None,
                     };
                 }
-                // todo generate access token
-                let access_token = BASE64_STANDARD.encode(model.id.to_owned());
+                // upgrade plaintext password written by old versions
+                if is_legacy_password(&model.password) {
+                    if let Err(err) = upgrade_legacy_password(db, &model, &body.password).await {
+                        error!("upgrade legacy password failed, err: {}", err);
+                    }
+                }
+                let password_expired = model.state == USER_STATE_PASSWORD_EXPIRED;
+                let device = body.device.as_deref().unwrap_or(DEFAULT_DEVICE);
+                let tokens =
+                    match create_session(db, token_secret, &model.id, device, password_expired)
+                        .await
+                    {
+                        Ok(tokens) => tokens,
+                        Err(err) => {
+                            error!("create user session failed, err: {}", err);
+                            return AppResponse::error(None, &err.to_string());
+                        }
+                    };
                 let result = LoginInfo {
-                    access_token,
+                    access_token: tokens.access_token,
+                    refresh_token: tokens.refresh_token,
+                    session_id: tokens.session_id,
                     desc: "".to_owned(),
                     real_name: model.nickname.to_owned(),
                     user_id: model.id.to_owned(),
                     username: model.username.to_owned(),
                     mail: model.mail.clone(),
+                    password_expired,
                 };
                 AppResponse {
                     code: RESPONSE_CODE_SUCCESS,
@@ -135,6 +192,89 @@ pub async fn login(db: &DatabaseConnection, body: &LoginBody) -> AppResponse<Opt
     }
 }
 
+async fn upgrade_legacy_password(
+    db: &DatabaseConnection,
+    model: &Model,
+    password: &str,
+) -> Result<(), anyhow::Error> {
+    let hash = hash_password(password)?;
+    UserService::update_password(db, &model.id, &hash, model.state).await?;
+    Ok(())
+}
+
+pub async fn change_password(
+    db: &DatabaseConnection,
+    token_secret: &[u8],
+    user_id: &str,
+    session_id: &str,
+    body: &ChangePasswordBody,
+) -> AppResponse<Option<LoginInfo>> {
+    let model = match UserService::get_user_model_by_id(db, user_id).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return AppResponse::error(None, "User not found"),
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    if !verify_password(&body.old_password, &model.password) {
+        return AppResponse::error(None, "Old password incorrect");
+    }
+    if body.new_password.len() < PASSWORD_MIN_LEN {
+        return AppResponse::error(None, "Password is too short");
+    }
+    if body.new_password == body.old_password {
+        return AppResponse::error(None, "New password must be different from old password");
+    }
+    let hash = match hash_password(&body.new_password) {
+        Ok(hash) => hash,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    if let Err(err) = UserService::update_password(db, user_id, &hash, USER_STATE_NORMAL).await {
+        error!("change password failed, err: {}", err);
+        return AppResponse::error(None, &err.to_string());
+    }
+    // sign out everywhere else, the old password may be known by others
+    if let Err(err) = SessionService::revoke_other_sessions(db, user_id, session_id).await {
+        error!("revoke other sessions failed, err: {}", err);
+    }
+    // old token may be a password expired one, issue a normal token
+    AppResponse::success(Some(LoginInfo {
+        access_token: generate_token(
+            token_secret,
+            user_id,
+            session_id,
+            false,
+            ACCESS_TOKEN_EXPIRE_SECONDS,
+        ),
+        refresh_token: "".to_owned(),
+        session_id: session_id.to_owned(),
+        desc: "".to_owned(),
+        real_name: model.nickname.to_owned(),
+        user_id: model.id.to_owned(),
+        username: model.username.to_owned(),
+        mail: model.mail.clone(),
+        password_expired: false,
+    }))
+}
+
+/// Force the user to set a new password if the password still equals the given one.
+pub async fn expire_password_if_match(
+    db: &DatabaseConnection,
+    user_id: &str,
+    password: &str,
+) -> AppResponse<bool> {
+    let model = match UserService::get_user_model_by_id(db, user_id).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return AppResponse::error(false, "User not found"),
+        Err(err) => return AppResponse::error(false, &err.to_string()),
+    };
+    if model.state == USER_STATE_PASSWORD_EXPIRED || !verify_password(password, &model.password) {
+        return AppResponse::success(false);
+    }
+    match UserService::update_state(db, user_id, USER_STATE_PASSWORD_EXPIRED).await {
+        Ok(_) => AppResponse::success(true),
+        Err(err) => AppResponse::error(false, &err.to_string()),
+    }
+}
+
 pub async fn get_user_info(db: &DatabaseConnection, id: &str) -> AppResponse<Option<UserInfo>> {
     let result = UserService::get_user_by_id(db, id).await;
     match result {
@@ -209,12 +349,23 @@ pub async fn get_user_info_by_name(
 
 pub async fn refresh_token(
     db: &DatabaseConnection,
-    access_token: &str,
-) -> AppResponse<RefreshTokenResult> {
-    AppResponse::success(RefreshTokenResult {
-        data: access_token.to_owned(),
-        status: 0,
-    })
+    token_secret: &[u8],
+    body: &RefreshTokenBody,
+) -> AppResponse<Option<RefreshTokenResult>> {
+    let response = rotate_session(db, token_secret, body).await;
+    match response.result {
+        Some(tokens) => AppResponse::success(Some(RefreshTokenResult {
+            data: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+            status: 0,
+        })),
+        None => AppResponse {
+            code: response.code,
+            r#type: response.r#type,
+            message: response.message,
+            result: None,
+        },
+    }
 }
 
 pub async fn get_access_codes(db: &DatabaseConnection) -> AppResponse<Vec<String>> {
@@ -227,8 +378,16 @@ pub async fn get_access_codes(db: &DatabaseConnection) -> AppResponse<Vec<String
     AppResponse::success(codes)
 }
 
-pub async fn logout() -> Result<AppResponse<Option<String>>, ()> {
-    return Ok(AppResponse::success(Some(String::new())));
+pub async fn logout(
+    db: &DatabaseConnection,
+    user_id: &str,
+    session_id: &str,
+) -> AppResponse<Option<String>> {
+    let response = revoke_session(db, user_id, session_id).await;
+    if response.is_error() {
+        return AppResponse::error(None, &response.message);
+    }
+    AppResponse::success(Some(String::new()))
 }
 
 #[cfg(test)]
@@ -237,9 +396,19 @@ mod tests {
 
     use sea_orm::{ConnectionTrait, Schema};
 
-    use crate::service::user_service::{get_user_info, login, register, LoginBody, RegisterBody};
+    use crate::dao::user_dao::UserService;
+    use crate::service::session_service::RefreshTokenBody;
+    use crate::service::user_service::{
+        change_password, create, expire_password_if_match, get_user_info, login, logout,
+        refresh_token, register, ChangePasswordBody, LoginBody, RegisterBody,
+    };
     use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
-    use crate::{entity, RESPONSE_CODE_SUCCESS};
+    use crate::util::password_util::is_legacy_password;
+    use crate::util::token_util::verify_token;
+    use crate::{
+        entity, RESPONSE_CODE_SUCCESS, RESPONSE_CODE_TIMEOUT, USER_STATE_NORMAL,
+        USER_STATE_PASSWORD_EXPIRED,
+    };
 
     #[tokio::test]
     async fn test_user() {
@@ -264,6 +433,9 @@ mod tests {
         db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::User)))
             .await
             .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Session)))
+            .await
+            .unwrap();
         // register
         let username = "admin";
         let password = "123456";
@@ -295,11 +467,14 @@ mod tests {
         assert_eq!(username, user_info.username);
         // login
         let username = model.username.as_str();
+        let token_secret = b"0123456789abcdef0123456789abcdef";
         let login_info_response = login(
             &db,
+            token_secret,
             &LoginBody {
                 username: username.to_string(),
                 password: password.to_string(),
+                device: None,
             },
         )
         .await;
@@ -310,5 +485,149 @@ mod tests {
         }
         let login_info = option_login_info.unwrap();
         assert_ne!("", login_info.access_token);
+        let claims = verify_token(token_secret, &login_info.access_token).unwrap();
+        assert_eq!(model.id, claims.sub);
+        // refresh
+        let refresh_response = refresh_token(
+            &db,
+            token_secret,
+            &RefreshTokenBody {
+                refresh_token: login_info.refresh_token.clone(),
+            },
+        )
+        .await;
+        assert_eq!(RESPONSE_CODE_SUCCESS, refresh_response.code);
+        let refresh_result = refresh_response.result.unwrap();
+        let claims = verify_token(token_secret, &refresh_result.data).unwrap();
+        assert_eq!(login_info.session_id, claims.sid);
+        // logout
+        let logout_response = logout(&db, &model.id, &login_info.session_id).await;
+        assert_eq!(RESPONSE_CODE_SUCCESS, logout_response.code);
+        let refresh_response = refresh_token(
+            &db,
+            token_secret,
+            &RefreshTokenBody {
+                refresh_token: refresh_result.refresh_token,
+            },
+        )
+        .await;
+        assert_eq!(RESPONSE_CODE_TIMEOUT, refresh_response.code);
+    }
+
+    #[tokio::test]
+    async fn test_user_password() {
+        let temp_dir = temp_dir();
+        let base_path = temp_dir.join(".fatherbox");
+        let file_path = &base_path.join("test-user-password.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = init_connection(&file_path).await.unwrap();
+        let builder = db.get_database_backend();
+        let schema = Schema::new(builder);
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::User)))
+            .await
+            .unwrap();
+        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Session)))
+            .await
+            .unwrap();
+        let token_secret = b"0123456789abcdef0123456789abcdef";
+        let username = "default";
+        let password = "123456";
+        // 1. password is hashed
+        let model = create(
+            &db,
+            &RegisterBody {
+                username: username.to_string(),
+                password: password.to_string(),
+                nickname: "default user".to_string(),
+            },
+            USER_STATE_NORMAL,
+        )
+        .await
+        .result
+        .unwrap();
+        let stored = UserService::get_user_model_by_id(&db, &model.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(password, stored.password);
+        assert!(!is_legacy_password(&stored.password));
+        // 2. legacy plaintext password is upgraded on login
+        UserService::update_password(&db, &model.id, password, USER_STATE_NORMAL)
+            .await
+            .unwrap();
+        let login_body = LoginBody {
+            username: username.to_string(),
+            password: password.to_string(),
+            device: None,
+        };
+        let response = login(&db, token_secret, &login_body).await;
+        assert_eq!(RESPONSE_CODE_SUCCESS, response.code);
+        let stored = UserService::get_user_model_by_id(&db, &model.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!is_legacy_password(&stored.password));
+        // 3. wrong password
+        let response = login(
+            &db,
+            token_secret,
+            &LoginBody {
+                username: username.to_string(),
+                password: "654321".to_string(),
+                device: None,
+            },
+        )
+        .await;
+        assert!(response.is_error());
+        // 4. default password must be changed
+        assert!(
+            expire_password_if_match(&db, &model.id, password)
+                .await
+                .result
+        );
+        let login_info = login(&db, token_secret, &login_body).await.result.unwrap();
+        assert!(login_info.password_expired);
+        let claims = verify_token(token_secret, &login_info.access_token).unwrap();
+        assert!(claims.password_expired);
+        // 5. change password
+        let new_password = "abcdef";
+        let response = change_password(
+            &db,
+            token_secret,
+            &model.id,
+            &login_info.session_id,
+            &ChangePasswordBody {
+                old_password: password.to_string(),
+                new_password: new_password.to_string(),
+            },
+        )
+        .await;
+        assert_eq!(RESPONSE_CODE_SUCCESS, response.code);
+        let claims = verify_token(token_secret, &response.result.unwrap().access_token).unwrap();
+        assert!(!claims.password_expired);
+        let stored = UserService::get_user_model_by_id(&db, &model.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(USER_STATE_NORMAL, stored.state);
+        assert!(
+            !expire_password_if_match(&db, &model.id, password)
+                .await
+                .result
+        );
+        let response = login(
+            &db,
+            token_secret,
+            &LoginBody {
+                username: username.to_string(),
+                password: new_password.to_string(),
+                device: None,
+            },
+        )
+        .await;
+        assert!(!response.result.unwrap().password_expired);
+        assert_ne!(USER_STATE_PASSWORD_EXPIRED, stored.state);
     }
 }
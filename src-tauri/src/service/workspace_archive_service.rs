@@ -0,0 +1,590 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use log::error;
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbErr, Set, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::dao::chat_setting_dao::ChatSettingService;
+use crate::dao::file_dao::FileService;
+use crate::dao::workspace_dao::WorkspaceService;
+use crate::entity::{chat_setting, file, workspace};
+use crate::service::blob_service::{
+    file_path, hash_file, put_bytes, remove_unreferenced, StoredBlob,
+};
+use crate::service::permission_service::{check_workspace, Role};
+use crate::service::search_service::index_file;
+use crate::util::fs_util::{remove_path, write_atomic, write_atomic_with};
+use crate::{AppResponse, CHAT_ZONE, DIR_TYPE};
+
+// archive layout: the manifest plus one entry per distinct content
+const MANIFEST_NAME: &str = "manifest.json";
+const BLOB_DIR: &str = "blobs";
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportBody {
+    pub request_id: String,
+    pub wid: String,
+    // archive file to write
+    pub path: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportBody {
+    pub request_id: String,
+    // archive file to read
+    pub path: String,
+    // name of the new workspace, the exported name when empty
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressPayload {
+    pub done: u64,
+    pub total: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    version: u32,
+    wid: String,
+    name: String,
+    description: Option<String>,
+    icon: Option<String>,
+    color: Option<String>,
+    create_time: i64,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestFile {
+    id: String,
+    pid: String,
+    name: String,
+    r#type: String,
+    zone: String,
+    // content entry in the archive, none for dirs and chats without messages
+    hash: Option<String>,
+    size: i64,
+    create_time: i64,
+    update_time: i64,
+    // system prompt and generation parameters of a chat
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chat_setting: Option<chat_setting::Model>,
+}
+
+fn blob_entry(hash: &str) -> String {
+    format!("{}/{}", BLOB_DIR, hash)
+}
+
+fn write_archive(
+    file: &mut File,
+    manifest: &Manifest,
+    contents: &HashMap<String, PathBuf>,
+    progress: impl Fn(u64),
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+    zip.start_file(MANIFEST_NAME, options)
+        .map_err(io::Error::other)?;
+    zip.write_all(&serde_json::to_vec_pretty(manifest)?)?;
+    let mut written = HashSet::new();
+    for (done, entry) in manifest.files.iter().enumerate() {
+        if let Some(hash) = &entry.hash {
+            if written.insert(hash.clone()) {
+                zip.start_file(blob_entry(hash), options)
+                    .map_err(io::Error::other)?;
+                io::copy(&mut File::open(&contents[hash])?, &mut zip)?;
+            }
+        }
+        progress(done as u64 + 1);
+    }
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+/// Write the files and chats of the workspace with their content into a single zip archive,
+/// trashed files and versions are left out.
+pub async fn export_workspace<F>(
+    callback: F,
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    user_id: &str,
+    body: &ExportBody,
+) -> AppResponse<u64>
+where
+    F: Fn(u64, u64),
+{
+    let workspace = match check_workspace(db, user_id, &body.wid, Role::Viewer).await {
+        Ok(workspace) => workspace,
+        Err(err) => return err.response(0),
+    };
+    let models = match FileService::list_normal_files_by_wid(db, &workspace.id).await {
+        Ok(models) => models,
+        Err(err) => return AppResponse::error(0, &err.to_string()),
+    };
+    // where the content of each hash is read from, files written before the blob store are
+    // hashed where they live
+    let mut contents = HashMap::new();
+    let mut files = vec![];
+    for model in &models {
+        let is_chat = model.zone == CHAT_ZONE;
+        let hash = match (&model.hash, model.r#type == DIR_TYPE) {
+            (_, true) => None,
+            (Some(hash), false) => Some(hash.clone()),
+            // transcript is only written with the first message
+            (None, false) if is_chat && !file_path(user_path, model).exists() => None,
+            (None, false) => match hash_file(&file_path(user_path, model)) {
+                Ok(hash) => Some(hash),
+                Err(err) => return AppResponse::error(0, &err.to_string()),
+            },
+        };
+        if let Some(hash) = &hash {
+            contents.insert(hash.clone(), file_path(user_path, model));
+        }
+        let chat_setting = match is_chat {
+            true => match ChatSettingService::get_setting(db, &model.id).await {
+                Ok(setting) => setting,
+                Err(err) => return AppResponse::error(0, &err.to_string()),
+            },
+            false => None,
+        };
+        files.push(ManifestFile {
+            id: model.id.clone(),
+            pid: model.pid.clone(),
+            name: model.name.clone(),
+            r#type: model.r#type.clone(),
+            zone: model.zone.clone(),
+            hash,
+            size: model.size,
+            create_time: model.create_time,
+            update_time: model.update_time,
+            chat_setting,
+        });
+    }
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        wid: workspace.id.clone(),
+        name: workspace.name.clone(),
+        description: workspace.description.clone(),
+        icon: workspace.icon.clone(),
+        color: workspace.color.clone(),
+        create_time: workspace.create_time,
+        files,
+    };
+    let total = manifest.files.len() as u64;
+    callback(0, total);
+    let result = write_atomic_with(Path::new(&body.path), |file| {
+        write_archive(file, &manifest, &contents, |done| callback(done, total))
+    });
+    match result {
+        Ok(_) => AppResponse::success(total),
+        Err(err) => AppResponse::error(0, &err.to_string()),
+    }
+}
+
+fn read_manifest(archive: &mut ZipArchive<File>) -> Result<Manifest, anyhow::Error> {
+    let mut content = String::new();
+    archive
+        .by_name(MANIFEST_NAME)?
+        .read_to_string(&mut content)?;
+    let manifest: Manifest = serde_json::from_str(&content)?;
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(anyhow::anyhow!(
+            "archive version {} is newer than supported version {}",
+            manifest.version,
+            ARCHIVE_VERSION
+        ));
+    }
+    Ok(manifest)
+}
+
+// the name when free, otherwise the first free "name (n)"
+async fn free_name<C: ConnectionTrait>(db: &C, uid: &str, name: &str) -> Result<String, DbErr> {
+    let mut candidate = name.to_string();
+    let mut n = 2;
+    while WorkspaceService::get_workspace_by_uid_and_name(db, uid, &candidate)
+        .await?
+        .is_some()
+    {
+        candidate = format!("{} ({})", name, n);
+        n += 1;
+    }
+    Ok(candidate)
+}
+
+// content of the entry, refused when it does not match its hash
+fn read_blob(archive: &mut ZipArchive<File>, hash: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut content = vec![];
+    archive
+        .by_name(&blob_entry(hash))?
+        .read_to_end(&mut content)?;
+    if format!("{:x}", Sha256::digest(&content)) != hash {
+        return Err(anyhow::anyhow!("content of {} is corrupted", hash));
+    }
+    Ok(content)
+}
+
+// chat transcripts are read by the chat service at wid/id, they are written there and not
+// referenced from the blob store
+async fn import_content<C: ConnectionTrait>(
+    db: &C,
+    user_path: &Path,
+    archive: &mut ZipArchive<File>,
+    entry: &ManifestFile,
+    wid: &str,
+    id: &str,
+) -> Result<Option<StoredBlob>, anyhow::Error> {
+    if entry.r#type == DIR_TYPE {
+        fs::create_dir_all(user_path.join(wid).join(id))?;
+        return Ok(None);
+    }
+    let content = match &entry.hash {
+        Some(hash) => read_blob(archive, hash)?,
+        None => vec![],
+    };
+    if entry.zone != CHAT_ZONE {
+        return Ok(Some(put_bytes(db, user_path, &content).await?));
+    }
+    if entry.hash.is_some() {
+        write_atomic(&user_path.join(wid).join(id), &content)?;
+    }
+    Ok(None)
+}
+
+// drop what a failed import wrote on disk, its transaction must be finished
+async fn discard_import(db: &DatabaseConnection, user_path: &Path, wid: &str, hashes: &[String]) {
+    remove_unreferenced(db, user_path, hashes).await;
+    remove_path(&user_path.join(wid));
+}
+
+/// Recreate an exported workspace for the user under a new id, every file gets a new id and
+/// a taken name gets a number appended.
+pub async fn import_workspace<F>(
+    callback: F,
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    user_id: &str,
+    body: &ImportBody,
+) -> AppResponse<Option<workspace::Model>>
+where
+    F: Fn(u64, u64),
+{
+    let mut archive = match File::open(&body.path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| Ok(ZipArchive::new(file)?))
+    {
+        Ok(archive) => archive,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let manifest = match read_manifest(&mut archive) {
+        Ok(manifest) => manifest,
+        Err(err) => return AppResponse::error(None, &format!("invalid archive, {}", err)),
+    };
+    let name = match &body.name {
+        Some(name) if !name.trim().is_empty() => name.clone(),
+        _ => manifest.name.clone(),
+    };
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let name = match free_name(&txn, user_id, &name).await {
+        Ok(name) => name,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    let now = Utc::now().timestamp();
+    let wid = Uuid::new_v4().to_string();
+    let active_model = workspace::ActiveModel {
+        id: Set(wid.clone()),
+        uid: Set(user_id.to_string()),
+        name: Set(name),
+        create_time: Set(now),
+        update_time: Set(now),
+        state: Set(1),
+        description: Set(manifest.description.clone()),
+        icon: Set(manifest.icon.clone()),
+        color: Set(manifest.color.clone()),
+        created_by: Set(user_id.to_string()),
+    };
+    let workspace = match WorkspaceService::create_workspace(&txn, active_model).await {
+        Ok(workspace) => workspace,
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    // parents outside of the archive become the root, chats keep the workspace as parent
+    let ids: HashMap<&str, String> = manifest
+        .files
+        .iter()
+        .map(|entry| (entry.id.as_str(), Uuid::new_v4().to_string()))
+        .collect();
+    let total = manifest.files.len() as u64;
+    callback(0, total);
+    let mut hashes = vec![];
+    let mut models = vec![];
+    for (done, entry) in manifest.files.iter().enumerate() {
+        let id = ids[entry.id.as_str()].clone();
+        let stored = import_content(&txn, user_path, &mut archive, entry, &wid, &id).await;
+        let stored = match stored {
+            Ok(stored) => stored,
+            Err(err) => {
+                error!("import file {} failed, err: {}", entry.id, err);
+                drop(txn);
+                discard_import(db, user_path, &wid, &hashes).await;
+                return AppResponse::error(None, &err.to_string());
+            }
+        };
+        if let Some(stored) = &stored {
+            hashes.push(stored.hash.clone());
+        }
+        let active_model = file::ActiveModel {
+            id: Set(id),
+            name: Set(entry.name.clone()),
+            r#type: Set(entry.r#type.clone()),
+            pid: Set(match entry.pid == manifest.wid {
+                true => wid.clone(),
+                false => ids.get(entry.pid.as_str()).cloned().unwrap_or_default(),
+            }),
+            wid: Set(wid.clone()),
+            zone: Set(entry.zone.clone()),
+            size: Set(stored.as_ref().map_or(entry.size, |stored| stored.size)),
+            hash: Set(stored.map(|stored| stored.hash)),
+            create_time: Set(entry.create_time),
+            update_time: Set(entry.update_time),
+            state: Set(1),
+            ..Default::default()
+        };
+        let result = async {
+            let model = FileService::create_file(&txn, active_model).await?;
+            if let Some(setting) = &entry.chat_setting {
+                let active_model = chat_setting::ActiveModel {
+                    id: Set(model.id.clone()),
+                    system_prompt: Set(setting.system_prompt.clone()),
+                    temperature: Set(setting.temperature),
+                    top_p: Set(setting.top_p),
+                    max_tokens: Set(setting.max_tokens),
+                    stop: Set(setting.stop.clone()),
+                    seed: Set(setting.seed),
+                    fallbacks: Set(setting.fallbacks.clone()),
+                    create_time: Set(now),
+                    update_time: Set(now),
+                };
+                ChatSettingService::save_setting(&txn, active_model, false).await?;
+            }
+            Ok::<file::Model, DbErr>(model)
+        }
+        .await;
+        match result {
+            Ok(model) => models.push(model),
+            Err(err) => {
+                drop(txn);
+                discard_import(db, user_path, &wid, &hashes).await;
+                return AppResponse::error(None, &err.to_string());
+            }
+        }
+        callback(done as u64 + 1, total);
+    }
+    if let Err(err) = txn.commit().await {
+        discard_import(db, user_path, &wid, &hashes).await;
+        return AppResponse::error(None, &err.to_string());
+    }
+    for model in &models {
+        index_file(db, user_path, model).await;
+    }
+    AppResponse::success(Some(workspace))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::env::temp_dir;
+    use std::fs;
+
+    use crate::dao::file_dao::FileService;
+    use crate::dto::file::CreateBody;
+    use crate::migration::migrate;
+    use crate::service::ai_chat_service::{
+        create as create_chat, get_settings, message_list, update_settings, ChatSettings,
+        CreateBody as ChatCreateBody, Message, MessageRole, UpdateSettingsBody,
+    };
+    use crate::service::blob_service::file_path;
+    use crate::service::chat_tree_service::{save_chat, ChatTree};
+    use crate::service::file_service::create_file;
+    use crate::service::workspace_archive_service::{
+        export_workspace, import_workspace, ExportBody, ImportBody,
+    };
+    use crate::service::workspace_service::create_workspace;
+    use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+    use crate::{CHAT_ZONE, DIR_TYPE, FILE_TYPE};
+
+    #[tokio::test]
+    async fn test_workspace_archive() {
+        let temp_dir = temp_dir();
+        let base_path = &temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-workspace-archive");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        let db_path = &base_path.join("test-workspace-archive.sqlite");
+        if exist_database_file(db_path) {
+            drop_database_file(&db_path).unwrap();
+        }
+        let db = &init_connection(&db_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let user_id = "test-user";
+        let wid = create_workspace(db, user_id, "notes")
+            .await
+            .result
+            .unwrap()
+            .id;
+        let create_body = |pid: &str, name: &str, r#type: &str, zone: &str| CreateBody {
+            name: name.to_string(),
+            pid: pid.to_string(),
+            wid: wid.clone(),
+            r#type: r#type.to_string(),
+            zone: zone.to_string(),
+            content: match r#type == FILE_TYPE {
+                true => Some(format!("content of {}", name).into_bytes()),
+                false => None,
+            },
+            path: None,
+        };
+        let dir = create_file(
+            db,
+            user_path,
+            user_id,
+            &create_body("", "dir", DIR_TYPE, ""),
+        )
+        .await
+        .result
+        .unwrap();
+        create_file(
+            db,
+            user_path,
+            user_id,
+            &create_body(&dir.id, "a.md", FILE_TYPE, ""),
+        )
+        .await;
+        let chat = create_chat(
+            db,
+            user_id,
+            &ChatCreateBody {
+                name: "chat".to_string(),
+                wid: wid.clone(),
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        let tree = ChatTree::from_messages(vec![
+            Message::new(MessageRole::User, "hello"),
+            Message::new(MessageRole::Assistant, "hi"),
+        ]);
+        save_chat(&user_path.join(&wid).join(&chat.id), &tree).unwrap();
+        let settings = ChatSettings {
+            system_prompt: Some("be brief".to_string()),
+            ..Default::default()
+        };
+        let settings_body = UpdateSettingsBody {
+            id: chat.id.clone(),
+            settings: settings.clone(),
+        };
+        assert!(update_settings(db, user_id, &settings_body)
+            .await
+            .is_success());
+        // 1. export reports progress up to every file
+        let archive_path = base_path.join("test-workspace-archive.zip");
+        let progress = Cell::new((0, 0));
+        let export_body = &ExportBody {
+            request_id: "export".to_string(),
+            wid: wid.clone(),
+            path: archive_path.to_str().unwrap().to_string(),
+        };
+        let response = export_workspace(
+            |done, total| progress.set((done, total)),
+            db,
+            user_path,
+            user_id,
+            export_body,
+        )
+        .await;
+        assert_eq!(3, response.result);
+        assert_eq!((3, 3), progress.get());
+        assert!(
+            export_workspace(|_, _| {}, db, user_path, "other-user", export_body)
+                .await
+                .is_forbidden()
+        );
+        // 2. import recreates the tree under new ids, a taken name gets a number
+        let import_body = &ImportBody {
+            request_id: "import".to_string(),
+            path: archive_path.to_str().unwrap().to_string(),
+            name: None,
+        };
+        let imported = import_workspace(|_, _| {}, db, user_path, user_id, import_body)
+            .await
+            .result
+            .unwrap();
+        assert_ne!(wid, imported.id);
+        assert_eq!("notes (2)", imported.name);
+        let models = FileService::list_files_by_wid(db, &imported.id)
+            .await
+            .unwrap();
+        assert_eq!(3, models.len());
+        let new_dir = models.iter().find(|model| model.name == "dir").unwrap();
+        assert_ne!(dir.id, new_dir.id);
+        let file = models.iter().find(|model| model.name == "a.md").unwrap();
+        assert_eq!(new_dir.id, file.pid);
+        assert_eq!(
+            b"content of a.md".to_vec(),
+            fs::read(file_path(user_path, file)).unwrap()
+        );
+        let new_chat = models.iter().find(|model| model.name == "chat").unwrap();
+        assert_eq!(CHAT_ZONE, new_chat.zone);
+        assert_eq!(imported.id, new_chat.pid);
+        assert!(new_chat.hash.is_none());
+        let messages = message_list(db, user_path, user_id, &new_chat.id)
+            .await
+            .result
+            .unwrap();
+        assert_eq!(2, messages.len());
+        assert_eq!("hi", messages[1].node.message.content);
+        assert_eq!(
+            Some(settings),
+            get_settings(db, user_id, &new_chat.id).await.result
+        );
+        // 3. the other user imports it as own workspace
+        let imported = import_workspace(|_, _| {}, db, user_path, "other-user", import_body)
+            .await
+            .result
+            .unwrap();
+        assert_eq!("notes", imported.name);
+        assert_eq!("other-user", imported.uid);
+        // 4. not an archive
+        let bad_body = &ImportBody {
+            request_id: "import".to_string(),
+            path: db_path.to_str().unwrap().to_string(),
+            name: None,
+        };
+        assert!(
+            !import_workspace(|_, _| {}, db, user_path, user_id, bad_body)
+                .await
+                .is_success()
+        );
+    }
+}
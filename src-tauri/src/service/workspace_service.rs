@@ -1,13 +1,25 @@
+use std::path::PathBuf;
+
 use chrono::Utc;
 use sea_orm::ActiveValue::Set;
-use sea_orm::DatabaseConnection;
+use sea_orm::{DatabaseConnection, DbErr, TransactionTrait};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::dao::file_dao::FileService;
+use crate::dao::prompt_template_dao::PromptTemplateService;
+use crate::dao::setting_dao::SettingService;
+use crate::dao::user_dao::UserService;
 use crate::dao::workspace_dao::WorkspaceService;
-use crate::entity::workspace;
+use crate::dao::workspace_member_dao::WorkspaceMemberService;
 use crate::entity::workspace::Model;
-use crate::AppResponse;
+use crate::entity::{workspace, workspace_member};
+use crate::service::file_service::{clean_removed_files, delete_file_rows, move_workspace_files};
+use crate::service::permission_service::{check_workspace, list_workspace_ids, AccessError, Role};
+use crate::service::search_service::move_indexed_workspace;
+use crate::service::setting_service::{save_setting, user_setting_key, workspace_setting_prefix};
+use crate::util::fs_util::remove_path;
+use crate::{AppResponse, DEFAULT_WORKSPACE, DEFAULT_WORKSPACE_SETTING_KEY};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
 #[serde(rename_all = "camelCase")]
@@ -21,11 +33,84 @@ pub struct GeneralBody {
     pub id: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateBody {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteBody {
+    pub id: String,
+    // workspace the files and chats are moved to instead of being deleted
+    #[serde(default)]
+    pub move_to: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberListBody {
+    pub wid: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberInviteBody {
+    pub wid: String,
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberBody {
+    pub wid: String,
+    pub uid: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberRoleBody {
+    pub wid: String,
+    pub uid: String,
+    pub role: Role,
+}
+
+// names are unique among the workspaces of an owner
+async fn check_name(
+    db: &DatabaseConnection,
+    uid: &str,
+    name: &str,
+    id: Option<&str>,
+) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("workspace name is empty".to_string());
+    }
+    match WorkspaceService::get_workspace_by_uid_and_name(db, uid, name).await {
+        Ok(Some(model)) if Some(model.id.as_str()) != id => {
+            Err(format!("workspace {} already exists", name))
+        }
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
 pub async fn create_workspace(
     db: &DatabaseConnection,
     uid: &str,
     name: &str,
-) -> AppResponse<Model> {
+) -> AppResponse<Option<Model>> {
+    if let Err(message) = check_name(db, uid, name, None).await {
+        return AppResponse::error(None, &message);
+    }
     let active_model = workspace::ActiveModel {
         id: Set(Uuid::new_v4().to_string()),
         uid: Set(uid.to_string()),
@@ -33,22 +118,130 @@ pub async fn create_workspace(
         create_time: Set(Utc::now().timestamp()),
         update_time: Set(Utc::now().timestamp()),
         state: Set(1),
+        created_by: Set(uid.to_string()),
         ..Default::default()
     };
-    let model = WorkspaceService::create_workspace(db, active_model)
-        .await
-        .unwrap();
-    AppResponse::success(model)
+    match WorkspaceService::create_workspace(db, active_model).await {
+        Ok(model) => AppResponse::success(Some(model)),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
 }
 
-pub async fn list_workspaces(db: &DatabaseConnection) -> AppResponse<Vec<Model>> {
-    let vec = WorkspaceService::list_workspaces(db).await.unwrap();
-    AppResponse::success(vec)
+/// Rename the workspace and replace its metadata, only owners change them.
+pub async fn update_workspace(
+    db: &DatabaseConnection,
+    uid: &str,
+    body: &UpdateBody,
+) -> AppResponse<Option<Model>> {
+    let model = match check_workspace(db, uid, &body.id, Role::Owner).await {
+        Ok(model) => model,
+        Err(err) => return err.response(None),
+    };
+    if let Err(message) = check_name(db, &model.uid, &body.name, Some(&model.id)).await {
+        return AppResponse::error(None, &message);
+    }
+    // the default workspace is found by its name until an other one is set, keep it
+    if model.name == DEFAULT_WORKSPACE && body.name != DEFAULT_WORKSPACE {
+        match get_default_workspace(db, &model.uid).await {
+            Ok(Some(default)) if default.id == model.id => {
+                let key = user_setting_key(&model.uid, DEFAULT_WORKSPACE_SETTING_KEY);
+                if let Err(err) = save_setting(db, &key, &model.id).await {
+                    return AppResponse::error(None, &err.to_string());
+                }
+            }
+            Ok(_) => {}
+            Err(err) => return AppResponse::error(None, &err.to_string()),
+        }
+    }
+    let active_model = workspace::ActiveModel {
+        id: Set(model.id.clone()),
+        name: Set(body.name.clone()),
+        description: Set(body.description.clone()),
+        icon: Set(body.icon.clone()),
+        color: Set(body.color.clone()),
+        update_time: Set(Utc::now().timestamp()),
+        ..Default::default()
+    };
+    match WorkspaceService::update_workspace(db, active_model).await {
+        Ok(model) => AppResponse::success(model),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
 }
 
-pub async fn get_workspace(db: &DatabaseConnection, id: &str) -> AppResponse<Option<Model>> {
-    let model = WorkspaceService::get_workspace(db, id).await.unwrap();
-    AppResponse::success(model)
+/// Workspaces owned by the user, without the ones shared with the user.
+pub async fn list_my_workspaces(db: &DatabaseConnection, uid: &str) -> AppResponse<Vec<Model>> {
+    match WorkspaceService::list_workspaces_by_uid(db, uid).await {
+        Ok(models) => AppResponse::success(models),
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+/// Workspace the user works in by default, the one named default unless an other one was set.
+pub async fn get_default_workspace(
+    db: &DatabaseConnection,
+    uid: &str,
+) -> Result<Option<Model>, DbErr> {
+    let key = user_setting_key(uid, DEFAULT_WORKSPACE_SETTING_KEY);
+    if let Some(setting) = SettingService::get_setting_by_key(db, &key).await? {
+        let wid = String::from_utf8_lossy(&setting.value).to_string();
+        match check_workspace(db, uid, &wid, Role::Viewer).await {
+            Ok(model) => return Ok(Some(model)),
+            Err(AccessError::Db(err)) => return Err(err),
+            // deleted or no longer shared
+            Err(_) => {}
+        }
+    }
+    WorkspaceService::get_workspace_by_uid_and_name(db, uid, DEFAULT_WORKSPACE).await
+}
+
+pub async fn default_workspace(db: &DatabaseConnection, uid: &str) -> AppResponse<Option<Model>> {
+    match get_default_workspace(db, uid).await {
+        Ok(model) => AppResponse::success(model),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+pub async fn set_default_workspace(
+    db: &DatabaseConnection,
+    uid: &str,
+    id: &str,
+) -> AppResponse<Option<Model>> {
+    let model = match check_workspace(db, uid, id, Role::Viewer).await {
+        Ok(model) => model,
+        Err(err) => return err.response(None),
+    };
+    let key = user_setting_key(uid, DEFAULT_WORKSPACE_SETTING_KEY);
+    match save_setting(db, &key, &model.id).await {
+        Ok(_) => AppResponse::success(Some(model)),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+/// Workspaces the user owns, followed by the ones shared with the user.
+pub async fn list_workspaces(db: &DatabaseConnection, uid: &str) -> AppResponse<Vec<Model>> {
+    let wids = match list_workspace_ids(db, uid).await {
+        Ok(wids) => wids,
+        Err(err) => return AppResponse::error(vec![], &err.to_string()),
+    };
+    match WorkspaceService::list_workspaces_by_ids(db, &wids).await {
+        Ok(mut models) => {
+            models.sort_by_key(|model| wids.iter().position(|wid| *wid == model.id));
+            AppResponse::success(models)
+        }
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+pub async fn get_workspace(
+    db: &DatabaseConnection,
+    uid: &str,
+    id: &str,
+) -> AppResponse<Option<Model>> {
+    match check_workspace(db, uid, id, Role::Viewer).await {
+        Ok(model) => AppResponse::success(Some(model)),
+        Err(AccessError::NotFound(_)) => AppResponse::success(None),
+        Err(err) => err.response(None),
+    }
 }
 
 pub async fn get_workspace_by_name(
@@ -61,29 +254,203 @@ pub async fn get_workspace_by_name(
     AppResponse::success(model)
 }
 
-pub async fn delete_workspace(db: &DatabaseConnection, id: &str) -> AppResponse<String> {
-    let model = get_workspace(db, id).await.result;
-    match model {
-        None => AppResponse::success("".to_string()),
-        Some(_) => {
-            let _ = WorkspaceService::delete_workspace(db, id).await.unwrap();
-            AppResponse::success("".to_string())
+/// Delete the workspace with its files, chats, members and settings, or move the files and
+/// chats to an other workspace first when move_to is set.
+pub async fn delete_workspace(
+    db: &DatabaseConnection,
+    user_path: &PathBuf,
+    uid: &str,
+    body: &DeleteBody,
+) -> AppResponse<String> {
+    let workspace = match check_workspace(db, uid, &body.id, Role::Owner).await {
+        Ok(workspace) => workspace,
+        Err(AccessError::NotFound(_)) => return AppResponse::success("".to_string()),
+        Err(err) => return err.response("".to_string()),
+    };
+    match get_default_workspace(db, &workspace.uid).await {
+        Ok(Some(default)) if default.id == workspace.id => {
+            return AppResponse::error("".to_string(), "default workspace can not be deleted")
+        }
+        Ok(_) => {}
+        Err(err) => return AppResponse::error("".to_string(), &err.to_string()),
+    }
+    if let Some(move_to) = &body.move_to {
+        if *move_to == workspace.id {
+            return AppResponse::error("".to_string(), "can not move into the deleted workspace");
+        }
+        if let Err(err) = check_workspace(db, uid, move_to, Role::Editor).await {
+            return err.response("".to_string());
         }
     }
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(err) => return AppResponse::error("".to_string(), &err.to_string()),
+    };
+    // files and blob hashes to clean up once committed, none when the files are moved
+    let removed = match &body.move_to {
+        Some(move_to) => {
+            match move_workspace_files(&txn, user_path, &workspace.id, move_to).await {
+                Ok(_) => None,
+                Err(err) => return AppResponse::error("".to_string(), &err.to_string()),
+            }
+        }
+        None => {
+            let models = match FileService::list_files_by_wid(&txn, &workspace.id).await {
+                Ok(models) => models,
+                Err(err) => return AppResponse::error("".to_string(), &err.to_string()),
+            };
+            match delete_file_rows(&txn, &models).await {
+                Ok(hashes) => Some((models, hashes)),
+                Err(err) => return AppResponse::error("".to_string(), &err.to_string()),
+            }
+        }
+    };
+    let prefix = workspace_setting_prefix(&workspace.id);
+    let result = async {
+        WorkspaceMemberService::delete_members_by_wid(&txn, &workspace.id).await?;
+        SettingService::delete_settings_by_prefix(&txn, &prefix).await?;
+        match &body.move_to {
+            Some(move_to) => {
+                PromptTemplateService::move_templates(&txn, &workspace.id, move_to).await?
+            }
+            None => PromptTemplateService::delete_templates_by_wid(&txn, &workspace.id).await?,
+        };
+        WorkspaceService::delete_workspace(&txn, &workspace.id).await
+    }
+    .await;
+    if let Err(err) = result {
+        return AppResponse::error("".to_string(), &err.to_string());
+    }
+    if let Err(err) = txn.commit().await {
+        return AppResponse::error("".to_string(), &err.to_string());
+    }
+    match (&body.move_to, removed) {
+        (_, Some((models, hashes))) => clean_removed_files(db, user_path, &models, &hashes).await,
+        (Some(move_to), None) => move_indexed_workspace(db, &workspace.id, move_to).await,
+        (None, None) => {}
+    }
+    // dirs and files written before the blob store
+    remove_path(&user_path.join(&workspace.id));
+    AppResponse::success("".to_string())
+}
+
+pub async fn list_members(
+    db: &DatabaseConnection,
+    uid: &str,
+    body: &MemberListBody,
+) -> AppResponse<Vec<workspace_member::Model>> {
+    if let Err(err) = check_workspace(db, uid, &body.wid, Role::Viewer).await {
+        return err.response(vec![]);
+    }
+    match WorkspaceMemberService::list_members(db, &body.wid).await {
+        Ok(members) => AppResponse::success(members),
+        Err(err) => AppResponse::error(vec![], &err.to_string()),
+    }
+}
+
+/// Share the workspace with an other local user.
+pub async fn invite_member(
+    db: &DatabaseConnection,
+    uid: &str,
+    body: &MemberInviteBody,
+) -> AppResponse<Option<workspace_member::Model>> {
+    let workspace = match check_workspace(db, uid, &body.wid, Role::Owner).await {
+        Ok(workspace) => workspace,
+        Err(err) => return err.response(None),
+    };
+    let user = match UserService::get_user_by_name(db, &body.username, "local").await {
+        Ok(Some(user)) => user,
+        Ok(None) => return AppResponse::error(None, "user not found"),
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    };
+    if user.id == workspace.uid {
+        return AppResponse::error(None, "user already owns the workspace");
+    }
+    match WorkspaceMemberService::get_member(db, &body.wid, &user.id).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return AppResponse::error(None, "user is already a member"),
+        Err(err) => return AppResponse::error(None, &err.to_string()),
+    }
+    let active_model = workspace_member::ActiveModel {
+        id: Set(Uuid::new_v4().to_string()),
+        wid: Set(body.wid.clone()),
+        uid: Set(user.id),
+        role: Set(body.role.as_str().to_string()),
+        create_time: Set(Utc::now().timestamp()),
+        update_time: Set(Utc::now().timestamp()),
+    };
+    match WorkspaceMemberService::create_member(db, active_model).await {
+        Ok(member) => AppResponse::success(Some(member)),
+        Err(err) => AppResponse::error(None, &err.to_string()),
+    }
+}
+
+/// Remove a member, owners remove anyone and every member may leave.
+pub async fn remove_member(
+    db: &DatabaseConnection,
+    uid: &str,
+    body: &MemberBody,
+) -> AppResponse<u64> {
+    let role = match body.uid == uid {
+        true => Role::Viewer,
+        false => Role::Owner,
+    };
+    if let Err(err) = check_workspace(db, uid, &body.wid, role).await {
+        return err.response(0);
+    }
+    match WorkspaceMemberService::delete_member(db, &body.wid, &body.uid).await {
+        Ok(count) => AppResponse::success(count),
+        Err(err) => AppResponse::error(0, &err.to_string()),
+    }
+}
+
+pub async fn update_member_role(
+    db: &DatabaseConnection,
+    uid: &str,
+    body: &MemberRoleBody,
+) -> AppResponse<u64> {
+    if let Err(err) = check_workspace(db, uid, &body.wid, Role::Owner).await {
+        return err.response(0);
+    }
+    match WorkspaceMemberService::update_role(db, &body.wid, &body.uid, body.role.as_str()).await {
+        Ok(0) => AppResponse::error(0, "member not found"),
+        Ok(count) => AppResponse::success(count),
+        Err(err) => AppResponse::error(0, &err.to_string()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::env::temp_dir;
+    use std::fs;
 
-    use sea_orm::{ConnectionTrait, Schema};
+    use sea_orm::Set;
     use tauri::Manager;
 
-    use crate::entity;
+    use crate::dao::file_dao::FileService;
+    use crate::dao::setting_dao::SettingService;
+    use crate::dto::file::{CreateBody, FsckBody, GeneralBody, ListGeneralBody, SearchBody};
+    use crate::entity::setting;
+    use crate::migration::migrate;
+    use crate::service::ai_chat_service::{
+        create as create_chat, message_list, CreateBody as ChatCreateBody, Message, MessageRole,
+    };
+    use crate::service::blob_service::blob_path;
+    use crate::service::chat_tree_service::{save_chat, ChatTree};
+    use crate::service::file_service::{create_file, get_file, get_workspace_files};
+    use crate::service::fsck_service::fsck;
+    use crate::service::permission_service::Role;
+    use crate::service::search_service::search;
+    use crate::service::setting_service::workspace_setting_key;
+    use crate::service::user_service::{register, RegisterBody};
     use crate::service::workspace_service::{
-        create_workspace, delete_workspace, get_workspace, list_workspaces,
+        create_workspace, default_workspace, delete_workspace, get_workspace, invite_member,
+        list_members, list_my_workspaces, list_workspaces, remove_member, set_default_workspace,
+        update_member_role, update_workspace, DeleteBody, MemberBody, MemberInviteBody,
+        MemberListBody, MemberRoleBody, UpdateBody,
     };
     use crate::util::db_util::{drop_database_file, exist_database_file, init_connection};
+    use crate::{CHAT_ZONE, DEFAULT_WORKSPACE, DIR_TYPE, FILE_TYPE};
 
     #[tokio::test]
     async fn test_workspace() {
@@ -94,27 +461,373 @@ mod tests {
             drop_database_file(&file_path).unwrap();
         }
         let db = init_connection(&file_path).await.unwrap();
-        let builder = db.get_database_backend();
-        let schema = Schema::new(builder);
-        db.execute(builder.build(&schema.create_table_from_entity(entity::prelude::Workspace)))
-            .await
-            .unwrap();
+        migrate(&db).await.unwrap();
+        let user_path = &base_path.join("test-workspace");
+        let delete_body = |id: &str| DeleteBody {
+            id: id.to_string(),
+            move_to: None,
+        };
         // begin invoke
         // 1. test create
-        let create_result = create_workspace(&db, "123", "default").await.result;
+        let create_result = create_workspace(&db, "123", "default")
+            .await
+            .result
+            .unwrap();
         assert_eq!("default", create_result.name);
         // 2. test get
-        let default_workspace_result = get_workspace(&db, &create_result.id).await.result;
+        let default_workspace_result = get_workspace(&db, "123", &create_result.id).await.result;
         assert_eq!(false, default_workspace_result.is_none());
         // 3. test list
         create_workspace(&db, "123", "abc").await.result;
-        let workspaces = list_workspaces(&db).await.result;
+        create_workspace(&db, "456", "other").await.result;
+        let workspaces = list_workspaces(&db, "123").await.result;
         assert_eq!(2, workspaces.len());
         assert_eq!("default", workspaces[0].name);
         assert_eq!("abc", workspaces[1].name);
-        // 4. test delete
-        delete_workspace(&db, &workspaces[0].id).await;
-        let deleted_workspace_result = get_workspace(&db, &workspaces[0].id).await.result;
+        // 4. workspace of an other user
+        assert!(get_workspace(&db, "456", &workspaces[0].id)
+            .await
+            .is_forbidden());
+        assert!(
+            delete_workspace(&db, user_path, "456", &delete_body(&workspaces[1].id))
+                .await
+                .is_forbidden()
+        );
+        // 5. test delete, the default workspace is kept
+        assert!(
+            !delete_workspace(&db, user_path, "123", &delete_body(&workspaces[0].id))
+                .await
+                .is_success()
+        );
+        delete_workspace(&db, user_path, "123", &delete_body(&workspaces[1].id)).await;
+        let deleted_workspace_result = get_workspace(&db, "123", &workspaces[1].id).await.result;
         assert_eq!(true, deleted_workspace_result.is_none())
     }
+
+    #[tokio::test]
+    async fn test_workspace_update() {
+        let temp_dir = temp_dir();
+        let base_path = temp_dir.join(".fatherbox");
+        let file_path = &base_path.join("test-workspace-update.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let user_path = &base_path.join("test-workspace-update");
+        let update_body = |id: &str, name: &str| UpdateBody {
+            id: id.to_string(),
+            name: name.to_string(),
+            description: Some("notes".to_string()),
+            icon: Some("book".to_string()),
+            color: Some("#ffffff".to_string()),
+        };
+        let default = create_workspace(db, "123", DEFAULT_WORKSPACE)
+            .await
+            .result
+            .unwrap();
+        let abc = create_workspace(db, "123", "abc").await.result.unwrap();
+        assert_eq!("123", abc.created_by);
+        // 1. names are unique per user
+        assert!(!create_workspace(db, "123", "abc").await.is_success());
+        assert!(create_workspace(db, "456", "abc").await.is_success());
+        assert!(
+            !update_workspace(db, "123", &update_body(&abc.id, DEFAULT_WORKSPACE))
+                .await
+                .is_success()
+        );
+        // 2. rename and metadata, only by owners
+        assert!(update_workspace(db, "456", &update_body(&abc.id, "work"))
+            .await
+            .is_forbidden());
+        let work = update_workspace(db, "123", &update_body(&abc.id, "work"))
+            .await
+            .result
+            .unwrap();
+        assert_eq!("work", work.name);
+        assert_eq!(Some("notes".to_string()), work.description);
+        assert_eq!(Some("#ffffff".to_string()), work.color);
+        assert_eq!(1, list_my_workspaces(db, "456").await.result.len());
+        // 3. default workspace survives a rename
+        assert_eq!(
+            default.id,
+            default_workspace(db, "123").await.result.unwrap().id
+        );
+        update_workspace(db, "123", &update_body(&default.id, "home")).await;
+        assert_eq!(
+            default.id,
+            default_workspace(db, "123").await.result.unwrap().id
+        );
+        // 4. an other default, the previous one may be deleted then
+        assert!(set_default_workspace(db, "456", &work.id)
+            .await
+            .is_forbidden());
+        assert!(set_default_workspace(db, "123", &work.id)
+            .await
+            .is_success());
+        let delete_body = |id: &str| DeleteBody {
+            id: id.to_string(),
+            move_to: None,
+        };
+        assert!(
+            !delete_workspace(db, user_path, "123", &delete_body(&work.id))
+                .await
+                .is_success()
+        );
+        assert!(
+            delete_workspace(db, user_path, "123", &delete_body(&default.id))
+                .await
+                .is_success()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_workspace_delete() {
+        let delete_body = |id: &str| DeleteBody {
+            id: id.to_string(),
+            move_to: None,
+        };
+        let temp_dir = temp_dir();
+        let base_path = temp_dir.join(".fatherbox");
+        let user_path = &base_path.join("test-workspace-delete");
+        if user_path.exists() {
+            fs::remove_dir_all(user_path).unwrap();
+        }
+        let file_path = &base_path.join("test-workspace-delete.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let uid = "test-user";
+        let create_body = |wid: &str, pid: &str, name: &str, r#type: &str, zone: &str| CreateBody {
+            name: name.to_string(),
+            pid: pid.to_string(),
+            wid: wid.to_string(),
+            r#type: r#type.to_string(),
+            zone: zone.to_string(),
+            content: match r#type == FILE_TYPE {
+                true => Some(name.as_bytes().to_vec()),
+                false => None,
+            },
+            path: None,
+        };
+        let search_body = |wid: &str, query: &str| SearchBody {
+            wid: wid.to_string(),
+            zone: None,
+            query: query.to_string(),
+            page_size: 20,
+            page_num: 0,
+        };
+        let source = create_workspace(db, uid, "source").await.result.unwrap().id;
+        let target = create_workspace(db, uid, "target").await.result.unwrap().id;
+        let dir = create_file(
+            db,
+            user_path,
+            uid,
+            &create_body(&source, "", "dir", DIR_TYPE, ""),
+        )
+        .await
+        .result
+        .unwrap();
+        let file = create_file(
+            db,
+            user_path,
+            uid,
+            &create_body(&source, &dir.id, "note", FILE_TYPE, ""),
+        )
+        .await
+        .result
+        .unwrap();
+        let chat = create_chat(
+            db,
+            uid,
+            &ChatCreateBody {
+                name: "chat".to_string(),
+                wid: source.clone(),
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        let tree = ChatTree::from_messages(vec![
+            Message::new(MessageRole::User, "hello"),
+            Message::new(MessageRole::Assistant, "hi"),
+        ]);
+        save_chat(&user_path.join(&source).join(&chat.id), &tree).unwrap();
+        let setting_key = workspace_setting_key(&source, "theme");
+        let setting = setting::ActiveModel {
+            key: Set(setting_key.clone()),
+            value: Set(b"dark".to_vec()),
+            create_time: Set(0),
+            update_time: Set(0),
+            state: Set(1),
+        };
+        SettingService::create_setting(db, setting).await.unwrap();
+        // 1. contents move to the other workspace
+        let move_body = &DeleteBody {
+            id: source.clone(),
+            move_to: Some(target.clone()),
+        };
+        assert!(delete_workspace(db, user_path, uid, move_body)
+            .await
+            .is_success());
+        assert!(get_workspace(db, uid, &source).await.result.is_none());
+        let list_body = |zone: &str| ListGeneralBody {
+            wid: target.clone(),
+            zone: zone.to_string(),
+            r#type: None,
+        };
+        assert_eq!(
+            2,
+            get_workspace_files(db, uid, &list_body(""))
+                .await
+                .result
+                .len()
+        );
+        assert_eq!(
+            1,
+            get_workspace_files(db, uid, &list_body(CHAT_ZONE))
+                .await
+                .result
+                .len()
+        );
+        let moved = get_file(
+            db,
+            uid,
+            &GeneralBody {
+                wid: target.clone(),
+                id: file.id.clone(),
+            },
+        )
+        .await
+        .result
+        .unwrap();
+        assert_eq!(dir.id, moved.pid);
+        let messages = message_list(db, user_path, uid, &chat.id)
+            .await
+            .result
+            .unwrap();
+        assert_eq!(2, messages.len());
+        assert_eq!("hi", messages[1].node.message.content);
+        assert!(!user_path.join(&source).exists());
+        let report = fsck(db, user_path, &FsckBody { repair: false })
+            .await
+            .result
+            .unwrap();
+        assert!(report.issues.is_empty());
+        assert_eq!(
+            1,
+            search(db, uid, &search_body(&target, "note"))
+                .await
+                .result
+                .len()
+        );
+        assert!(SettingService::get_setting_by_key(db, &setting_key)
+            .await
+            .unwrap()
+            .is_none());
+        // 2. without move_to files, chats and blobs are removed
+        let content_path = blob_path(user_path, moved.hash.as_ref().unwrap());
+        assert!(content_path.exists());
+        assert!(delete_workspace(db, user_path, uid, &delete_body(&target))
+            .await
+            .is_success());
+        assert!(FileService::list_files_by_wid(db, &target)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(!content_path.exists());
+        assert!(search(db, uid, &search_body(&target, "note"))
+            .await
+            .result
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_workspace_members() {
+        let temp_dir = temp_dir();
+        let base_path = temp_dir.join(".fatherbox");
+        let file_path = &base_path.join("test-workspace-members.sqlite");
+        if exist_database_file(file_path) {
+            drop_database_file(&file_path).unwrap();
+        }
+        let db = &init_connection(&file_path).await.unwrap();
+        migrate(db).await.unwrap();
+        let register_body = |username: &str| RegisterBody {
+            username: username.to_string(),
+            password: "password".to_string(),
+            nickname: username.to_string(),
+        };
+        let owner = register(db, &register_body("owner")).await.result.unwrap();
+        let guest = register(db, &register_body("guest")).await.result.unwrap();
+        let wid = create_workspace(db, &owner.id, "shared")
+            .await
+            .result
+            .unwrap()
+            .id;
+        // 1. only owners invite, and only existing users
+        let invite_body = |username: &str, role: Role| MemberInviteBody {
+            wid: wid.clone(),
+            username: username.to_string(),
+            role,
+        };
+        assert!(
+            invite_member(db, &guest.id, &invite_body("guest", Role::Owner))
+                .await
+                .is_forbidden()
+        );
+        assert!(
+            !invite_member(db, &owner.id, &invite_body("nobody", Role::Viewer))
+                .await
+                .is_success()
+        );
+        assert!(
+            invite_member(db, &owner.id, &invite_body("guest", Role::Viewer))
+                .await
+                .is_success()
+        );
+        assert!(
+            !invite_member(db, &owner.id, &invite_body("guest", Role::Editor))
+                .await
+                .is_success()
+        );
+        // 2. shared workspace is listed and readable for the member
+        assert_eq!(1, list_workspaces(db, &guest.id).await.result.len());
+        assert!(get_workspace(db, &guest.id, &wid).await.is_success());
+        let list_body = &MemberListBody { wid: wid.clone() };
+        let members = list_members(db, &guest.id, list_body).await.result;
+        assert_eq!(1, members.len());
+        assert_eq!("viewer", members[0].role);
+        // 3. only owners change roles and delete the workspace
+        let role_body = |role: Role| MemberRoleBody {
+            wid: wid.clone(),
+            uid: guest.id.clone(),
+            role,
+        };
+        assert!(update_member_role(db, &guest.id, &role_body(Role::Owner))
+            .await
+            .is_forbidden());
+        assert!(update_member_role(db, &owner.id, &role_body(Role::Editor))
+            .await
+            .is_success());
+        let delete_body = &DeleteBody {
+            id: wid.clone(),
+            move_to: None,
+        };
+        let user_path = &base_path.join("test-workspace-members");
+        assert!(delete_workspace(db, user_path, &guest.id, delete_body)
+            .await
+            .is_forbidden());
+        // 4. members may leave
+        let member_body = &MemberBody {
+            wid: wid.clone(),
+            uid: guest.id.clone(),
+        };
+        assert_eq!(1, remove_member(db, &guest.id, member_body).await.result);
+        assert!(get_workspace(db, &guest.id, &wid).await.is_forbidden());
+        assert!(delete_workspace(db, user_path, &owner.id, delete_body)
+            .await
+            .is_success());
+    }
 }
@@ -1,61 +1,47 @@
-use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
 
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
-use log::{debug, error, info, trace};
-use sea_orm::DatabaseConnection;
+use log::{debug, error};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::{to_value, Value};
 use tauri::{State, Window};
-use bytes::Bytes;
-
-use app::{AppResponse, AppState, LoginInfo};
-
-
-use std::time::Duration;
-use std::error::Error;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::collections::HashMap;
-use std::fs;
-use async_openai::config::OpenAIConfig;
-use async_openai::types::{ChatCompletionRequestUserMessageArgs, ChatCompletionResponseStream, CreateChatCompletionRequestArgs};
-use async_openai::types::Role::User;
-use futures::StreamExt;
-use reqwest::Client;
-use reqwest::header::{HeaderName, HeaderMap};
-use serde::{Deserialize, Serialize};
-use app::service::ai_chat_service::Message;
-use app::service::user_service::LoginBody;
 
+use app::dto::ai_model::PullBody as AiModelPullBody;
+use app::dto::chat::ChunkPayload;
+use app::service::ai_chat_service::{
+    message_edit as chat_message_edit, message_regenerate as chat_message_regenerate,
+    message_request_stream as chat_message_request, EditBody as ModelMessageEditBody,
+    RegenerateBody as ModelMessageRegenerateBody, RequestBody as ChatRequestBody,
+};
+use app::service::ai_model_service::{pull as ai_model_pull, PullProgress};
+use app::service::workspace_archive_service::{
+    export_workspace, import_workspace, ExportBody as WorkspaceExportBody,
+    ImportBody as WorkspaceImportBody, ProgressPayload,
+};
+use app::{AppResponse, AppState};
+
+use crate::route::get_user_info_from_access_token;
+
+// numbers every stream so events of a reused request id can be told apart
 static REQUEST_COUNTER: AtomicU32 = AtomicU32::new(0);
 
-#[derive(Debug, Clone, serde::Serialize)]
-pub struct StreamResponse {
-    request_id: u32,
-    status: u16,
-    status_text: String,
-    headers: HashMap<String, String>
-}
-
-#[derive(Clone, serde::Serialize)]
-pub struct EndPayload {
-    request_id: u32,
-    status: u16,
+/// Event emitted on the request id of a stream command, a stream is a start,
+/// any number of chunks and then an end or an error.
+#[derive(Clone, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum StreamEvent {
+    Start { request_id: u32, command: String },
+    Chunk { request_id: u32, data: StreamChunk },
+    End { request_id: u32, result: Value },
+    Error { request_id: u32, message: String },
 }
 
-#[derive(Clone, serde::Serialize)]
-pub struct ChunkPayload {
-    request_id: u32,
-    chunk: String,
-}
-
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct RequestBody {
-    pub id: String,
-    pub prompt: String,
-    pub model_id: String,
-    pub source_id: String,
-    pub stream: bool,
+#[derive(Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum StreamChunk {
+    Chat(ChunkPayload),
+    Progress(ProgressPayload),
+    Pull(PullProgress),
 }
 
 #[tauri::command]
@@ -67,66 +53,148 @@ pub async fn stream_cmd(
     access_token: Option<String>,
     args: Value,
 ) -> Result<Value, ()> {
-
-
-    let body: RequestBody = serde_json::from_value(args).unwrap();
-
-    // todo:  use body
-
-    let event_name = &request_id;
-
-    let url = "http://localhost:11434/v1";
-    let key = "sn-123456";
-    let model = "llama3.1:8b";
-
-    let config = OpenAIConfig::new()
-        .with_api_base(url)
-        .with_api_key(key);
-    let client = async_openai::Client::with_config(config);
-    // todo convert message to chat message
-    let request_messages = vec![ChatCompletionRequestUserMessageArgs::default()
-        .content("Write a marketing blog praising and introducing Rust library async-openai")
-        .build().unwrap().into()];
-
-    let request = CreateChatCompletionRequestArgs::default()
-        .model(model.to_string())
-        .messages(request_messages)
-        .build()
-        .unwrap();
-    debug!("request {:?}", request);
-    match client.chat().create_stream(request).await {
-        Ok(mut stream) => {
-            while let Some(result) = stream.next().await {
-                match result {
-                    Ok(response) => {
-                        response.choices.iter().for_each(|chat_choice| {
-                            if let Some(ref content) = chat_choice.delta.content {
-                                info!("stream body: {:?}", content);
-                                window.emit(event_name, ChunkPayload{
-                                    request_id: 0,
-                                    chunk: content.clone()
-                                }).unwrap();
-                            }
-                        });
-                    }
-                    Err(err) => {
-                        error!("stream err: {:?}", err);
-                        window.emit(event_name, EndPayload{
-                            request_id: 0,
-                            status: 2,
-                        }).unwrap();
-                    }
-                }
-            }
-            window.emit(event_name, EndPayload{
-                request_id: 0,
-                status: 1,
-            }).unwrap();
-        }
-        Err(err) => {
-            error!( "{:?}",err)
+    let number = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+    let emit = |event: StreamEvent| {
+        if let Err(err) = window.emit(&request_id, event) {
+            error!("emit stream event failed, err: {}", err);
         }
+    };
+    let chunk = |data: StreamChunk| {
+        emit(StreamEvent::Chunk {
+            request_id: number,
+            data,
+        })
+    };
+    let db = &state.conn;
+    let user_path = &state.user_path;
+    let retry_settings = &state.retry_settings;
+    let login_info =
+        match get_user_info_from_access_token(db, &state.token_secret, access_token).await {
+            Ok(login_info) => login_info,
+            Err(response) => return Ok(finish(&emit, number, response)),
+        };
+    if login_info.password_expired {
+        let response = AppResponse::error(
+            None::<String>,
+            "Password is expired, please change password first",
+        );
+        return Ok(finish(&emit, number, response));
     }
+    let user_id = &login_info.user_id;
+    emit(StreamEvent::Start {
+        request_id: number,
+        command: command.clone(),
+    });
+    debug!("stream command: {}, number: {}", command, number);
+    let chat_callback = |payload: ChunkPayload| chunk(StreamChunk::Chat(payload));
+    let progress_callback =
+        |done: u64, total: u64| chunk(StreamChunk::Progress(ProgressPayload { done, total }));
+    let response = match command.as_str() {
+        // chat streams are cancelled with chat_message_cancel on the stream's request id
+        "chat_message_request" => match parse_args::<ChatRequestBody>(args) {
+            Ok(mut body) => {
+                body.request_id = request_id.clone();
+                let response = chat_message_request(
+                    chat_callback,
+                    db,
+                    user_path,
+                    user_id,
+                    retry_settings,
+                    &body,
+                )
+                .await;
+                finish(&emit, number, response)
+            }
+            Err(response) => finish(&emit, number, response),
+        },
+        "chat_message_regenerate" => match parse_args::<ModelMessageRegenerateBody>(args) {
+            Ok(mut body) => {
+                body.request_id = request_id.clone();
+                let response = chat_message_regenerate(
+                    chat_callback,
+                    db,
+                    user_path,
+                    user_id,
+                    retry_settings,
+                    &body,
+                )
+                .await;
+                finish(&emit, number, response)
+            }
+            Err(response) => finish(&emit, number, response),
+        },
+        "chat_message_edit" => match parse_args::<ModelMessageEditBody>(args) {
+            Ok(mut body) => {
+                body.request_id = request_id.clone();
+                let response = chat_message_edit(
+                    chat_callback,
+                    db,
+                    user_path,
+                    user_id,
+                    retry_settings,
+                    &body,
+                )
+                .await;
+                finish(&emit, number, response)
+            }
+            Err(response) => finish(&emit, number, response),
+        },
+        "workspace_export" => match parse_args::<WorkspaceExportBody>(args) {
+            Ok(body) => {
+                let response =
+                    export_workspace(progress_callback, db, user_path, user_id, &body).await;
+                finish(&emit, number, response)
+            }
+            Err(response) => finish(&emit, number, response),
+        },
+        "workspace_import" => match parse_args::<WorkspaceImportBody>(args) {
+            Ok(body) => {
+                let response =
+                    import_workspace(progress_callback, db, user_path, user_id, &body).await;
+                finish(&emit, number, response)
+            }
+            Err(response) => finish(&emit, number, response),
+        },
+        "ai_model_pull" => match parse_args::<AiModelPullBody>(args) {
+            Ok(body) => {
+                let callback = |progress: PullProgress| chunk(StreamChunk::Pull(progress));
+                let response = ai_model_pull(callback, db, &body).await;
+                finish(&emit, number, response)
+            }
+            Err(response) => finish(&emit, number, response),
+        },
+        _ => {
+            let response = AppResponse::error(
+                None::<String>,
+                &format!("Stream command {:?} not found", command),
+            );
+            finish(&emit, number, response)
+        }
+    };
+    Ok(response)
+}
 
-    Ok(Value::Null)
+fn parse_args<T: DeserializeOwned>(args: Value) -> Result<T, AppResponse<Option<String>>> {
+    serde_json::from_value(args)
+        .map_err(|err| AppResponse::error(None, &format!("Invalid arguments, err: {}", err)))
+}
+
+/// Emit the end or the error event of the stream and return the response of the command.
+fn finish<E, T>(emit: &E, number: u32, response: AppResponse<T>) -> Value
+where
+    E: Fn(StreamEvent),
+    T: Serialize,
+{
+    if response.is_success() {
+        emit(StreamEvent::End {
+            request_id: number,
+            result: to_value(&response.result).unwrap_or_default(),
+        });
+    } else {
+        emit(StreamEvent::Error {
+            request_id: number,
+            message: response.message.clone(),
+        });
+    }
+    to_value(&response).unwrap()
 }
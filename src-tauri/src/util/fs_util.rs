@@ -0,0 +1,113 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use log::error;
+use uuid::Uuid;
+
+// temp files live next to the target so the final rename never crosses a file system
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            fs::create_dir_all(parent)?;
+            Ok(parent.join(format!(
+                ".{}.{}.tmp",
+                name.to_string_lossy(),
+                Uuid::new_v4()
+            )))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid file path {}", path.display()),
+        )),
+    }
+}
+
+fn commit_temp(temp: &Path, path: &Path, result: io::Result<u64>) -> io::Result<u64> {
+    let result = result.and_then(|size| fs::rename(temp, path).map(|_| size));
+    if result.is_err() {
+        let _ = fs::remove_file(temp);
+    }
+    result
+}
+
+/// Write content to a temp file then rename it over path, return size of the new file.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<u64> {
+    let temp = temp_path(path)?;
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        Ok(file.metadata()?.len())
+    })();
+    commit_temp(&temp, path, result)
+}
+
+/// Copy from to a temp file then rename it over path, return size of the new file.
+pub fn copy_atomic(from: &Path, path: &Path) -> io::Result<u64> {
+    let temp = temp_path(path)?;
+    let result = (|| {
+        let size = fs::copy(from, &temp)?;
+        File::open(&temp)?.sync_all()?;
+        Ok(size)
+    })();
+    commit_temp(&temp, path, result)
+}
+
+/// Let write fill a temp file then rename it over path, return size of the new file.
+pub fn write_atomic_with<F>(path: &Path, write: F) -> io::Result<u64>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    let temp = temp_path(path)?;
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        Ok(file.metadata()?.len())
+    })();
+    commit_temp(&temp, path, result)
+}
+
+/// Best effort cleanup of a file or dir written by a failed operation.
+pub fn remove_path(path: &Path) {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    if let Err(err) = result {
+        if err.kind() != io::ErrorKind::NotFound {
+            error!("remove {} failed, err: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+
+    use crate::util::fs_util::{copy_atomic, write_atomic};
+
+    #[test]
+    fn test_write_atomic() {
+        let base_path = temp_dir().join(".fatherbox").join("test-fs-util");
+        if base_path.exists() {
+            fs::remove_dir_all(&base_path).unwrap();
+        }
+        fs::create_dir_all(&base_path).unwrap();
+        let path = base_path.join("file");
+        assert_eq!(5, write_atomic(&path, b"hello").unwrap());
+        assert_eq!(3, write_atomic(&path, b"abc").unwrap());
+        assert_eq!(b"abc".to_vec(), fs::read(&path).unwrap());
+        let copy_path = base_path.join("copy");
+        assert_eq!(3, copy_atomic(&path, &copy_path).unwrap());
+        // failed write keeps old content and leaves no temp file
+        assert!(copy_atomic(&base_path.join("missing"), &path).is_err());
+        assert_eq!(b"abc".to_vec(), fs::read(&path).unwrap());
+        assert_eq!(2, fs::read_dir(&base_path).unwrap().count());
+    }
+}
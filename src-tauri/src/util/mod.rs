@@ -1 +1,4 @@
 pub mod db_util;
+pub mod fs_util;
+pub mod password_util;
+pub mod token_util;
@@ -0,0 +1,57 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use subtle::ConstantTimeEq;
+
+// every hash produced by this module is an argon2 PHC string, e.g. $argon2id$v=19$...
+const HASH_PREFIX: &str = "$argon2";
+
+pub const PASSWORD_MIN_LEN: usize = 6;
+
+/// Hash password with argon2id and a random per-user salt.
+pub fn hash_password(password: &str) -> Result<String, anyhow::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(err) => Err(anyhow::anyhow!("hash password failed, err: {}", err)),
+    }
+}
+
+/// Plaintext rows written before passwords were hashed.
+pub fn is_legacy_password(stored: &str) -> bool {
+    !stored.starts_with(HASH_PREFIX)
+}
+
+/// Verify password against stored hash, legacy plaintext is compared in constant time.
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    if is_legacy_password(stored) {
+        return password.as_bytes().ct_eq(stored.as_bytes()).into();
+    }
+    match PasswordHash::new(stored) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::password_util::{hash_password, is_legacy_password, verify_password};
+
+    #[test]
+    fn test_password() {
+        let password = "123456";
+        let hash = hash_password(password).unwrap();
+        assert_ne!(password, hash);
+        assert!(!is_legacy_password(&hash));
+        assert!(verify_password(password, &hash));
+        assert!(!verify_password("654321", &hash));
+        // same password, different salt
+        assert_ne!(hash, hash_password(password).unwrap());
+        // legacy plaintext
+        assert!(is_legacy_password(password));
+        assert!(verify_password(password, password));
+        assert!(!verify_password("654321", password));
+    }
+}
@@ -0,0 +1,247 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::TOKEN_SECRET_FILE_NAME;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// header of every token, tokens are HS256 JWTs
+const TOKEN_HEADER: &str = r#"{"alg":"HS256","typ":"JWT"}"#;
+const TOKEN_SECRET_LEN: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq)]
+pub struct Claims {
+    // user id
+    pub sub: String,
+    // session id
+    #[serde(default)]
+    pub sid: String,
+    // issued at, unix seconds
+    pub iat: i64,
+    // expire at, unix seconds
+    pub exp: i64,
+    // only password change is allowed with this token
+    #[serde(default)]
+    pub password_expired: bool,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TokenError {
+    #[error("User token is malformed")]
+    Malformed,
+
+    #[error("User token signature is invalid")]
+    InvalidSignature,
+
+    #[error("User token is expired")]
+    Expired,
+}
+
+/// Load the per-install token secret from the config dir, create it on first run.
+pub fn load_or_create_secret(config_path: &Path) -> io::Result<Vec<u8>> {
+    let secret_path = config_path.join(TOKEN_SECRET_FILE_NAME);
+    if secret_path.exists() {
+        let content = fs::read_to_string(&secret_path)?;
+        if let Ok(secret) = BASE64_URL_SAFE_NO_PAD.decode(content.trim()) {
+            if secret.len() == TOKEN_SECRET_LEN {
+                return Ok(secret);
+            }
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("token secret {} is corrupted", secret_path.display()),
+        ));
+    }
+    let mut secret = vec![0u8; TOKEN_SECRET_LEN];
+    rand::thread_rng().fill_bytes(&mut secret);
+    fs::write(&secret_path, BASE64_URL_SAFE_NO_PAD.encode(&secret))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&secret_path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(secret)
+}
+
+pub fn generate_token(
+    secret: &[u8],
+    user_id: &str,
+    session_id: &str,
+    password_expired: bool,
+    expire_seconds: i64,
+) -> String {
+    let now = Utc::now().timestamp();
+    sign(
+        secret,
+        &Claims {
+            sub: user_id.to_string(),
+            sid: session_id.to_string(),
+            iat: now,
+            exp: now + expire_seconds,
+            password_expired,
+        },
+    )
+}
+
+/// Refresh token is `<session id>.<random>`, only its hash is stored.
+pub fn generate_refresh_token(session_id: &str) -> String {
+    let mut random = vec![0u8; TOKEN_SECRET_LEN];
+    rand::thread_rng().fill_bytes(&mut random);
+    format!("{}.{}", session_id, BASE64_URL_SAFE_NO_PAD.encode(random))
+}
+
+pub fn parse_refresh_token(refresh_token: &str) -> Option<&str> {
+    match refresh_token.split_once('.') {
+        Some((session_id, random)) if !session_id.is_empty() && !random.is_empty() => {
+            Some(session_id)
+        }
+        _ => None,
+    }
+}
+
+pub fn hash_refresh_token(refresh_token: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(refresh_token.as_bytes()))
+}
+
+pub fn sign(secret: &[u8], claims: &Claims) -> String {
+    let header = BASE64_URL_SAFE_NO_PAD.encode(TOKEN_HEADER);
+    let payload = BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap());
+    let signing_input = format!("{}.{}", header, payload);
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+    mac.update(signing_input.as_bytes());
+    let signature = BASE64_URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    format!("{}.{}", signing_input, signature)
+}
+
+pub fn verify_token(secret: &[u8], token: &str) -> Result<Claims, TokenError> {
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != 3 {
+        return Err(TokenError::Malformed);
+    }
+    let header = BASE64_URL_SAFE_NO_PAD
+        .decode(parts[0])
+        .map_err(|_| TokenError::Malformed)?;
+    if header != TOKEN_HEADER.as_bytes() {
+        return Err(TokenError::Malformed);
+    }
+    let signature = BASE64_URL_SAFE_NO_PAD
+        .decode(parts[2])
+        .map_err(|_| TokenError::Malformed)?;
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+    mac.update(parts[0].as_bytes());
+    mac.update(b".");
+    mac.update(parts[1].as_bytes());
+    // constant time compare
+    mac.verify_slice(&signature)
+        .map_err(|_| TokenError::InvalidSignature)?;
+    let payload = BASE64_URL_SAFE_NO_PAD
+        .decode(parts[1])
+        .map_err(|_| TokenError::Malformed)?;
+    let claims: Claims = serde_json::from_slice(&payload).map_err(|_| TokenError::Malformed)?;
+    if claims.exp <= Utc::now().timestamp() {
+        return Err(TokenError::Expired);
+    }
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+
+    use chrono::Utc;
+
+    use crate::util::token_util::{
+        generate_refresh_token, generate_token, hash_refresh_token, load_or_create_secret,
+        parse_refresh_token, sign, verify_token, Claims, TokenError,
+    };
+
+    #[test]
+    fn test_token() {
+        let secret = b"0123456789abcdef0123456789abcdef";
+        let user_id = "user-1";
+        // 1. valid token
+        let token = generate_token(secret, user_id, "session-1", false, 60);
+        let claims = verify_token(secret, &token).unwrap();
+        assert_eq!(user_id, claims.sub);
+        assert_eq!("session-1", claims.sid);
+        // 2. other secret
+        let other_secret = b"fedcba9876543210fedcba9876543210";
+        assert_eq!(
+            Err(TokenError::InvalidSignature),
+            verify_token(other_secret, &token)
+        );
+        // 3. forged payload
+        let forged_claims = Claims {
+            sub: "user-2".to_string(),
+            sid: claims.sid.clone(),
+            iat: claims.iat,
+            exp: claims.exp,
+            password_expired: false,
+        };
+        let forged_token = sign(other_secret, &forged_claims);
+        let parts: Vec<&str> = token.split('.').collect();
+        let forged_parts: Vec<&str> = forged_token.split('.').collect();
+        let tampered = format!("{}.{}.{}", parts[0], forged_parts[1], parts[2]);
+        assert_eq!(
+            Err(TokenError::InvalidSignature),
+            verify_token(secret, &tampered)
+        );
+        // 4. expired token
+        let now = Utc::now().timestamp();
+        let expired_token = sign(
+            secret,
+            &Claims {
+                sub: user_id.to_string(),
+                sid: "session-1".to_string(),
+                iat: now - 120,
+                exp: now - 60,
+                password_expired: false,
+            },
+        );
+        assert_eq!(
+            Err(TokenError::Expired),
+            verify_token(secret, &expired_token)
+        );
+        // 5. legacy base64 user id token
+        assert_eq!(Err(TokenError::Malformed), verify_token(secret, "dXNlci0x"));
+    }
+
+    #[test]
+    fn test_refresh_token() {
+        let refresh_token = generate_refresh_token("session-1");
+        assert_eq!(Some("session-1"), parse_refresh_token(&refresh_token));
+        assert_eq!(None, parse_refresh_token("session-1"));
+        assert_eq!(None, parse_refresh_token(".abc"));
+        // rotated token must differ
+        let other_refresh_token = generate_refresh_token("session-1");
+        assert_ne!(
+            hash_refresh_token(&refresh_token),
+            hash_refresh_token(&other_refresh_token)
+        );
+    }
+
+    #[test]
+    fn test_secret() {
+        let config_path = temp_dir().join(".fatherbox").join("test-token-secret");
+        if config_path.exists() {
+            fs::remove_dir_all(&config_path).unwrap();
+        }
+        fs::create_dir_all(&config_path).unwrap();
+        let secret = load_or_create_secret(&config_path).unwrap();
+        assert_eq!(32, secret.len());
+        // load again, must be same secret
+        let loaded_secret = load_or_create_secret(&config_path).unwrap();
+        assert_eq!(secret, loaded_secret);
+    }
+}